@@ -0,0 +1,80 @@
+use crate::api::types::Trytes81;
+use chronicle_cql::{
+    compression::MyCompression,
+    frame::{
+        consistency::Consistency,
+        decoder::{
+            ColumnDecoder,
+            Decoder,
+            Frame,
+        },
+        header::Header,
+        query::Query,
+        queryflags::{
+            SKIP_METADATA,
+            VALUES,
+        },
+    },
+    rows,
+};
+
+// ----------- decoding scope -----------
+
+rows!(
+    rows: Confirmation {bundle: Option<Trytes81>, milestone: Option<i64>},
+    row: Row(
+        Bundle,
+        Milestone
+    ),
+    column_decoder: ConfirmationDecoder
+);
+
+pub trait Rows {
+    fn decode(self) -> Self;
+    fn finalize(self) -> (Option<Trytes81>, Option<i64>);
+}
+
+impl Rows for Confirmation {
+    fn decode(mut self) -> Self {
+        self.next();
+        self
+    }
+    fn finalize(self) -> (Option<Trytes81>, Option<i64>) {
+        (self.bundle, self.milestone)
+    }
+}
+// implementation to decode the columns in order to form the (bundle, milestone) of the transaction eventually
+impl ConfirmationDecoder for Bundle {
+    fn decode_column(start: usize, length: i32, acc: &mut Confirmation) {
+        acc.bundle = Some(Trytes81::decode(&acc.buffer()[start..], length as usize));
+    }
+    fn handle_null(_: &mut Confirmation) {
+        unreachable!()
+    }
+}
+impl ConfirmationDecoder for Milestone {
+    fn decode_column(start: usize, length: i32, acc: &mut Confirmation) {
+        acc.milestone = Some(i64::decode(&acc.buffer()[start..], length as usize));
+    }
+    // the transaction is not confirmed yet
+    fn handle_null(_: &mut Confirmation) {}
+}
+
+// ----------- encoding scope -----------
+
+/// Create a query frame to lookup for the bundle and the milestone (confirmation) of a transaction
+pub fn query(hash: &Trytes81) -> Vec<u8> {
+    let Query(payload) = Query::new()
+        .version()
+        .flags(MyCompression::flag())
+        .stream(0)
+        .opcode()
+        .length()
+        .statement("SELECT bundle, milestone FROM tangle.transaction WHERE hash = ?")
+        .consistency(Consistency::One)
+        .query_flags(SKIP_METADATA | VALUES)
+        .value_count(1)
+        .value(hash)
+        .build(MyCompression::get());
+    payload
+}
@@ -0,0 +1,83 @@
+use crate::api::types::Trytes81;
+use chronicle_cql::{
+    compression::MyCompression,
+    frame::{
+        consistency::Consistency,
+        decoder::{
+            ColumnDecoder,
+            Decoder,
+            Frame,
+        },
+        header::Header,
+        query::Query,
+        queryflags::{
+            SKIP_METADATA,
+            VALUES,
+        },
+    },
+    rows,
+};
+
+// ----------- decoding scope -----------
+
+rows!(
+    rows: Inputs {inputs: Vec<(Trytes81, Option<Trytes81>)>},
+    row: Row(
+        Hash,
+        Extra
+    ),
+    column_decoder: InputsDecoder
+);
+
+pub trait Rows {
+    fn decode(self) -> Self;
+    fn finalize(self) -> Vec<(Trytes81, Option<Trytes81>)>;
+}
+
+impl Rows for Inputs {
+    fn decode(mut self) -> Self {
+        while let Some(_) = self.next() {}
+        self
+    }
+    fn finalize(self) -> Vec<(Trytes81, Option<Trytes81>)> {
+        self.inputs
+    }
+}
+// implementation to decode the columns in order to form the (input, bundle) pairs eventually
+impl InputsDecoder for Hash {
+    fn decode_column(start: usize, length: i32, acc: &mut Inputs) {
+        let hash = Trytes81::decode(&acc.buffer()[start..], length as usize);
+        acc.inputs.push((hash, None));
+    }
+    fn handle_null(_: &mut Inputs) {
+        unreachable!()
+    }
+}
+impl InputsDecoder for Extra {
+    fn decode_column(start: usize, length: i32, acc: &mut Inputs) {
+        // the bundle of the input
+        let bundle = Trytes81::decode(&acc.buffer()[start..], length as usize);
+        acc.inputs.last_mut().unwrap().1.replace(bundle);
+    }
+    // the inputs stored before the conflicts index don't have their bundle in the extra column
+    fn handle_null(_: &mut Inputs) {}
+}
+
+// ----------- encoding scope -----------
+
+/// Create a query frame to lookup for the input tx-hashes (and their bundles) in the edge table using an address
+pub fn query(address: &Trytes81) -> Vec<u8> {
+    let Query(payload) = Query::new()
+        .version()
+        .flags(MyCompression::flag())
+        .stream(0)
+        .opcode()
+        .length()
+        .statement("SELECT tx, extra FROM tangle.edge WHERE vertex = ? AND kind = 'input'")
+        .consistency(Consistency::One)
+        .query_flags(SKIP_METADATA | VALUES)
+        .value_count(1)
+        .value(address)
+        .build(MyCompression::get());
+    payload
+}
@@ -0,0 +1,297 @@
+// The conflicts are read from the conflicts index of the edge table, the reattachments of a bundle are its tail edges,
+// and the spends of an address are the bundles of its input edges.
+mod confirmation;
+mod inputs;
+mod tails;
+
+use crate::api::{
+    getconflicts::{
+        confirmation::Rows as ConfirmationRows,
+        inputs::Rows as InputsRows,
+        tails::Rows as TailsRows,
+    },
+    types::Trytes81,
+};
+use chronicle_common::actor;
+use chronicle_cql::{
+    compression::MyCompression,
+    frame::decoder::{
+        Decoder,
+        Frame,
+    },
+};
+use chronicle_storage::{
+    ring::Ring,
+    stage::reporter,
+    worker::{
+        Error,
+        Worker,
+    },
+};
+use hyper::{
+    Body,
+    Response,
+};
+use log::*;
+use serde::Serialize;
+use tokio::sync::mpsc;
+type Sender = mpsc::UnboundedSender<Event>;
+type Receiver = mpsc::UnboundedReceiver<Event>;
+#[derive(Debug)]
+pub struct GetConflictsId(Sender);
+
+actor!(GetConflictsBuilder {
+    addresses: Option<Vec<Trytes81>>,
+    bundles: Option<Vec<Trytes81>>
+});
+
+impl GetConflictsBuilder {
+    pub fn build(self) -> GetConflicts {
+        GetConflicts {
+            addresses: self.addresses.unwrap(),
+            bundles: self.bundles.unwrap(),
+        }
+    }
+}
+
+pub struct GetConflicts {
+    addresses: Option<Vec<Trytes81>>,
+    bundles: Option<Vec<Trytes81>>,
+}
+
+/// A transaction with its confirmation state, the milestone is null if it's not confirmed.
+#[derive(Serialize)]
+struct Attachment {
+    hash: Trytes81,
+    milestone: Option<i64>,
+}
+
+/// The bundle which spends from an address, with its input transactions.
+#[derive(Serialize)]
+struct Spend {
+    bundle: Trytes81,
+    confirmed: bool,
+    inputs: Vec<Attachment>,
+}
+
+/// An address with inputs in more than one distinct bundle (double-spend).
+#[derive(Serialize)]
+struct AddressConflicts {
+    address: Trytes81,
+    spends: Vec<Spend>,
+}
+
+/// A bundle attached more than once (reattachments), with its distinct tails.
+#[derive(Serialize)]
+struct BundleConflicts {
+    bundle: Trytes81,
+    confirmed: bool,
+    tails: Vec<Attachment>,
+}
+
+#[derive(Serialize, Default)]
+struct ResConflicts {
+    addresses: Option<Vec<AddressConflicts>>,
+    bundles: Option<Vec<BundleConflicts>>,
+}
+
+impl GetConflicts {
+    pub async fn run(mut self) -> Response<Body> {
+        let (tx, mut rx) = mpsc::unbounded_channel::<Event>();
+        let worker = Box::new(GetConflictsId(tx));
+        let mut res_conflicts = ResConflicts::default();
+        match self.process_addresses(&mut res_conflicts, worker, &mut rx).await {
+            Ok(worker) => match self.process_bundles(&mut res_conflicts, worker, &mut rx).await {
+                Ok(_) => response!(body: serde_json::to_string(&res_conflicts).unwrap()),
+                Err(response) => response,
+            },
+            Err(response) => response,
+        }
+    }
+
+    async fn process_addresses(
+        &mut self,
+        res_conflicts: &mut ResConflicts,
+        mut worker: Box<GetConflictsId>,
+        rx: &mut Receiver,
+    ) -> Result<Box<GetConflictsId>, Response<Body>> {
+        if let Some(addresses) = self.addresses.take() {
+            let mut conflicts = Vec::new();
+            for address in addresses {
+                let (pid, decoder) = Self::request(inputs::query(&address), worker, rx).await;
+                worker = pid;
+                let inputs = match decoder {
+                    Some(decoder) => inputs::Inputs::new(decoder, Vec::new()).decode().finalize(),
+                    None => {
+                        return Err(
+                            response!(status: INTERNAL_SERVER_ERROR, body: r#"{"error":"internal error while processing an address"}"#),
+                        );
+                    }
+                };
+                // an address spent by a single bundle has no conflicts, unless some inputs are not indexed by bundle
+                let first_bundle = inputs.first().and_then(|(_, bundle)| *bundle);
+                if inputs.iter().all(|(_, bundle)| match (bundle, &first_bundle) {
+                    (Some(bundle), Some(first_bundle)) => bundle.0[..] == first_bundle.0[..],
+                    _ => false,
+                }) {
+                    continue;
+                }
+                let mut spends: Vec<Spend> = Vec::new();
+                for (hash, bundle) in inputs {
+                    let (pid, decoder) = Self::request(confirmation::query(&hash), worker, rx).await;
+                    worker = pid;
+                    let (stored_bundle, milestone) = match decoder {
+                        Some(decoder) => confirmation::Confirmation::new(decoder, None, None).decode().finalize(),
+                        None => {
+                            return Err(
+                                response!(status: INTERNAL_SERVER_ERROR, body: r#"{"error":"internal error while processing an input"}"#),
+                            );
+                        }
+                    };
+                    // skip the inputs whose bundle is unknown
+                    if let Some(bundle) = bundle.or(stored_bundle) {
+                        add_input(&mut spends, bundle, Attachment { hash, milestone });
+                    }
+                }
+                if spends.len() > 1 {
+                    conflicts.push(AddressConflicts { address, spends });
+                }
+            }
+            res_conflicts.addresses.replace(conflicts);
+        }
+        Ok(worker)
+    }
+
+    async fn process_bundles(
+        &mut self,
+        res_conflicts: &mut ResConflicts,
+        mut worker: Box<GetConflictsId>,
+        rx: &mut Receiver,
+    ) -> Result<Box<GetConflictsId>, Response<Body>> {
+        if let Some(bundles) = self.bundles.take() {
+            let mut conflicts = Vec::new();
+            for bundle in bundles {
+                let (pid, decoder) = Self::request(tails::query(&bundle), worker, rx).await;
+                worker = pid;
+                let hashes = match decoder {
+                    Some(decoder) => tails::Tails::new(decoder, Vec::new()).decode().finalize(),
+                    None => {
+                        return Err(
+                            response!(status: INTERNAL_SERVER_ERROR, body: r#"{"error":"internal error while processing a bundle"}"#),
+                        );
+                    }
+                };
+                // a bundle attached once has no conflicts
+                if hashes.len() < 2 {
+                    continue;
+                }
+                let mut tails = Vec::with_capacity(hashes.len());
+                for hash in hashes {
+                    let (pid, decoder) = Self::request(confirmation::query(&hash), worker, rx).await;
+                    worker = pid;
+                    let milestone = match decoder {
+                        Some(decoder) => {
+                            confirmation::Confirmation::new(decoder, None, None)
+                                .decode()
+                                .finalize()
+                                .1
+                        }
+                        None => {
+                            return Err(
+                                response!(status: INTERNAL_SERVER_ERROR, body: r#"{"error":"internal error while processing a tail"}"#),
+                            );
+                        }
+                    };
+                    tails.push(Attachment { hash, milestone });
+                }
+                conflicts.push(BundleConflicts {
+                    bundle,
+                    confirmed: tails.iter().any(|tail| tail.milestone.is_some()),
+                    tails,
+                });
+            }
+            res_conflicts.bundles.replace(conflicts);
+        }
+        Ok(worker)
+    }
+
+    // send the query and return the ownership of the worker, with the rows decoder if the query succeeded.
+    async fn request(
+        payload: Vec<u8>,
+        worker: Box<GetConflictsId>,
+        rx: &mut Receiver,
+    ) -> (Box<GetConflictsId>, Option<Decoder>) {
+        let request = reporter::Event::Request { payload, worker };
+        // send request using ring, todo use shard-awareness algo
+        Ring::send_local_random_replica(0, request);
+        match rx.recv().await.unwrap() {
+            Event::Response { giveload, pid } => {
+                let decoder = Decoder::new(giveload, MyCompression::get());
+                if decoder.is_rows() {
+                    (pid, Some(decoder))
+                } else {
+                    error!("GetConflicts: {:?}", decoder.get_error());
+                    (pid, None)
+                }
+            }
+            Event::Error { kind, pid } => {
+                error!("GetConflicts: {:?}", kind);
+                (pid, None)
+            }
+        }
+    }
+}
+
+// group the input by its bundle, a bundle is confirmed once any of its inputs is confirmed.
+fn add_input(spends: &mut Vec<Spend>, bundle: Trytes81, input: Attachment) {
+    let confirmed = input.milestone.is_some();
+    match spends.iter_mut().find(|spend| spend.bundle.0[..] == bundle.0[..]) {
+        Some(spend) => {
+            spend.confirmed |= confirmed;
+            spend.inputs.push(input);
+        }
+        None => spends.push(Spend {
+            bundle,
+            confirmed,
+            inputs: vec![input],
+        }),
+    }
+}
+
+pub enum Event {
+    Response {
+        giveload: Vec<u8>,
+        pid: Box<GetConflictsId>,
+    },
+    Error {
+        kind: Error,
+        pid: Box<GetConflictsId>,
+    },
+}
+
+// implementation!
+impl Worker for GetConflictsId {
+    fn send_response(self: Box<Self>, _: &Option<reporter::Sender>, giveload: Vec<u8>) {
+        // to enable reusable self(Sender), we will do unsafe trick
+        unsafe {
+            // convert box into raw
+            let raw = Box::into_raw(self);
+            // convert back to box from raw
+            let pid = Box::from_raw(raw);
+            let event = Event::Response { giveload, pid };
+            // now we can use raw to send self through itself.
+            let _ = (*raw).0.send(event);
+        }
+    }
+    fn send_error(self: Box<Self>, kind: Error) {
+        unsafe {
+            // convert box into raw
+            let raw = Box::into_raw(self);
+            // convert back to box from raw
+            let pid = Box::from_raw(raw);
+            let event = Event::Error { kind, pid };
+            // now we can use raw to send itself through itself.
+            let _ = (*raw).0.send(event);
+        }
+    }
+}
@@ -18,6 +18,7 @@ use chronicle_cql::{
 use chronicle_storage::{
     ring::Ring,
     stage::reporter,
+    transaction,
     worker::{
         Error,
         Worker,
@@ -195,7 +196,7 @@ impl Rows for Trytes {
         // check if result was not empty
         if self.rows_count == 1 {
             // the buffer is ready to be converted to string trytes
-            self.buffer().truncate(2673);
+            self.buffer().truncate(transaction::TRANSACTION_TRYTES_LENGTH);
             Some(String::from_utf8(self.decoder.into_buffer()).unwrap())
         } else {
             // we didn't have any transaction row for the provided hash.
@@ -206,9 +207,10 @@ impl Rows for Trytes {
 // implementation to decoder the columns in order to form the trytes eventually
 impl TrytesDecoder for Payload {
     fn decode_column(start: usize, length: i32, acc: &mut Trytes) {
-        // Payload trytes offest is 0..2187, note: assuming length != -1(indicate empty column).
-        // copy_within so a buffer[0..2187] will = buffer[start..length]
-        acc.buffer().copy_within(start..(start + length as usize), 0)
+        // note: assuming length != -1(indicate empty column).
+        // copy_within so a buffer[PAYLOAD] will = buffer[start..length]
+        acc.buffer()
+            .copy_within(start..(start + length as usize), transaction::PAYLOAD.start)
     }
     fn handle_null(_: &mut Trytes) {
         unreachable!()
@@ -216,8 +218,9 @@ impl TrytesDecoder for Payload {
 }
 impl TrytesDecoder for Address {
     fn decode_column(start: usize, length: i32, acc: &mut Trytes) {
-        // Address trytes offest is 2187..2268, note: we assume the length value is also correct
-        acc.buffer().copy_within(start..(start + length as usize), 2187)
+        // note: we assume the length value is also correct
+        acc.buffer()
+            .copy_within(start..(start + length as usize), transaction::ADDRESS.start)
     }
     fn handle_null(_: &mut Trytes) {
         unreachable!()
@@ -225,8 +228,8 @@ impl TrytesDecoder for Address {
 }
 impl TrytesDecoder for Value {
     fn decode_column(start: usize, length: i32, acc: &mut Trytes) {
-        // Value tryte offset is 2268..2295
-        acc.buffer().copy_within(start..(start + length as usize), 2268);
+        acc.buffer()
+            .copy_within(start..(start + length as usize), transaction::VALUE.start);
     }
     fn handle_null(_: &mut Trytes) {
         unreachable!()
@@ -234,8 +237,8 @@ impl TrytesDecoder for Value {
 }
 impl TrytesDecoder for ObsoleteTag {
     fn decode_column(start: usize, length: i32, acc: &mut Trytes) {
-        // ObsoleteTag tryte offset is 2295..2322
-        acc.buffer().copy_within(start..(start + length as usize), 2295)
+        acc.buffer()
+            .copy_within(start..(start + length as usize), transaction::OBSOLETE_TAG.start)
     }
     fn handle_null(_: &mut Trytes) {
         unreachable!()
@@ -243,8 +246,8 @@ impl TrytesDecoder for ObsoleteTag {
 }
 impl TrytesDecoder for Timestamp {
     fn decode_column(start: usize, length: i32, acc: &mut Trytes) {
-        // Timestamp tryte offset is 2322..2331
-        acc.buffer().copy_within(start..(start + length as usize), 2322);
+        acc.buffer()
+            .copy_within(start..(start + length as usize), transaction::TIMESTAMP.start);
     }
     fn handle_null(_: &mut Trytes) {
         unreachable!()
@@ -252,8 +255,8 @@ impl TrytesDecoder for Timestamp {
 }
 impl TrytesDecoder for CurrentIndex {
     fn decode_column(start: usize, length: i32, acc: &mut Trytes) {
-        // CurrentIndex tryte offset is 2331..2340
-        acc.buffer().copy_within(start..(start + length as usize), 2331);
+        acc.buffer()
+            .copy_within(start..(start + length as usize), transaction::CURRENT_INDEX.start);
     }
     fn handle_null(_: &mut Trytes) {
         unreachable!()
@@ -261,8 +264,8 @@ impl TrytesDecoder for CurrentIndex {
 }
 impl TrytesDecoder for LastIndex {
     fn decode_column(start: usize, length: i32, acc: &mut Trytes) {
-        // LastIndex tryte offset is 2340..2349
-        acc.buffer().copy_within(start..(start + length as usize), 2340);
+        acc.buffer()
+            .copy_within(start..(start + length as usize), transaction::LAST_INDEX.start);
     }
     fn handle_null(_: &mut Trytes) {
         unreachable!()
@@ -270,8 +273,8 @@ impl TrytesDecoder for LastIndex {
 }
 impl TrytesDecoder for Bundle {
     fn decode_column(start: usize, length: i32, acc: &mut Trytes) {
-        // Bundle tryte offset is 2349..2430
-        acc.buffer().copy_within(start..(start + length as usize), 2349)
+        acc.buffer()
+            .copy_within(start..(start + length as usize), transaction::BUNDLE.start)
     }
     fn handle_null(_: &mut Trytes) {
         unreachable!()
@@ -279,8 +282,8 @@ impl TrytesDecoder for Bundle {
 }
 impl TrytesDecoder for Trunk {
     fn decode_column(start: usize, length: i32, acc: &mut Trytes) {
-        // Trunk tryte offset is 2430..2511
-        acc.buffer().copy_within(start..(start + length as usize), 2430)
+        acc.buffer()
+            .copy_within(start..(start + length as usize), transaction::TRUNK.start)
     }
     fn handle_null(_: &mut Trytes) {
         unreachable!()
@@ -288,8 +291,8 @@ impl TrytesDecoder for Trunk {
 }
 impl TrytesDecoder for Branch {
     fn decode_column(start: usize, length: i32, acc: &mut Trytes) {
-        // Branch tryte offset is 2511..2592
-        acc.buffer().copy_within(start..(start + length as usize), 2511)
+        acc.buffer()
+            .copy_within(start..(start + length as usize), transaction::BRANCH.start)
     }
     fn handle_null(_: &mut Trytes) {
         unreachable!()
@@ -297,8 +300,8 @@ impl TrytesDecoder for Branch {
 }
 impl TrytesDecoder for Tag {
     fn decode_column(start: usize, length: i32, acc: &mut Trytes) {
-        // Tag tryte offset is 2592..2619
-        acc.buffer().copy_within(start..(start + length as usize), 2592)
+        acc.buffer()
+            .copy_within(start..(start + length as usize), transaction::TAG.start)
     }
     fn handle_null(_: &mut Trytes) {
         unreachable!()
@@ -306,8 +309,10 @@ impl TrytesDecoder for Tag {
 }
 impl TrytesDecoder for AttachmentTimestamp {
     fn decode_column(start: usize, length: i32, acc: &mut Trytes) {
-        // AttachmentTimestamp tryte offset is 2619..2628
-        acc.buffer().copy_within(start..(start + length as usize), 2619);
+        acc.buffer().copy_within(
+            start..(start + length as usize),
+            transaction::ATTACHMENT_TIMESTAMP.start,
+        );
     }
     fn handle_null(_: &mut Trytes) {
         unreachable!()
@@ -315,8 +320,10 @@ impl TrytesDecoder for AttachmentTimestamp {
 }
 impl TrytesDecoder for AttachmentTimestampLower {
     fn decode_column(start: usize, length: i32, acc: &mut Trytes) {
-        // AttachmentTimestampLower tryte offset is 2628..2637
-        acc.buffer().copy_within(start..(start + length as usize), 2628);
+        acc.buffer().copy_within(
+            start..(start + length as usize),
+            transaction::ATTACHMENT_TIMESTAMP_LOWER.start,
+        );
     }
     fn handle_null(_: &mut Trytes) {
         unreachable!()
@@ -324,8 +331,10 @@ impl TrytesDecoder for AttachmentTimestampLower {
 }
 impl TrytesDecoder for AttachmentTimestampUpper {
     fn decode_column(start: usize, length: i32, acc: &mut Trytes) {
-        // AttachmentTimestampUpper tryte offset is 2637..2646
-        acc.buffer().copy_within(start..(start + length as usize), 2637);
+        acc.buffer().copy_within(
+            start..(start + length as usize),
+            transaction::ATTACHMENT_TIMESTAMP_UPPER.start,
+        );
     }
     fn handle_null(_: &mut Trytes) {
         unreachable!()
@@ -333,8 +342,8 @@ impl TrytesDecoder for AttachmentTimestampUpper {
 }
 impl TrytesDecoder for Nonce {
     fn decode_column(start: usize, length: i32, acc: &mut Trytes) {
-        // Tag tryte offset is 2646..2673
-        acc.buffer().copy_within(start..(start + length as usize), 2646)
+        acc.buffer()
+            .copy_within(start..(start + length as usize), transaction::NONCE.start)
     }
     fn handle_null(_: &mut Trytes) {
         unreachable!()
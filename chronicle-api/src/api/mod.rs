@@ -16,6 +16,7 @@ macro_rules! response {
 
 pub mod endpoint;
 pub mod findtransactions;
+pub mod getconflicts;
 pub mod gettrytes;
 pub mod router;
 pub mod types;
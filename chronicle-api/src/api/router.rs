@@ -3,6 +3,7 @@ use super::{
         hints::Hint,
         FindTransactionsBuilder,
     },
+    getconflicts::GetConflictsBuilder,
     gettrytes::GetTrytesBuilder,
     types::Trytes81,
 };
@@ -103,6 +104,18 @@ async fn route(request: ReqBody) -> Response<Body> {
                 .run()
                 .await
         }
+        "getConflicts" => {
+            if request.addresses.is_some() || request.bundles.is_some() {
+                GetConflictsBuilder::new()
+                    .addresses(request.addresses)
+                    .bundles(request.bundles)
+                    .build()
+                    .run()
+                    .await
+            } else {
+                response!(status: BAD_REQUEST, body: r#"{"error":"No Addresses or Bundles"}"#)
+            }
+        }
         _ => response!(status: BAD_REQUEST, body: r#"{"error":"Invalid Request Command"}"#),
     }
 }
@@ -0,0 +1,138 @@
+// Synthetic dmp files, every line is a well formed transaction whose hash is its Curl-P-81 hash, so the importer
+// processes them exactly like the lines of a real dmp file (hash verification included).
+use chronicle_broker::curl;
+use chronicle_storage::transaction::{
+    HASH_TRYTES_LENGTH,
+    TRANSACTION_TRYTES_LENGTH,
+};
+use rand::{
+    rngs::StdRng,
+    Rng,
+    SeedableRng,
+};
+use std::{
+    error::Error,
+    fmt::Write,
+    path::Path,
+};
+use tokio::{
+    fs,
+    task,
+};
+
+const TRYTES: &[u8] = b"9ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+/// The transactions of a synthetic bundle.
+const BUNDLE_SIZE: u64 = 4;
+/// The transactions confirmed by a synthetic milestone.
+const TRANSACTIONS_PER_MILESTONE: u64 = 100;
+/// The milestone confirming the first transactions (the attachment timestamps are trusted after 337541).
+const FIRST_MILESTONE: u64 = 1_000_000;
+/// The timestamp (in seconds) of the first transaction.
+const FIRST_TIMESTAMP: i64 = 1_590_000_000;
+// the lines are generated in chunks on the blocking threads, as hashing the transactions is cpu bound.
+const CHUNK_SIZE: u64 = 1000;
+
+/// The milestone of a synthetic dmp file of the given number of transactions.
+pub fn milestone(transactions: u64) -> u64 {
+    FIRST_MILESTONE + transactions.saturating_sub(1) / TRANSACTIONS_PER_MILESTONE
+}
+
+/// Generate the dmp file of the given number of transactions, the lines only depend on the seed and the line number,
+/// so the same file is generated for the same arguments.
+pub async fn generate(path: &Path, transactions: u64, seed: u64) -> Result<(), Box<dyn Error>> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < transactions {
+        let end = (start + CHUNK_SIZE).min(transactions);
+        chunks.push(task::spawn_blocking(move || lines(start..end, seed)));
+        start = end;
+    }
+    let mut content = String::new();
+    for chunk in chunks {
+        content.push_str(&chunk.await?);
+    }
+    fs::write(path, content).await?;
+    Ok(())
+}
+
+// the dmp lines of the transactions, in order.
+fn lines(range: std::ops::Range<u64>, seed: u64) -> String {
+    let mut lines = String::with_capacity((range.end - range.start) as usize * (TRANSACTION_TRYTES_LENGTH + 100));
+    for index in range {
+        let trytes = transaction_trytes(index, seed);
+        let hash = trits_to_trytes(&curl::transaction_hash(&trytes).unwrap());
+        writeln!(
+            &mut lines,
+            "{},{},{}",
+            hash,
+            trytes,
+            FIRST_MILESTONE + index / TRANSACTIONS_PER_MILESTONE
+        )
+        .unwrap();
+    }
+    lines
+}
+
+// the trytes of the transaction at the index, with random payload, address and references, and consistent
+// value, timestamps and bundle indexes.
+fn transaction_trytes(index: u64, seed: u64) -> String {
+    let mut rng = StdRng::seed_from_u64(seed ^ index.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    let bundle = {
+        let mut bundle_rng = StdRng::seed_from_u64(seed ^ (index / BUNDLE_SIZE).wrapping_mul(0xC2B2_AE3D_27D4_EB4F));
+        random_trytes(&mut bundle_rng, HASH_TRYTES_LENGTH)
+    };
+    let timestamp = FIRST_TIMESTAMP + index as i64;
+    let mut trytes = String::with_capacity(TRANSACTION_TRYTES_LENGTH);
+    // payload, address
+    trytes.push_str(&random_trytes(&mut rng, 2187 + HASH_TRYTES_LENGTH));
+    // value
+    trytes.push_str(&i64_to_trytes(0, 27));
+    // obsolete tag
+    trytes.push_str(&random_trytes(&mut rng, 27));
+    // timestamp, current index, last index
+    trytes.push_str(&i64_to_trytes(timestamp, 9));
+    trytes.push_str(&i64_to_trytes((index % BUNDLE_SIZE) as i64, 9));
+    trytes.push_str(&i64_to_trytes(BUNDLE_SIZE as i64 - 1, 9));
+    trytes.push_str(&bundle);
+    // trunk, branch, tag
+    trytes.push_str(&random_trytes(&mut rng, 2 * HASH_TRYTES_LENGTH + 27));
+    // attachment timestamp (in milliseconds), its lower and upper bounds
+    trytes.push_str(&i64_to_trytes(timestamp * 1000, 9));
+    trytes.push_str(&i64_to_trytes(0, 9));
+    trytes.push_str(&i64_to_trytes(timestamp * 1000, 9));
+    // nonce
+    trytes.push_str(&random_trytes(&mut rng, 27));
+    debug_assert_eq!(trytes.len(), TRANSACTION_TRYTES_LENGTH);
+    trytes
+}
+
+fn random_trytes(rng: &mut StdRng, length: usize) -> String {
+    (0..length)
+        .map(|_| TRYTES[rng.gen_range(0, TRYTES.len())] as char)
+        .collect()
+}
+
+// encode the number in balanced ternary, as the length trytes of a transaction field.
+fn i64_to_trytes(mut value: i64, length: usize) -> String {
+    let mut trits = Vec::with_capacity(3 * length);
+    for _ in 0..(3 * length) {
+        let mut trit = value % 3;
+        value /= 3;
+        if trit > 1 {
+            trit -= 3;
+            value += 1;
+        } else if trit < -1 {
+            trit += 3;
+            value -= 1;
+        }
+        trits.push(trit as i8);
+    }
+    trits_to_trytes(&trits)
+}
+
+fn trits_to_trytes(trits: &[i8]) -> String {
+    trits
+        .chunks(3)
+        .map(|t| TRYTES[((t[0] + 3 * t[1] + 9 * t[2] + 27) % 27) as usize] as char)
+        .collect()
+}
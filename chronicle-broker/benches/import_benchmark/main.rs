@@ -0,0 +1,219 @@
+// The import benchmark runs the importer against an in-process mock Scylla node which acknowledges every insert,
+// so the numbers only reflect the client side cost (importer, ring, stages) of an import. It generates a synthetic
+// dmp file, imports it once, and reports the lines per second and the allocations of the import.
+//
+// cargo bench -p chronicle-broker --bench import_benchmark
+//
+// The import is tuned through the environment variables:
+// - IMPORT_BENCH_TRANSACTIONS: the lines of the synthetic dmp file (default 100000).
+// - IMPORT_BENCH_REPORTER_COUNT: the reporters per stage (default 2).
+// - IMPORT_BENCH_BUFFER_SIZE: the stage buffer size, and the socket recv/send buffer sizes (default 1024000).
+// - IMPORT_BENCH_MAX_IN_FLIGHT: the transactions in flight (default 1000).
+// - IMPORT_BENCH_BATCH_TYPE: insert each transaction as one "logged" or "unlogged" batch (unset by default).
+// - IMPORT_BENCH_VERIFY_HASH: verify the Curl-P-81 hash of each line (default true).
+// The dmp file is generated once per size in the temp folder and reused by the following runs.
+mod dmp;
+mod mock;
+
+use chronicle_broker::importer::{
+    ImporterBuilder,
+    DEFAULT_MAX_IN_FLIGHT,
+};
+use chronicle_common::launcher;
+use chronicle_cql::frame::batch::BatchTypes;
+use chronicle_storage::{
+    dashboard::client::add_nodes,
+    storage::StorageBuilder,
+};
+use log::*;
+use std::{
+    alloc::{
+        GlobalAlloc,
+        Layout,
+        System,
+    },
+    env,
+    path::PathBuf,
+    str::FromStr,
+    sync::atomic::{
+        AtomicU64,
+        Ordering,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+use tokio::time::delay_for;
+
+const DEFAULT_TRANSACTIONS: u64 = 100_000;
+const DEFAULT_REPORTER_COUNT: u8 = 2;
+const DEFAULT_BUFFER_SIZE: usize = 1_024_000;
+// the tokens of the mock node, as many as the default num_tokens of a Scylla node.
+const TOKEN_COUNT: usize = 256;
+const SEED: u64 = 42;
+
+/// Count the allocations of the whole process, the importer and the storage app run on the same threads.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size() as u64, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(new_size as u64, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// The settings of the benchmark, see the environment variables above.
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    transactions: u64,
+    reporter_count: u8,
+    buffer_size: usize,
+    max_in_flight: usize,
+    batch_type: Option<BatchTypes>,
+    verify_hash: bool,
+}
+
+impl Settings {
+    fn from_env() -> Self {
+        let batch_type = env::var("IMPORT_BENCH_BATCH_TYPE").ok().map(|batch| match &batch[..] {
+            "logged" => BatchTypes::Logged,
+            "unlogged" => BatchTypes::Unlogged,
+            _ => panic!(
+                "invalid IMPORT_BENCH_BATCH_TYPE: {}, expected logged or unlogged",
+                batch
+            ),
+        });
+        Settings {
+            transactions: var("IMPORT_BENCH_TRANSACTIONS", DEFAULT_TRANSACTIONS),
+            reporter_count: var("IMPORT_BENCH_REPORTER_COUNT", DEFAULT_REPORTER_COUNT),
+            buffer_size: var("IMPORT_BENCH_BUFFER_SIZE", DEFAULT_BUFFER_SIZE),
+            max_in_flight: var("IMPORT_BENCH_MAX_IN_FLIGHT", DEFAULT_MAX_IN_FLIGHT),
+            batch_type,
+            verify_hash: var("IMPORT_BENCH_VERIFY_HASH", true),
+        }
+    }
+}
+
+fn var<T: FromStr>(name: &str, default: T) -> T {
+    match env::var(name) {
+        Ok(value) => value.parse().unwrap_or_else(|_| panic!("invalid {}: {}", name, value)),
+        Err(_) => default,
+    }
+}
+
+launcher!(
+    apps_builder: AppsBuilder {storage: StorageBuilder}, // Apps
+    apps: Apps{} // Launcher state
+);
+
+impl AppsBuilder {
+    fn build(self, settings: Settings, dashboard_address: String) -> Apps {
+        let storage = StorageBuilder::new()
+            .listen_address(dashboard_address)
+            .thread_count(8)
+            .local_dc(mock::DATA_CENTER.to_string())
+            .reporter_count(settings.reporter_count)
+            .buffer_size(settings.buffer_size)
+            .recv_buffer_size(settings.buffer_size)
+            .send_buffer_size(settings.buffer_size);
+        self.storage(storage).to_apps()
+    }
+}
+
+#[tokio::main(core_threads = 8)]
+async fn main() {
+    let settings = Settings::from_env();
+    println!("import benchmark: {:?}", settings);
+    // generate the dmp file (once)
+    let filepath: PathBuf = env::temp_dir().join(format!("chronicle-import-benchmark-{}.dmp", settings.transactions));
+    if !filepath.exists() {
+        println!("generating {:?}", filepath);
+        dmp::generate(&filepath, settings.transactions, SEED)
+            .await
+            .expect("failed to generate the dmp file");
+    }
+    let filepath = filepath.to_str().unwrap().to_string();
+    // the checkpoint of the previous run marks the file as imported
+    let _ = std::fs::remove_file(format!("{}.checkpoint", filepath));
+    let node = mock::MockNode::spawn(TOKEN_COUNT)
+        .await
+        .expect("failed to spawn the mock node");
+    // reserve a free port for the dashboard
+    let dashboard_address = std::net::TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("failed to find a free port")
+        .to_string();
+    let websocket = format!("ws://{}/", dashboard_address);
+    AppsBuilder::new()
+        .build(settings, dashboard_address)
+        .storage()
+        .await
+        .future(|mut apps| {
+            let (filepath, websocket, node_address) = (filepath.clone(), websocket.clone(), node.address.to_string());
+            let requests = node.requests.clone();
+            async move {
+                // add the mock node and build the ring, once the dashboard listener is up
+                let mut attempts = 0;
+                while let Err(error) = add_nodes(&websocket, vec![node_address.clone()], 1).await {
+                    attempts += 1;
+                    if attempts == 50 {
+                        panic!("failed to add the mock node: {}", error);
+                    }
+                    delay_for(Duration::from_millis(100)).await;
+                }
+                let mut importer = ImporterBuilder::new()
+                    .filepath(filepath.clone())
+                    .milestone(dmp::milestone(settings.transactions))
+                    .only_confirmed(true)
+                    .verify_hash(settings.verify_hash)
+                    .max_retries(0)
+                    .max_in_flight(settings.max_in_flight)
+                    .show_progress(false);
+                if let Some(batch_type) = settings.batch_type {
+                    importer = importer.batch_type(batch_type);
+                }
+                let importer = importer.build();
+                let (allocations, allocated_bytes) = (
+                    ALLOCATIONS.load(Ordering::Relaxed),
+                    ALLOCATED_BYTES.load(Ordering::Relaxed),
+                );
+                let start = Instant::now();
+                importer.run().await.expect("failed to import the dmp file");
+                let elapsed = start.elapsed().as_secs_f64();
+                let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+                let allocated_bytes = ALLOCATED_BYTES.load(Ordering::Relaxed) - allocated_bytes;
+                let lines = settings.transactions as f64;
+                println!("imported {} lines in {:.3}s", settings.transactions, elapsed);
+                println!("lines/sec: {:.0}", lines / elapsed);
+                println!("requests/sec: {:.0}", requests.load(Ordering::Relaxed) as f64 / elapsed);
+                println!(
+                    "allocations: {} ({:.1}/line), allocated bytes: {} ({:.0}/line)",
+                    allocations,
+                    allocations as f64 / lines,
+                    allocated_bytes,
+                    allocated_bytes as f64 / lines
+                );
+                apps.tx.exit_program();
+                apps
+            }
+        })
+        .await
+        .one_for_one()
+        .await;
+}
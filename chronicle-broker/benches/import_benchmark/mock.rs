@@ -0,0 +1,185 @@
+// A mock Scylla node speaking just enough of the CQL protocol (v4) for the storage app: it answers the STARTUP and
+// OPTIONS handshake of every connection, the tokens query of the cluster supervisor, and acknowledges any other
+// request with a void result. It reuses its buffers, so it barely adds to the measured allocations.
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{
+            AtomicU64,
+            Ordering,
+        },
+        Arc,
+    },
+};
+use tokio::{
+    io::{
+        AsyncReadExt,
+        AsyncWriteExt,
+        BufReader,
+        BufWriter,
+    },
+    net::{
+        TcpListener,
+        TcpStream,
+    },
+};
+
+const HEADER_LENGTH: usize = 9;
+const RESPONSE_VERSION: u8 = 0x84;
+// opcodes
+const STARTUP: u8 = 0x01;
+const READY: u8 = 0x02;
+const OPTIONS: u8 = 0x05;
+const SUPPORTED: u8 = 0x06;
+const QUERY: u8 = 0x07;
+const RESULT: u8 = 0x08;
+// result kinds
+const VOID: i32 = 0x0001;
+const ROWS: i32 = 0x0002;
+// column types
+const VARCHAR: u16 = 0x000D;
+const INET: u16 = 0x0010;
+const LIST: u16 = 0x0020;
+/// The data center of the mock node.
+pub const DATA_CENTER: &str = "datacenter1";
+
+pub struct MockNode {
+    pub address: SocketAddr,
+    /// The acknowledged requests (the handshakes excluded).
+    pub requests: Arc<AtomicU64>,
+}
+
+impl MockNode {
+    /// Listen on a random local port, the node owns the given number of tokens (evenly spread over the ring).
+    pub async fn spawn(token_count: usize) -> io::Result<MockNode> {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let requests = Arc::new(AtomicU64::new(0));
+        let supported = supported_body();
+        let tokens = tokens_body(address, token_count);
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(serve(socket, supported.clone(), tokens.clone(), counter.clone()));
+            }
+        });
+        Ok(MockNode { address, requests })
+    }
+}
+
+// serve the connection until it's closed by the storage app.
+async fn serve(socket: TcpStream, supported: Arc<Vec<u8>>, tokens: Arc<Vec<u8>>, requests: Arc<AtomicU64>) {
+    let _ = socket.set_nodelay(true);
+    let (socket_rx, socket_tx) = tokio::io::split(socket);
+    let mut reader = BufReader::with_capacity(1 << 20, socket_rx);
+    let mut writer = BufWriter::with_capacity(1 << 16, socket_tx);
+    let mut header = [0; HEADER_LENGTH];
+    let mut body = Vec::new();
+    while reader.read_exact(&mut header).await.is_ok() {
+        let length = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) as usize;
+        body.resize(length, 0);
+        if reader.read_exact(&mut body).await.is_err() {
+            break;
+        }
+        let stream = [header[2], header[3]];
+        let written = match header[4] {
+            STARTUP => respond(&mut writer, stream, READY, &[]).await,
+            OPTIONS => respond(&mut writer, stream, SUPPORTED, &supported).await,
+            QUERY if contains(&body, b"system.local") => respond(&mut writer, stream, RESULT, &tokens).await,
+            _ => {
+                requests.fetch_add(1, Ordering::Relaxed);
+                respond(&mut writer, stream, RESULT, &VOID.to_be_bytes()).await
+            }
+        };
+        // the responses are flushed once the received requests are answered
+        if written.is_err() || (reader.buffer().is_empty() && writer.flush().await.is_err()) {
+            break;
+        }
+    }
+}
+
+async fn respond<W: AsyncWriteExt + Unpin>(writer: &mut W, stream: [u8; 2], opcode: u8, body: &[u8]) -> io::Result<()> {
+    let length = (body.len() as u32).to_be_bytes();
+    let header = [
+        RESPONSE_VERSION,
+        0,
+        stream[0],
+        stream[1],
+        opcode,
+        length[0],
+        length[1],
+        length[2],
+        length[3],
+    ];
+    writer.write_all(&header).await?;
+    writer.write_all(body).await
+}
+
+fn contains(body: &[u8], pattern: &[u8]) -> bool {
+    body.windows(pattern.len()).any(|window| window == pattern)
+}
+
+// the [string multimap] of the SUPPORTED response, announcing a single shard.
+fn supported_body() -> Arc<Vec<u8>> {
+    let options = [
+        ("SCYLLA_SHARD", "0"),
+        ("SCYLLA_NR_SHARDS", "1"),
+        ("SCYLLA_SHARDING_IGNORE_MSB", "12"),
+    ];
+    let mut body = Vec::new();
+    body.extend(&(options.len() as u16).to_be_bytes());
+    for (key, value) in options.iter() {
+        push_string(&mut body, key);
+        body.extend(&1u16.to_be_bytes());
+        push_string(&mut body, value);
+    }
+    Arc::new(body)
+}
+
+// the rows result of `SELECT data_center, rpc_address, tokens FROM system.local`.
+fn tokens_body(address: SocketAddr, token_count: usize) -> Arc<Vec<u8>> {
+    let mut body = Vec::new();
+    body.extend(&ROWS.to_be_bytes());
+    // metadata: global table spec, 3 columns
+    body.extend(&1i32.to_be_bytes());
+    body.extend(&3i32.to_be_bytes());
+    push_string(&mut body, "system");
+    push_string(&mut body, "local");
+    push_string(&mut body, "data_center");
+    body.extend(&VARCHAR.to_be_bytes());
+    push_string(&mut body, "rpc_address");
+    body.extend(&INET.to_be_bytes());
+    push_string(&mut body, "tokens");
+    body.extend(&LIST.to_be_bytes());
+    body.extend(&VARCHAR.to_be_bytes());
+    // the row
+    body.extend(&1i32.to_be_bytes());
+    push_bytes(&mut body, DATA_CENTER.as_bytes());
+    let ip = match address {
+        SocketAddr::V4(address) => address.ip().octets().to_vec(),
+        SocketAddr::V6(address) => address.ip().octets().to_vec(),
+    };
+    push_bytes(&mut body, &ip);
+    let step = (u64::MAX / token_count as u64) as i128;
+    let mut tokens = Vec::new();
+    tokens.extend(&(token_count as i32).to_be_bytes());
+    for i in 0..token_count {
+        let token = (i64::MIN as i128 + step * i as i128) as i64;
+        push_bytes(&mut tokens, token.to_string().as_bytes());
+    }
+    push_bytes(&mut body, &tokens);
+    Arc::new(body)
+}
+
+// [string]: a [short] n, followed by n bytes.
+fn push_string(body: &mut Vec<u8>, string: &str) {
+    body.extend(&(string.len() as u16).to_be_bytes());
+    body.extend(string.as_bytes());
+}
+
+// [bytes]: an [int] n, followed by n bytes.
+fn push_bytes(body: &mut Vec<u8>, bytes: &[u8]) {
+    body.extend(&(bytes.len() as i32).to_be_bytes());
+    body.extend(bytes);
+}
@@ -0,0 +1,197 @@
+// The backfill job recovers the confirmed transactions of a milestone range from a node, it's used to fill the gaps
+// when the broker was down, without the need of a dmp file.
+use crate::cone::{
+    Walker,
+    WalkerBuilder,
+};
+use chronicle_common::actor;
+use chronicle_cql::frame::batch::BatchTypes;
+use log::*;
+use std::error::Error;
+use tokio::fs;
+
+// the max number of milestone hashes looked up from the node at once.
+const MAX_MILESTONES_PER_LOOKUP: u64 = 100;
+
+actor!(BackfillBuilder {
+    node: String,
+    coordinator: String,
+    start: u64,
+    end: u64,
+    progress_file: String,
+    max_walk: usize,
+    max_retries: usize,
+    batch_type: Option<BatchTypes>
+});
+
+impl BackfillBuilder {
+    pub fn build(self) -> Backfill {
+        let node = self.node.unwrap();
+        let mut walker = WalkerBuilder::new()
+            .node(node.clone())
+            .max_retries(self.max_retries.unwrap())
+            .batch_type(self.batch_type.unwrap_or(None));
+        if let Some(max_walk) = self.max_walk {
+            walker = walker.max_walk(max_walk);
+        }
+        Backfill {
+            walker: walker.build(),
+            node,
+            coordinator: self.coordinator.unwrap(),
+            start: self.start.unwrap(),
+            end: self.end.unwrap(),
+            progress_file: self.progress_file.unwrap(),
+        }
+    }
+}
+
+pub struct Backfill {
+    walker: Walker,
+    node: String,
+    coordinator: String,
+    start: u64,
+    end: u64,
+    progress_file: String,
+}
+
+impl Backfill {
+    pub async fn run(mut self) -> Result<(), Box<dyn Error>> {
+        // resume from the last backfilled milestone (if any)
+        let mut start = self.start;
+        if let Ok(progress) = fs::read_to_string(&self.progress_file).await {
+            if let Ok(last) = progress.trim().parse::<u64>() {
+                if last >= start {
+                    info!("backfill: resuming after milestone: {}", last);
+                    start = last + 1;
+                }
+            }
+        }
+        if start > self.end {
+            info!(
+                "backfill: milestones {}..={} are backfilled already",
+                self.start, self.end
+            );
+            return Ok(());
+        }
+        // the progress only moves over the backfilled milestones, so a restart retries the unknown ones and
+        // continues the walk of the incomplete ones
+        let mut unknown = Vec::new();
+        let mut incomplete = Vec::new();
+        let mut window_start = start;
+        while window_start <= self.end {
+            let window_end = self.end.min(window_start + MAX_MILESTONES_PER_LOOKUP - 1);
+            let mut milestones = self
+                .walker
+                .milestones(&self.coordinator, window_start..=window_end)
+                .await?;
+            for index in window_start..=window_end {
+                if let Some(hash) = milestones.remove(&index) {
+                    let progress = self.walker.walk(hash, index).await?;
+                    info!("backfill: {:?}", progress);
+                    if !progress.complete {
+                        incomplete.push(index);
+                    } else if unknown.is_empty() && incomplete.is_empty() {
+                        fs::write(&self.progress_file, index.to_string()).await?;
+                    }
+                } else {
+                    warn!("backfill: milestone: {} is unknown to node: {}", index, self.node);
+                    unknown.push(index);
+                }
+            }
+            window_start = window_end + 1;
+        }
+        if !unknown.is_empty() {
+            warn!("backfill: milestones unknown to node: {}, {:?}", self.node, unknown);
+        }
+        if !incomplete.is_empty() {
+            warn!("backfill: milestones which exceeded max_walk: {:?}", incomplete);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::mock::mock_node,
+        cone::{
+            mock::{
+                failures,
+                fill_store,
+                mock_store,
+                stored,
+                transaction,
+            },
+            NULL_HASH,
+        },
+    };
+    use chronicle_storage::transaction::{
+        ADDRESS,
+        BRANCH,
+        OBSOLETE_TAG,
+        TAG,
+        TRUNK,
+    };
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn resume_incomplete_milestone() {
+        let progress_file = std::env::temp_dir()
+            .join(format!("chronicle-backfill-{}.progress", std::process::id()))
+            .to_str()
+            .unwrap()
+            .to_string();
+        let coordinator = "C".repeat(81);
+        // milestone 2 references milestone 1 and a missing parent, whose trunk is stored unconfirmed
+        let stored_parent = "S".repeat(81);
+        let (first, first_trytes) = transaction(&[(ADDRESS, &coordinator), (OBSOLETE_TAG, "A")]);
+        let (parent, parent_trytes) = transaction(&[(TRUNK, &stored_parent), (TAG, "PARENT")]);
+        let (second, second_trytes) = transaction(&[
+            (ADDRESS, &coordinator),
+            (OBSOLETE_TAG, "B"),
+            (TRUNK, &first),
+            (BRANCH, &parent),
+        ]);
+        let (third, third_trytes) = transaction(&[(ADDRESS, &coordinator), (OBSOLETE_TAG, "C"), (TRUNK, &second)]);
+        let mut node = HashMap::new();
+        node.insert(first.clone(), first_trytes);
+        node.insert(parent.clone(), parent_trytes);
+        node.insert(second.clone(), second_trytes);
+        node.insert(third.clone(), third_trytes);
+        let url = mock_node(node, 1);
+        let null = NULL_HASH.to_string();
+        let mut store = HashMap::new();
+        store.insert(first.clone(), (null.clone(), null.clone(), Some(1)));
+        store.insert(stored_parent.clone(), (null.clone(), null, None));
+        fill_store(store, 1);
+        // milestone 1 is backfilled already
+        fs::write(&progress_file, "1").await.unwrap();
+        let backfill = |url: String| {
+            let mut backfill = BackfillBuilder::new()
+                .node(url)
+                .coordinator(coordinator.clone())
+                .start(1)
+                .end(3)
+                .progress_file(progress_file.clone())
+                .max_walk(2)
+                .max_retries(2)
+                .build();
+            mock_store(&mut backfill.walker);
+            backfill
+        };
+        // milestone 2 exceeds max_walk before confirming the stored parent, so the progress stays at milestone 1
+        backfill(url.clone()).run().await.unwrap();
+        assert_eq!(failures(), 0);
+        assert_eq!(fs::read_to_string(&progress_file).await.unwrap(), "1");
+        assert_eq!(stored(&second).unwrap().2, Some(2));
+        assert_eq!(stored(&parent).unwrap().2, Some(2));
+        assert_eq!(stored(&stored_parent).unwrap().2, None);
+        assert_eq!(stored(&third).unwrap().2, Some(3));
+        // the restart continues the walk of milestone 2
+        backfill(url).run().await.unwrap();
+        assert_eq!(fs::read_to_string(&progress_file).await.unwrap(), "3");
+        assert_eq!(stored(&stored_parent).unwrap().2, Some(2));
+        fs::remove_file(&progress_file).await.unwrap();
+    }
+}
@@ -0,0 +1,518 @@
+use super::{
+    supervisor::{
+        self,
+        Peer,
+        Sender as SupervisorTx,
+        Topic,
+    },
+    unconfirmed::{
+        Confirmation,
+        Unconfirmed,
+    },
+};
+use crate::{
+    curl,
+    metrics::PeerMetrics,
+    selective::{
+        Decision,
+        SharedSelector,
+    },
+    tracker::TrackerTx,
+};
+use chronicle_common::actor;
+use chronicle_cql::{
+    compression::MyCompression,
+    frame::{
+        batch::BatchTypes,
+        decoder::{
+            Decoder,
+            Frame,
+        },
+    },
+};
+use chronicle_storage::{
+    ring::Ring,
+    stage::reporter,
+    transaction::{
+        self,
+        Transaction,
+        HASH_TRYTES_LENGTH,
+    },
+    worker::{
+        self,
+        Error,
+    },
+};
+use log::*;
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+        VecDeque,
+    },
+    sync::Arc,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+use tokio::{
+    sync::mpsc,
+    time::timeout,
+};
+type Sender = mpsc::UnboundedSender<Event>;
+type Receiver = mpsc::UnboundedReceiver<Event>;
+type TransactionId = usize;
+// the number of recently sent transactions kept per worker, to detect the duplicates sent by the peer.
+const RECENT_CAPACITY: usize = 50_000;
+actor!(IngestBuilder {
+    peer: Peer,
+    supervisor_tx: SupervisorTx,
+    max_in_flight: usize,
+    verify_hash: bool,
+    selector: Option<SharedSelector>,
+    batch_type: Option<BatchTypes>,
+    metrics: Arc<PeerMetrics>,
+    tracker: TrackerTx,
+    only_confirmed: bool,
+    unconfirmed: Option<Unconfirmed>,
+    shutdown_timeout: Duration
+});
+
+impl IngestBuilder {
+    pub fn build(self) -> Ingest {
+        // create channel
+        let (tx, rx) = mpsc::unbounded_channel::<Event>();
+        let max_in_flight = self.max_in_flight.unwrap();
+        // create pids in advance for the whole window, each transaction needs at most 8 pids
+        let mut pids = Vec::with_capacity(8 * max_in_flight);
+        for _ in 0..(8 * max_in_flight) {
+            pids.push(Box::new(IngestId(tx.clone(), 0)));
+        }
+        Ingest {
+            tx,
+            rx,
+            pids,
+            in_flight: HashMap::with_capacity(max_in_flight),
+            recent: HashSet::new(),
+            recent_order: VecDeque::new(),
+            max_in_flight,
+            next_id: 0,
+            verify_hash: self.verify_hash.unwrap(),
+            rejected: 0,
+            selector: self.selector.unwrap(),
+            batch_type: self.batch_type.unwrap(),
+            metrics: self.metrics.unwrap(),
+            tracker: self.tracker.unwrap(),
+            only_confirmed: self.only_confirmed.unwrap_or(false),
+            unconfirmed: self.unconfirmed.unwrap_or(None),
+            shutdown_timeout: self.shutdown_timeout.unwrap(),
+            shutting_down: false,
+            peer: self.peer.unwrap(),
+            supervisor_tx: self.supervisor_tx.unwrap(),
+        }
+    }
+}
+
+/// The processing shared by the zmq and mqtt workers of a peer: it parses, verifies and selects the transactions of
+/// the topic, and inserts them within the in flight window.
+pub struct Ingest {
+    tx: Sender,
+    rx: Receiver,
+    peer: Peer,
+    supervisor_tx: SupervisorTx,
+    pids: Vec<Box<IngestId>>,
+    in_flight: HashMap<TransactionId, InFlight>,
+    /// The hashes of the transactions in flight or recently stored, the failed ones are removed.
+    recent: HashSet<String>,
+    recent_order: VecDeque<String>,
+    max_in_flight: usize,
+    next_id: TransactionId,
+    verify_hash: bool,
+    rejected: u64,
+    selector: Option<SharedSelector>,
+    batch_type: Option<BatchTypes>,
+    metrics: Arc<PeerMetrics>,
+    tracker: TrackerTx,
+    only_confirmed: bool,
+    unconfirmed: Option<Unconfirmed>,
+    shutdown_timeout: Duration,
+    shutting_down: bool,
+}
+
+/// The bookkeeping of a transaction whose inserts are not fully responded yet.
+struct InFlight {
+    hash: String,
+    /// None if only the milestone column of the transaction is updated.
+    trytes: Option<String>,
+    milestone: Option<u64>,
+    pending: u8,
+    error: Option<Error>,
+    sent: Instant,
+}
+
+impl InFlight {
+    /// The transaction as a dmp line (hash,trytes,milestone), so it can be reinserted using the importer.
+    fn to_dmp_line(&self) -> Option<String> {
+        self.trytes
+            .as_ref()
+            .map(|trytes| format!("{},{},{}", self.hash, trytes, self.milestone.unwrap_or(0)))
+    }
+}
+
+/// A handle to shutdown the peer worker.
+pub struct Shutdown(Sender);
+impl Shutdown {
+    pub fn shutdown(self) {
+        let _ = self.0.send(Event::Shutdown);
+    }
+}
+
+#[derive(Debug)]
+pub struct IngestId(Sender, TransactionId);
+impl IngestId {
+    fn transaction_id(mut self: Box<Self>, id: TransactionId) -> Box<Self> {
+        self.1 = id;
+        self
+    }
+    fn get_transaction_id(&self) -> TransactionId {
+        self.1
+    }
+}
+
+pub enum Event {
+    Void { pid: Box<IngestId> },
+    Error { kind: Error, pid: Box<IngestId> },
+    Shutdown,
+}
+
+impl Ingest {
+    pub fn peer(&self) -> &Peer {
+        &self.peer
+    }
+    pub fn set_connected(&mut self, connected: bool) {
+        self.peer.set_connected(connected);
+    }
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down
+    }
+    /// Returns true if the in flight window is full, so the transport must stop consuming the topic.
+    pub fn is_window_full(&self) -> bool {
+        self.in_flight.len() >= self.max_in_flight
+    }
+    /// Receive the next response (or shutdown) event.
+    pub async fn recv(&mut self) -> Option<Event> {
+        self.rx.recv().await
+    }
+    pub fn shutdown_handle(&self) -> Shutdown {
+        Shutdown(self.tx.clone())
+    }
+
+    /// Wait for the responses of the in flight transactions up to the shutdown timeout, then tell the supervisor by
+    /// returning the peer and the unacknowledged transactions (if any).
+    pub async fn exit(mut self) {
+        self.drain().await;
+        let unacknowledged = self.in_flight.values().filter_map(InFlight::to_dmp_line).collect();
+        let _ = self.supervisor_tx.send(supervisor::Event::Exit {
+            peer: self.peer,
+            unacknowledged,
+        });
+    }
+
+    async fn drain(&mut self) {
+        let in_flight = self.in_flight.len();
+        let shutdown_timeout = self.shutdown_timeout;
+        let drain = async {
+            while !self.in_flight.is_empty() {
+                match self.rx.recv().await {
+                    Some(event) => self.handle_event(event),
+                    None => break,
+                }
+            }
+        };
+        if timeout(shutdown_timeout, drain).await.is_err() {
+            warn!(
+                "peer: {}, topic: {}, drained {} of {} in flight transactions before the shutdown timeout",
+                self.peer.get_address(),
+                self.peer.get_topic_as_string(),
+                in_flight - self.in_flight.len(),
+                in_flight
+            );
+        }
+    }
+
+    /// Process the payload of a message of the subscribed topic.
+    pub fn handle_payload(&mut self, payload: &str) {
+        self.metrics.received();
+        // process payload according the subscribed topic
+        match self.peer.get_topic() {
+            // this topic used to store newly seen transactions
+            Topic::Trytes => self.handle_trytes(payload),
+            // this topic used to store confirmed transactions only
+            Topic::SnTrytes => self.handle_sn_trytes(payload),
+            // this topic used to upsert milestone column in transaction table (confirmed status)
+            Topic::Sn => self.handle_sn(payload),
+        }
+    }
+
+    fn handle_trytes(&mut self, payload: &str) {
+        // trytes hash
+        match (payload.get(..2673), payload.get(2674..2755)) {
+            (Some(trytes), Some(hash)) => self.handle_transaction(hash, trytes, None),
+            _ => self.reject_payload("malformed trytes message"),
+        }
+    }
+    fn handle_sn_trytes(&mut self, payload: &str) {
+        // trytes hash milestone
+        let milestone = payload.get(2756..).and_then(|milestone| milestone.parse::<u64>().ok());
+        match (payload.get(..2673), payload.get(2674..2755), milestone) {
+            (Some(trytes), Some(hash), Some(milestone)) => self.handle_transaction(hash, trytes, Some(milestone)),
+            _ => self.reject_payload("malformed sn_trytes message"),
+        }
+    }
+    pub fn reject_payload(&mut self, reason: &str) {
+        self.rejected += 1;
+        self.metrics.rejected();
+        warn!(
+            "peer: {}, topic: {}, rejected {}, rejected so far: {}",
+            self.peer.get_address(),
+            self.peer.get_topic_as_string(),
+            reason,
+            self.rejected
+        );
+    }
+    fn handle_transaction(&mut self, hash: &str, trytes: &str, milestone: Option<u64>) {
+        // never trust the transaction sent by the node
+        let invalid = match Transaction::parse(hash, trytes) {
+            Ok(transaction) if !self.verify_hash || curl::verify_transaction(hash, trytes) => {
+                match (milestone, self.unconfirmed.as_ref()) {
+                    // the confirmed transaction doesn't await a confirmation anymore
+                    (Some(_), Some(unconfirmed)) => {
+                        unconfirmed.confirm(hash);
+                    }
+                    // hold the unconfirmed transaction until the sn topic confirms it
+                    (None, Some(unconfirmed)) if self.only_confirmed => {
+                        unconfirmed.hold(hash, trytes);
+                        return;
+                    }
+                    // nothing can confirm the transaction
+                    (None, None) if self.only_confirmed => return,
+                    _ => {}
+                }
+                // the peer sent the transaction already, it's in flight or stored
+                if self.recent.contains(hash) {
+                    self.metrics.duplicate();
+                    return;
+                }
+                self.select_transaction(&transaction, milestone);
+                return;
+            }
+            Ok(_) => "invalid hash".to_string(),
+            Err(error) => error.to_string(),
+        };
+        self.rejected += 1;
+        self.metrics.rejected();
+        warn!(
+            "peer: {}, topic: {}, rejected transaction: {}, {}, rejected so far: {}",
+            self.peer.get_address(),
+            self.peer.get_topic_as_string(),
+            hash,
+            invalid,
+            self.rejected
+        );
+    }
+    fn select_transaction(&mut self, transaction: &Transaction, milestone: Option<u64>) {
+        // the selector (if any) decides whether to store the transaction and the held members of its bundle
+        if let Some(selector) = self.selector.as_ref() {
+            match selector.select(transaction, milestone) {
+                Decision::Store(released) => {
+                    for held in released {
+                        if let Ok(held_transaction) = Transaction::parse(&held.hash, &held.trytes) {
+                            self.send_transaction(&held_transaction, held.milestone);
+                        }
+                    }
+                }
+                Decision::Skip => return,
+            }
+        }
+        self.send_transaction(transaction, milestone);
+    }
+    fn send_transaction(&mut self, transaction: &Transaction, milestone: Option<u64>) {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        // either all the inserts of the transaction, or a single batch routed to the owner of the transaction row
+        let queries = transaction.insert_queries(milestone, self.batch_type);
+        let pending = queries.len() as u8;
+        for (token, payload) in queries {
+            let request = reporter::Event::Request {
+                payload,
+                worker: self.pid(id),
+            };
+            Ring::send_local_random_replica(token, request);
+        }
+        if let (None, Some(unconfirmed)) = (milestone, self.unconfirmed.as_ref()) {
+            unconfirmed.stored(transaction.hash());
+        }
+        // the oldest sent transaction is forgotten first
+        if self.recent_order.len() == RECENT_CAPACITY {
+            let oldest = self.recent_order.pop_front().unwrap();
+            self.recent.remove(&oldest);
+        }
+        self.recent.insert(transaction.hash().to_string());
+        self.recent_order.push_back(transaction.hash().to_string());
+        self.in_flight.insert(
+            id,
+            InFlight {
+                hash: transaction.hash().to_string(),
+                trytes: Some(transaction.trytes().to_string()),
+                milestone,
+                pending,
+                error: None,
+                sent: Instant::now(),
+            },
+        );
+    }
+    fn handle_sn(&mut self, payload: &str) {
+        // milestone_index hash address trunk branch bundle
+        let mut fields = payload.split(' ');
+        let milestone = fields.next().and_then(|milestone| milestone.parse::<u64>().ok());
+        let hash = fields.next().filter(|hash| hash.len() == HASH_TRYTES_LENGTH);
+        let (milestone, hash) = match (milestone, hash) {
+            (Some(milestone), Some(hash)) => (milestone, hash),
+            _ => return self.reject_payload("malformed sn message"),
+        };
+        let confirmation = self
+            .unconfirmed
+            .as_ref()
+            .and_then(|unconfirmed| unconfirmed.confirm(hash));
+        match confirmation {
+            Some(Confirmation::Held(trytes)) => {
+                if let Ok(transaction) = Transaction::parse(hash, &trytes) {
+                    self.select_transaction(&transaction, Some(milestone));
+                }
+            }
+            Some(Confirmation::Stored) => self.update_milestone(hash, milestone),
+            // unknown to the broker (or expired), the solidifier (if enabled) fetches it
+            None => {}
+        }
+    }
+    fn update_milestone(&mut self, hash: &str, milestone: u64) {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        let request = reporter::Event::Request {
+            payload: transaction::update_milestone(hash, milestone),
+            worker: self.pid(id),
+        };
+        Ring::send_local_random_replica(transaction::token(hash.as_bytes()), request);
+        self.in_flight.insert(
+            id,
+            InFlight {
+                hash: hash.to_string(),
+                trytes: None,
+                milestone: Some(milestone),
+                pending: 1,
+                error: None,
+                sent: Instant::now(),
+            },
+        );
+    }
+
+    pub fn handle_event(&mut self, event: Event) {
+        let (id, error) = match event {
+            Event::Void { pid } => {
+                let id = pid.get_transaction_id();
+                self.pids.push(pid);
+                (id, None)
+            }
+            Event::Error { kind, pid } => {
+                let id = pid.get_transaction_id();
+                self.pids.push(pid);
+                (id, Some(kind))
+            }
+            Event::Shutdown => {
+                self.shutting_down = true;
+                return;
+            }
+        };
+        let in_flight = self.in_flight.get_mut(&id).unwrap();
+        in_flight.pending -= 1;
+        if let Some(error) = error {
+            self.metrics.error(&error);
+            in_flight.error = Some(error);
+        }
+        if in_flight.pending == 0 {
+            // all the responses of the transaction are received
+            let in_flight = self.in_flight.remove(&id).unwrap();
+            self.metrics
+                .inserted(in_flight.sent.elapsed(), in_flight.error.is_none());
+            if let Some(error) = in_flight.error.as_ref() {
+                // a duplicate of the failed transaction is inserted again
+                if in_flight.trytes.is_some() {
+                    self.recent.remove(&in_flight.hash);
+                }
+                // TODO retry and report to dashboard, check warnings,
+                // TOOD impl smart strategy for internal error: No Sender/Lost which happens when stage lose
+                // connection with scylla node, all what we can do is to retry a few times and if it kept failing:
+                // - alert admin for a possiblity of a dead scylla node
+                // - skip the transaction but make sure to log it (this log is important)
+                // eventually the admin should fix the data layer(scylladb), and everything should back to normal,
+                // NOTE: the solidifier (if enabled) fetches the missing confirmed transactions from its node, still
+                // once everything back to normal the log we just collected from the skipped transactions should
+                // be reinserted by the admin (possibly using importer)
+                error!(
+                    "peer: {}, topic: {}, failed to insert transaction: {}, error: {:?}",
+                    self.peer.get_address(),
+                    self.peer.get_topic_as_string(),
+                    in_flight.hash,
+                    error
+                );
+                // the supervisor appends it to the failure log
+                if let Some(dmp_line) = in_flight.to_dmp_line() {
+                    let _ = self.supervisor_tx.send(supervisor::Event::Failed(dmp_line));
+                }
+            } else if let Some(trytes) = in_flight.trytes.as_ref() {
+                // the transaction is stored, so it counts toward the completeness of its bundle
+                if let Ok(transaction) = Transaction::parse(&in_flight.hash, trytes) {
+                    self.tracker.stored(&transaction);
+                }
+            }
+        }
+    }
+
+    fn pid(&mut self, id: TransactionId) -> Box<IngestId> {
+        // reuse a pid from the pool, or create a new one in case the pool is drained
+        match self.pids.pop() {
+            Some(pid) => pid.transaction_id(id),
+            None => Box::new(IngestId(self.tx.clone(), id)),
+        }
+    }
+}
+
+impl worker::Worker for IngestId {
+    fn send_response(self: Box<Self>, _: &Option<reporter::Sender>, giveload: Vec<u8>) {
+        let decoder = Decoder::new(giveload, MyCompression::get());
+        unsafe {
+            let raw = Box::into_raw(self);
+            let pid = Box::from_raw(raw);
+            let event;
+            if decoder.is_error() {
+                let error = decoder.get_error();
+                event = Event::Error {
+                    kind: worker::Error::Cql(error),
+                    pid,
+                }
+            } else {
+                event = Event::Void { pid };
+            }
+            let _ = (*raw).0.send(event);
+        }
+    }
+    fn send_error(self: Box<Self>, kind: worker::Error) {
+        unsafe {
+            let raw = Box::into_raw(self);
+            let pid = Box::from_raw(raw);
+            let event = Event::Error { kind, pid };
+            let _ = (*raw).0.send(event);
+        }
+    }
+}
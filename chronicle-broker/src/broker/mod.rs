@@ -1,8 +1,52 @@
+pub mod ingest;
+pub mod mqtt;
 pub mod supervisor;
+pub mod unconfirmed;
 pub mod zmq;
 
+use crate::{
+    selective::SelectiveRules,
+    tracker::DEFAULT_BUNDLE_TIMEOUT,
+};
 use chronicle_common::app;
-app!(BrokerBuilder { trytes: Vec<String>, sn_trytes: Vec<String>, sn: Vec<String> });
+use chronicle_cql::frame::batch::BatchTypes;
+use std::{
+    net::SocketAddr,
+    time::Duration,
+};
+use unconfirmed::{
+    DEFAULT_UNCONFIRMED_CAPACITY,
+    DEFAULT_UNCONFIRMED_TTL,
+};
+/// The default number of transactions that a peer worker can keep in flight.
+pub const DEFAULT_MAX_IN_FLIGHT: usize = 256;
+/// The default seconds to wait for the in flight transactions on shutdown.
+pub const DEFAULT_SHUTDOWN_TIMEOUT: u64 = 10;
+/// The default path of the failure log, the transactions failed to be stored (in dmp format).
+pub const DEFAULT_FAILURE_LOG: &str = "broker_failures.dmp";
+app!(BrokerBuilder {
+    trytes: Vec<String>,
+    sn_trytes: Vec<String>,
+    sn: Vec<String>,
+    mqtt_sn: Vec<String>,
+    mqtt_trytes: Vec<String>,
+    mqtt_sn_trytes: Vec<String>,
+    max_in_flight: usize,
+    only_confirmed: bool,
+    unconfirmed_capacity: usize,
+    unconfirmed_ttl: u64,
+    verify_hash: bool,
+    selective: SelectiveRules,
+    batch_type: BatchTypes,
+    solidifier_node: String,
+    solidifier_coordinator: String,
+    solidifier_progress_file: String,
+    solidifier_max_walk: usize,
+    bundle_timeout: u64,
+    metrics_address: SocketAddr,
+    shutdown_timeout: u64,
+    failure_log: String
+});
 
 impl BrokerBuilder {
     pub fn build(self) -> Broker {
@@ -10,6 +54,28 @@ impl BrokerBuilder {
             .trytes(self.trytes)
             .sn_trytes(self.sn_trytes)
             .sn(self.sn)
+            .mqtt_sn(self.mqtt_sn)
+            .mqtt_trytes(self.mqtt_trytes)
+            .mqtt_sn_trytes(self.mqtt_sn_trytes)
+            .max_in_flight(self.max_in_flight.unwrap_or(DEFAULT_MAX_IN_FLIGHT))
+            .verify_hash(self.verify_hash.unwrap_or(true))
+            .only_confirmed(self.only_confirmed.unwrap_or(false))
+            .unconfirmed_capacity(self.unconfirmed_capacity.unwrap_or(DEFAULT_UNCONFIRMED_CAPACITY))
+            .unconfirmed_ttl(Duration::from_secs(
+                self.unconfirmed_ttl.unwrap_or(DEFAULT_UNCONFIRMED_TTL),
+            ))
+            .selective(self.selective)
+            .batch_type(self.batch_type)
+            .solidifier_node(self.solidifier_node)
+            .solidifier_coordinator(self.solidifier_coordinator)
+            .solidifier_progress_file(self.solidifier_progress_file)
+            .solidifier_max_walk(self.solidifier_max_walk)
+            .bundle_timeout(self.bundle_timeout.unwrap_or(DEFAULT_BUNDLE_TIMEOUT))
+            .metrics_address(self.metrics_address)
+            .shutdown_timeout(Duration::from_secs(
+                self.shutdown_timeout.unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT),
+            ))
+            .failure_log(self.failure_log.unwrap_or_else(|| DEFAULT_FAILURE_LOG.to_string()))
             .launcher_tx(self.launcher_tx.unwrap());
         Broker { supervisor_builder }
     }
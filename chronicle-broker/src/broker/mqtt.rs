@@ -0,0 +1,522 @@
+// The mqtt worker of a peer, and a minimal MQTT 3.1.1 subscriber, enough to consume the transaction topics (trytes,
+// sn_trytes, sn) published by the MQTT plugin of the node (ie Hornet/Bee). It subscribes with QoS 0 and clean session,
+// a message published by the node has the same payload as its zmq message without the leading topic.
+use super::ingest::{
+    Ingest,
+    Shutdown,
+};
+use chronicle_common::actor;
+use log::*;
+use std::{
+    io::{
+        Error,
+        ErrorKind,
+        Result,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+use tokio::{
+    io::{
+        AsyncReadExt,
+        AsyncWriteExt,
+    },
+    net::TcpStream,
+    time::{
+        delay_for,
+        timeout,
+    },
+};
+
+/// The default keep alive of the mqtt connection in seconds.
+pub const DEFAULT_KEEP_ALIVE: u64 = 60;
+/// The delay before reconnecting to a mqtt peer.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+actor!(MqttBuilder { ingest: Ingest });
+
+impl MqttBuilder {
+    pub fn build(self) -> Mqtt {
+        Mqtt {
+            ingest: self.ingest.unwrap(),
+        }
+    }
+}
+
+/// The worker which consumes the mqtt topic of a peer and inserts the transactions.
+pub struct Mqtt {
+    ingest: Ingest,
+}
+
+impl Mqtt {
+    pub async fn run(mut self) {
+        let topic = self.ingest.peer().get_topic_as_string();
+        let keep_alive = Duration::from_secs(DEFAULT_KEEP_ALIVE);
+        while !self.ingest.is_shutting_down() {
+            let client_id = format!("chronicle-{}-{}", topic, rand::random::<u32>());
+            match subscribe(self.ingest.peer().get_address(), &client_id, &topic, keep_alive).await {
+                Ok(mut subscription) => {
+                    self.ingest.set_connected(true);
+                    if let Err(error) = self.consume(&mut subscription).await {
+                        warn!(
+                            "peer: {}, topic: {}, mqtt error: {}",
+                            self.ingest.peer().get_address(),
+                            topic,
+                            error
+                        );
+                    }
+                    self.ingest.set_connected(false);
+                    if self.ingest.is_shutting_down() {
+                        // unsubscribe by disconnecting from the mqtt broker
+                        let _ = subscription.disconnect().await;
+                        break;
+                    }
+                }
+                Err(error) => {
+                    error!(
+                        "peer: {}, topic: {}, unable to subscribe, error: {}",
+                        self.ingest.peer().get_address(),
+                        topic,
+                        error
+                    );
+                }
+            }
+            // keep processing the responses until it's time to reconnect
+            let mut reconnect = delay_for(RECONNECT_DELAY);
+            while !self.ingest.is_shutting_down() {
+                tokio::select! {
+                    _ = &mut reconnect => break,
+                    Some(event) = self.ingest.recv() => {
+                        self.ingest.handle_event(event);
+                    }
+                }
+            }
+        }
+        // drain the in flight transactions and tell supervisor
+        self.ingest.exit().await;
+    }
+
+    pub fn shutdown_handle(&self) -> Shutdown {
+        self.ingest.shutdown_handle()
+    }
+
+    // consume the subscription until shutdown, returns once the connection got closed or failed.
+    async fn consume(&mut self, subscription: &mut Subscription) -> Result<()> {
+        while !self.ingest.is_shutting_down() {
+            if !self.ingest.is_window_full() {
+                // the window is not full, so we keep consuming the mqtt topic while processing the responses
+                tokio::select! {
+                    publish = subscription.next() => {
+                        match publish? {
+                            Some(publish) => self.handle_publish(publish),
+                            None => {
+                                warn!(
+                                    "peer: {}, topic: {}, mqtt connection closed",
+                                    self.ingest.peer().get_address(),
+                                    self.ingest.peer().get_topic_as_string()
+                                );
+                                break;
+                            }
+                        }
+                    }
+                    Some(event) = self.ingest.recv() => {
+                        self.ingest.handle_event(event);
+                    }
+                }
+            } else {
+                // the window is full, apply backpressure by awaiting responses only, but keep pinging the broker
+                // meanwhile, otherwise it drops the connection once the keep alive expires
+                tokio::select! {
+                    _ = delay_for(subscription.until_ping()) => {
+                        subscription.keep_alive().await?;
+                    }
+                    Some(event) = self.ingest.recv() => {
+                        self.ingest.handle_event(event);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_publish(&mut self, publish: Publish) {
+        match std::str::from_utf8(&publish.payload) {
+            Ok(payload) => self.ingest.handle_payload(payload),
+            Err(_) => self.ingest.reject_payload("non utf8 message"),
+        }
+    }
+}
+
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const PUBACK: u8 = 4;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const PINGREQ: u8 = 12;
+const PINGRESP: u8 = 13;
+const DISCONNECT: u8 = 14;
+
+/// A message published on the subscribed topic.
+#[derive(Debug, PartialEq)]
+pub struct Publish {
+    pub topic: String,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Packet {
+    ConnAck { return_code: u8 },
+    Publish { packet_id: Option<u16>, publish: Publish },
+    SubAck { return_codes: Vec<u8> },
+    PingResp,
+    Other(u8),
+}
+
+/// A connection subscribed to a topic of a mqtt broker.
+pub struct Subscription {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+    keep_alive: Duration,
+    last_sent: Instant,
+}
+
+/// Connect to the mqtt broker at address (host:port) and subscribe to the topic.
+pub async fn subscribe(address: &str, client_id: &str, topic: &str, keep_alive: Duration) -> Result<Subscription> {
+    let stream = TcpStream::connect(address).await?;
+    let mut subscription = Subscription {
+        stream,
+        buffer: Vec::new(),
+        keep_alive,
+        last_sent: Instant::now(),
+    };
+    subscription.send(&connect(client_id, keep_alive)).await?;
+    match subscription.recv_packet().await? {
+        Some(Packet::ConnAck { return_code: 0 }) => {}
+        Some(Packet::ConnAck { return_code }) => {
+            return Err(Error::new(
+                ErrorKind::ConnectionRefused,
+                format!("mqtt connection refused, return code: {}", return_code),
+            ));
+        }
+        _ => return Err(Error::new(ErrorKind::InvalidData, "expected mqtt connack")),
+    }
+    subscription.send(&subscribe_packet(1, topic)).await?;
+    match subscription.recv_packet().await? {
+        Some(Packet::SubAck { return_codes }) if return_codes.iter().all(|code| *code < 0x80) => {}
+        Some(Packet::SubAck { .. }) => {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("mqtt subscription to topic: {} refused", topic),
+            ));
+        }
+        _ => return Err(Error::new(ErrorKind::InvalidData, "expected mqtt suback")),
+    }
+    Ok(subscription)
+}
+
+impl Subscription {
+    /// Receive the next published message, returns None once the broker closes the connection.
+    /// NOTE: it's cancel safe for QoS 0 messages, so it can be used as a branch of tokio::select!.
+    pub async fn next(&mut self) -> Result<Option<Publish>> {
+        loop {
+            match decode(&mut self.buffer)? {
+                Some(Packet::Publish { packet_id, publish }) => {
+                    // we subscribe with QoS 0, still a QoS 1 publish must be acknowledged
+                    if let Some(packet_id) = packet_id {
+                        let [msb, lsb] = packet_id.to_be_bytes();
+                        self.send(&[PUBACK << 4, 2, msb, lsb]).await?;
+                    }
+                    return Ok(Some(publish));
+                }
+                Some(_) => continue,
+                None => {}
+            }
+            self.keep_alive().await?;
+            let until_ping = self.until_ping();
+            if !self.fill(until_ping).await? {
+                return Ok(None);
+            }
+        }
+    }
+    /// The time left until a ping is due to keep the connection alive.
+    pub fn until_ping(&self) -> Duration {
+        (self.keep_alive / 2)
+            .checked_sub(self.last_sent.elapsed())
+            .unwrap_or_default()
+    }
+    /// Ping the broker if it's due, without reading the connection, so it can keep the connection alive while the
+    /// subscriber doesn't consume the messages.
+    pub async fn keep_alive(&mut self) -> Result<()> {
+        if self.until_ping() == Duration::from_secs(0) {
+            self.send(&[PINGREQ << 4, 0]).await?;
+        }
+        Ok(())
+    }
+    /// Disconnect gracefully from the broker.
+    pub async fn disconnect(mut self) -> Result<()> {
+        self.send(&[DISCONNECT << 4, 0]).await
+    }
+    async fn recv_packet(&mut self) -> Result<Option<Packet>> {
+        loop {
+            if let Some(packet) = decode(&mut self.buffer)? {
+                return Ok(Some(packet));
+            }
+            if !self.fill(self.keep_alive).await? {
+                return Ok(None);
+            }
+        }
+    }
+    // read the available bytes into the buffer up to the duration, returns false if the connection got closed.
+    async fn fill(&mut self, duration: Duration) -> Result<bool> {
+        let mut chunk = [0; 8192];
+        match timeout(duration, self.stream.read(&mut chunk)).await {
+            Ok(Ok(0)) => Ok(false),
+            Ok(Ok(n)) => {
+                self.buffer.extend_from_slice(&chunk[..n]);
+                Ok(true)
+            }
+            Ok(Err(error)) => Err(error),
+            // time to ping
+            Err(_) => Ok(true),
+        }
+    }
+    async fn send(&mut self, packet: &[u8]) -> Result<()> {
+        self.stream.write_all(packet).await?;
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+}
+
+fn connect(client_id: &str, keep_alive: Duration) -> Vec<u8> {
+    let mut body = Vec::new();
+    put_string(&mut body, "MQTT");
+    // protocol level 4 (3.1.1), clean session flag
+    body.push(4);
+    body.push(0b10);
+    body.extend_from_slice(&(keep_alive.as_secs().min(u16::MAX as u64) as u16).to_be_bytes());
+    put_string(&mut body, client_id);
+    packet(CONNECT << 4, body)
+}
+
+fn subscribe_packet(packet_id: u16, topic: &str) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&packet_id.to_be_bytes());
+    put_string(&mut body, topic);
+    // requested QoS 0
+    body.push(0);
+    packet(SUBSCRIBE << 4 | 0b10, body)
+}
+
+fn packet(header: u8, body: Vec<u8>) -> Vec<u8> {
+    let mut packet = vec![header];
+    let mut remaining = body.len();
+    loop {
+        let mut byte = (remaining % 128) as u8;
+        remaining /= 128;
+        if remaining > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if remaining == 0 {
+            break;
+        }
+    }
+    packet.extend(body);
+    packet
+}
+
+fn put_string(buffer: &mut Vec<u8>, string: &str) {
+    buffer.extend_from_slice(&(string.len() as u16).to_be_bytes());
+    buffer.extend_from_slice(string.as_bytes());
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+// decode the first packet in the buffer (if complete) and remove it from the buffer.
+fn decode(buffer: &mut Vec<u8>) -> Result<Option<Packet>> {
+    let mut remaining = 0;
+    let mut multiplier = 1;
+    let mut header_len = 1;
+    loop {
+        let byte = match buffer.get(header_len) {
+            Some(byte) => *byte as usize,
+            None => return Ok(None),
+        };
+        header_len += 1;
+        remaining += (byte & 0x7f) * multiplier;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        multiplier *= 128;
+        // the remaining length is encoded in at most 4 bytes
+        if header_len > 4 {
+            return Err(invalid("malformed mqtt remaining length"));
+        }
+    }
+    if buffer.len() < header_len + remaining {
+        return Ok(None);
+    }
+    let packet: Vec<u8> = buffer.drain(..header_len + remaining).collect();
+    let (header, body) = (packet[0], &packet[header_len..]);
+    let packet = match header >> 4 {
+        CONNACK if body.len() == 2 => Packet::ConnAck { return_code: body[1] },
+        PUBLISH => {
+            let qos = (header >> 1) & 0b11;
+            let topic_len = u16::from_be_bytes([
+                *body.get(0).ok_or_else(|| invalid("truncated mqtt publish"))?,
+                *body.get(1).ok_or_else(|| invalid("truncated mqtt publish"))?,
+            ]) as usize;
+            let mut offset = 2 + topic_len;
+            let topic = body
+                .get(2..offset)
+                .and_then(|topic| std::str::from_utf8(topic).ok())
+                .ok_or_else(|| invalid("invalid mqtt publish topic"))?
+                .to_string();
+            let packet_id = if qos > 0 {
+                let packet_id = body
+                    .get(offset..offset + 2)
+                    .ok_or_else(|| invalid("truncated mqtt publish"))?;
+                offset += 2;
+                Some(u16::from_be_bytes([packet_id[0], packet_id[1]]))
+            } else {
+                None
+            };
+            Packet::Publish {
+                packet_id,
+                publish: Publish {
+                    topic,
+                    payload: body[offset..].to_vec(),
+                },
+            }
+        }
+        SUBACK if body.len() > 2 => Packet::SubAck {
+            return_codes: body[2..].to_vec(),
+        },
+        PINGRESP => Packet::PingResp,
+        CONNACK | SUBACK => return Err(invalid("malformed mqtt packet")),
+        kind => Packet::Other(kind),
+    };
+    Ok(Some(packet))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn decode_partial_and_long_packets() {
+        let payload = vec![b'9'; 2755];
+        let mut body = Vec::new();
+        put_string(&mut body, "trytes");
+        body.extend_from_slice(&payload);
+        let mut buffer = packet(PUBLISH << 4, body);
+        // the remaining length of the publish needs 2 bytes
+        assert_eq!(&buffer[1..3], &[0xcb, 0x15]);
+        let rest = buffer.split_off(100);
+        assert_eq!(decode(&mut buffer).unwrap(), None);
+        buffer.extend(rest);
+        buffer.extend_from_slice(&[PINGRESP << 4, 0]);
+        let publish = Publish {
+            topic: "trytes".to_string(),
+            payload,
+        };
+        assert_eq!(
+            decode(&mut buffer).unwrap(),
+            Some(Packet::Publish {
+                packet_id: None,
+                publish
+            })
+        );
+        assert_eq!(decode(&mut buffer).unwrap(), Some(Packet::PingResp));
+        assert!(buffer.is_empty());
+    }
+
+    // a mqtt broker stand-in, which accepts a single subscriber, publishes the messages to it and awaits the pings
+    async fn mock_broker(mut listener: TcpListener, messages: Vec<&'static str>, pings: usize) {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut buffer = Vec::new();
+        let mut chunk = [0; 1024];
+        let mut subscribed = None;
+        while subscribed.is_none() {
+            let n = stream.read(&mut chunk).await.unwrap();
+            buffer.extend_from_slice(&chunk[..n]);
+            while buffer.len() >= 2 && buffer.len() >= 2 + buffer[1] as usize {
+                let packet: Vec<u8> = buffer.drain(..2 + buffer[1] as usize).collect();
+                match packet[0] >> 4 {
+                    CONNECT => {
+                        assert_eq!(&packet[2..8], b"\x00\x04MQTT");
+                        stream.write_all(&[CONNACK << 4, 2, 0, 0]).await.unwrap();
+                    }
+                    SUBSCRIBE => {
+                        let topic_len = u16::from_be_bytes([packet[4], packet[5]]) as usize;
+                        subscribed = Some(String::from_utf8(packet[6..6 + topic_len].to_vec()).unwrap());
+                        stream
+                            .write_all(&[SUBACK << 4, 3, packet[2], packet[3], 0])
+                            .await
+                            .unwrap();
+                    }
+                    kind => panic!("unexpected packet: {}", kind),
+                }
+            }
+        }
+        let topic = subscribed.unwrap();
+        for message in messages {
+            let mut body = Vec::new();
+            put_string(&mut body, &topic);
+            body.extend_from_slice(message.as_bytes());
+            stream.write_all(&packet(PUBLISH << 4, body)).await.unwrap();
+        }
+        for _ in 0..pings {
+            let mut ping = [0; 2];
+            stream.read_exact(&mut ping).await.unwrap();
+            assert_eq!(ping, [PINGREQ << 4, 0]);
+            stream.write_all(&[PINGRESP << 4, 0]).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn subscribe_with_mock_broker() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(mock_broker(listener, vec!["first", "second"], 0));
+        let mut subscription = subscribe(
+            &address,
+            "chronicle",
+            "sn_trytes",
+            Duration::from_secs(DEFAULT_KEEP_ALIVE),
+        )
+        .await
+        .unwrap();
+        for expected in &["first", "second"] {
+            let publish = subscription.next().await.unwrap().unwrap();
+            assert_eq!(publish.topic, "sn_trytes");
+            assert_eq!(publish.payload, expected.as_bytes());
+        }
+        // the broker stand-in closed the connection
+        assert_eq!(subscription.next().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn keep_alive_without_reading() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(mock_broker(listener, vec!["first"], 1));
+        let keep_alive = Duration::from_millis(200);
+        let mut subscription = subscribe(&address, "chronicle", "sn", keep_alive).await.unwrap();
+        // not due yet
+        subscription.keep_alive().await.unwrap();
+        assert!(subscription.until_ping() > Duration::from_secs(0));
+        delay_for(subscription.until_ping()).await;
+        subscription.keep_alive().await.unwrap();
+        assert!(subscription.until_ping() > keep_alive / 4);
+        // the published message is still there, then the broker stand-in closes the connection once it got the ping
+        assert_eq!(subscription.next().await.unwrap().unwrap().payload, b"first");
+        assert_eq!(subscription.next().await.unwrap(), None);
+    }
+}
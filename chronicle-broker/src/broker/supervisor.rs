@@ -1,4 +1,23 @@
-use super::zmq;
+use super::{
+    ingest::IngestBuilder,
+    mqtt::MqttBuilder,
+    unconfirmed::Unconfirmed,
+    zmq::ZmqBuilder,
+};
+use crate::{
+    metrics::{
+        Metrics,
+        DEFAULT_REPORT_INTERVAL,
+    },
+    selective::{
+        self,
+        SelectiveRules,
+        Selector,
+        SharedSelector,
+    },
+    solidifier::SolidifierBuilder,
+    tracker::TrackerBuilder,
+};
 use chronicle_common::{
     actor,
     traits::{
@@ -6,17 +25,56 @@ use chronicle_common::{
         shutdown::ShutdownTx,
     },
 };
-use std::string::ToString;
-use tokio::sync::mpsc;
+use chronicle_cql::frame::batch::BatchTypes;
+use log::*;
+use std::{
+    net::SocketAddr,
+    string::ToString,
+    time::Duration,
+};
+use tokio::{
+    fs::OpenOptions,
+    io::AsyncWriteExt,
+    sync::{
+        mpsc,
+        watch,
+    },
+};
 actor!(SupervisorBuilder {
     sn: Option<Vec<String>>,
     trytes: Option<Vec<String>>,
     sn_trytes: Option<Vec<String>>,
+    mqtt_sn: Option<Vec<String>>,
+    mqtt_trytes: Option<Vec<String>>,
+    mqtt_sn_trytes: Option<Vec<String>>,
+    max_in_flight: usize,
+    only_confirmed: bool,
+    unconfirmed_capacity: usize,
+    unconfirmed_ttl: Duration,
+    verify_hash: bool,
+    selective: Option<SelectiveRules>,
+    batch_type: Option<BatchTypes>,
+    solidifier_node: Option<String>,
+    solidifier_coordinator: Option<String>,
+    solidifier_progress_file: Option<String>,
+    solidifier_max_walk: Option<usize>,
+    bundle_timeout: u64,
+    metrics_address: Option<SocketAddr>,
+    shutdown_timeout: Duration,
+    failure_log: String,
     launcher_tx: Box<dyn LauncherTx>
 });
 pub enum Event {
     // TODO useful events to dyanmicly add/remove zmq nodes
     Shutdown,
+    /// A transaction failed to be stored, as a dmp line.
+    Failed(String),
+    /// A peer worker exited, returning its peer and the transactions which got no response before the shutdown
+    /// timeout.
+    Exit {
+        peer: Peer,
+        unacknowledged: Vec<String>,
+    },
 }
 pub type Sender = mpsc::UnboundedSender<Event>;
 pub type Receiver = mpsc::UnboundedReceiver<Event>;
@@ -31,6 +89,7 @@ impl ShutdownTx for Shutdown {
 
 pub struct Peer {
     topic: Topic,
+    protocol: Protocol,
     address: String,
     connected: bool,
 }
@@ -44,12 +103,15 @@ impl Peer {
     pub fn get_topic_as_string(&self) -> String {
         self.topic.to_string()
     }
+    pub fn get_protocol(&self) -> Protocol {
+        self.protocol
+    }
     pub fn set_connected(&mut self, connected: bool) {
         self.connected = connected;
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Hash, PartialEq, Eq)]
 pub enum Topic {
     Sn,
     Trytes,
@@ -65,34 +127,30 @@ impl ToString for Topic {
     }
 }
 
+/// The protocol used to subscribe to the topic of a peer.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Protocol {
+    Zmq,
+    Mqtt,
+}
+
 impl SupervisorBuilder {
     pub fn build(self) -> Supervisor {
         let mut peers = Vec::new();
-        // create peers from sn nodes (if any)
-        if let Some(mut addresses) = self.sn.unwrap().take() {
-            for address in addresses.drain(..) {
-                peers.push(Peer {
-                    topic: Topic::Sn,
-                    address,
-                    connected: false,
-                })
-            }
-        }
-        // create peers from trytes nodes (if any)
-        if let Some(mut addresses) = self.trytes.unwrap().take() {
-            for address in addresses.drain(..) {
-                peers.push(Peer {
-                    topic: Topic::Trytes,
-                    address,
-                    connected: false,
-                })
-            }
-        }
-        // create peers from sn_trytes nodes (if any)
-        if let Some(mut addresses) = self.sn_trytes.unwrap().take() {
-            for address in addresses.drain(..) {
+        // create peers from the sn, trytes and sn_trytes nodes (if any) of both protocols
+        let nodes = vec![
+            (self.sn, Topic::Sn, Protocol::Zmq),
+            (self.trytes, Topic::Trytes, Protocol::Zmq),
+            (self.sn_trytes, Topic::SnTrytes, Protocol::Zmq),
+            (self.mqtt_sn, Topic::Sn, Protocol::Mqtt),
+            (self.mqtt_trytes, Topic::Trytes, Protocol::Mqtt),
+            (self.mqtt_sn_trytes, Topic::SnTrytes, Protocol::Mqtt),
+        ];
+        for (addresses, topic, protocol) in nodes {
+            for address in addresses.flatten().unwrap_or_default() {
                 peers.push(Peer {
-                    topic: Topic::SnTrytes,
+                    topic,
+                    protocol,
                     address,
                     connected: false,
                 })
@@ -101,6 +159,22 @@ impl SupervisorBuilder {
         let (tx, rx) = mpsc::unbounded_channel::<Event>();
         Supervisor {
             peers,
+            max_in_flight: self.max_in_flight.unwrap(),
+            only_confirmed: self.only_confirmed.unwrap(),
+            unconfirmed_capacity: self.unconfirmed_capacity.unwrap(),
+            unconfirmed_ttl: self.unconfirmed_ttl.unwrap(),
+            verify_hash: self.verify_hash.unwrap(),
+            selective: self.selective.unwrap(),
+            batch_type: self.batch_type.unwrap(),
+            solidifier_node: self.solidifier_node.unwrap(),
+            solidifier_coordinator: self.solidifier_coordinator.unwrap(),
+            solidifier_progress_file: self.solidifier_progress_file.unwrap(),
+            solidifier_max_walk: self.solidifier_max_walk.unwrap(),
+            bundle_timeout: self.bundle_timeout.unwrap(),
+            metrics: Metrics::new(),
+            metrics_address: self.metrics_address.unwrap(),
+            shutdown_timeout: self.shutdown_timeout.unwrap(),
+            failure_log: self.failure_log.unwrap(),
             tx,
             rx,
             launcher_tx: self.launcher_tx.unwrap(),
@@ -109,6 +183,22 @@ impl SupervisorBuilder {
 }
 pub struct Supervisor {
     peers: Vec<Peer>,
+    max_in_flight: usize,
+    only_confirmed: bool,
+    unconfirmed_capacity: usize,
+    unconfirmed_ttl: Duration,
+    verify_hash: bool,
+    selective: Option<SelectiveRules>,
+    batch_type: Option<BatchTypes>,
+    solidifier_node: Option<String>,
+    solidifier_coordinator: Option<String>,
+    solidifier_progress_file: Option<String>,
+    solidifier_max_walk: Option<usize>,
+    bundle_timeout: u64,
+    metrics: Metrics,
+    metrics_address: Option<SocketAddr>,
+    shutdown_timeout: Duration,
+    failure_log: String,
     tx: Sender,
     rx: Receiver,
     launcher_tx: Box<dyn LauncherTx>,
@@ -116,25 +206,153 @@ pub struct Supervisor {
 
 impl Supervisor {
     pub async fn run(mut self) {
-        for peer in self.peers {
-            let zmq_worker = zmq::ZmqBuilder::new().peer(peer).supervisor_tx(self.tx.clone()).build();
-            tokio::spawn(zmq_worker.run());
+        // the background tasks stop once stop_tx is dropped
+        let (stop_tx, stop_rx) = watch::channel(());
+        // spawn the tracker of the bundles stored by the peer workers and the solidifier
+        let tracker = TrackerBuilder::new()
+            .timeout(self.bundle_timeout)
+            .stop(stop_rx.clone())
+            .build();
+        let tracker_tx = tracker.sender();
+        tokio::spawn(tracker.run());
+        // the sn topic (if any) confirms the recent transactions of the trytes topic
+        let unconfirmed = if self.peers.iter().any(|peer| peer.get_topic() == Topic::Sn) {
+            Some(Unconfirmed::new(self.unconfirmed_capacity, self.unconfirmed_ttl))
+        } else {
+            if self.only_confirmed {
+                warn!("broker: only_confirmed without sn nodes, the transactions of the trytes topic are dropped");
+            }
+            None
+        };
+        // the peer workers share the selector, so the members of a bundle are selected together whatever their peer
+        let selector = self
+            .selective
+            .take()
+            .map(|rules| SharedSelector::new(Selector::new(rules, selective::DEFAULT_MAX_BUNDLES)));
+        let mut workers = Vec::new();
+        for peer in self.peers.drain(..) {
+            let metrics = self.metrics.register(peer.get_address(), &peer.get_topic_as_string());
+            let protocol = peer.get_protocol();
+            let ingest = IngestBuilder::new()
+                .peer(peer)
+                .metrics(metrics)
+                .tracker(tracker_tx.clone())
+                .only_confirmed(self.only_confirmed)
+                .unconfirmed(unconfirmed.clone())
+                .supervisor_tx(self.tx.clone())
+                .max_in_flight(self.max_in_flight)
+                .verify_hash(self.verify_hash)
+                .selector(selector.clone())
+                .batch_type(self.batch_type)
+                .shutdown_timeout(self.shutdown_timeout)
+                .build();
+            match protocol {
+                Protocol::Zmq => {
+                    let zmq_worker = ZmqBuilder::new().ingest(ingest).build();
+                    workers.push(zmq_worker.shutdown_handle());
+                    tokio::spawn(zmq_worker.run());
+                }
+                Protocol::Mqtt => {
+                    let mqtt_worker = MqttBuilder::new().ingest(ingest).build();
+                    workers.push(mqtt_worker.shutdown_handle());
+                    tokio::spawn(mqtt_worker.run());
+                }
+            }
+        }
+        // spawn the solidifier (if any node is configured)
+        if let Some(node) = self.solidifier_node.take() {
+            let mut solidifier = SolidifierBuilder::new()
+                .node(node)
+                .batch_type(self.batch_type)
+                .tracker(tracker_tx)
+                .stop(stop_rx.clone());
+            if let Some(coordinator) = self.solidifier_coordinator.take() {
+                solidifier = solidifier.coordinator(coordinator);
+            }
+            if let Some(progress_file) = self.solidifier_progress_file.take() {
+                solidifier = solidifier.progress_file(progress_file);
+            }
+            if let Some(max_walk) = self.solidifier_max_walk {
+                solidifier = solidifier.max_walk(max_walk);
+            }
+            tokio::spawn(solidifier.build().run());
+        }
+        // report the metrics to the dashboard(s), and serve them to prometheus (if any address is configured)
+        tokio::spawn(
+            self.metrics
+                .clone()
+                .report(self.launcher_tx.clone(), DEFAULT_REPORT_INTERVAL, stop_rx.clone()),
+        );
+        if let Some(address) = self.metrics_address {
+            tokio::spawn(self.metrics.clone().serve(address, stop_rx));
         }
         // register broker app with launcher
         self.launcher_tx
             .register_app("broker".to_string(), Box::new(Shutdown(self.tx.clone())));
+        let mut running = workers.len();
         while let Some(event) = self.rx.recv().await {
             match event {
                 Event::Shutdown => {
-                    // todo shutdown zmq worker
+                    // unsubscribe the peer workers, they drain their in flight transactions before exiting
+                    for worker in workers.drain(..) {
+                        worker.shutdown();
+                    }
+                    drop(stop_tx);
                     break;
-                } // _ => todo!(),
+                }
+                Event::Failed(dmp_line) => log_failures(&self.failure_log, vec![dmp_line]).await,
+                Event::Exit { peer, unacknowledged } => {
+                    // the peer worker exited without being asked to
+                    error!(
+                        "peer: {}, topic: {}, worker exited",
+                        peer.get_address(),
+                        peer.get_topic_as_string()
+                    );
+                    log_failures(&self.failure_log, unacknowledged).await;
+                    running -= 1;
+                }
+            }
+        }
+        // await the exit of the peer workers, and persist the transactions they were not able to store
+        while running > 0 {
+            match self.rx.recv().await {
+                Some(Event::Failed(dmp_line)) => log_failures(&self.failure_log, vec![dmp_line]).await,
+                Some(Event::Exit { unacknowledged, .. }) => {
+                    log_failures(&self.failure_log, unacknowledged).await;
+                    running -= 1;
+                }
+                Some(Event::Shutdown) => {}
+                None => break,
             }
         }
-        // TODO await exit signal from zmq workers or dynamic topology events from dashboard
-        // TODO once the zmq worker got shutdown, take the ownership of the log and pass it to the dashboard.
-        // in order to be reinserted at somepoint by admin.
         // aknowledge_shutdown
         self.launcher_tx.aknowledge_shutdown("broker".to_string());
     }
 }
+
+/// Append the dmp lines to the failure log, they should be reinserted by the admin using the importer.
+async fn log_failures(failure_log: &str, dmp_lines: Vec<String>) {
+    if dmp_lines.is_empty() {
+        return;
+    }
+    let mut text = dmp_lines.join("\n");
+    text.push('\n');
+    let appended = match OpenOptions::new().create(true).append(true).open(failure_log).await {
+        Ok(mut file) => file.write_all(text.as_bytes()).await,
+        Err(error) => Err(error),
+    };
+    match appended {
+        Ok(()) => warn!(
+            "broker: appended {} failed transactions to: {}",
+            dmp_lines.len(),
+            failure_log
+        ),
+        Err(error) => {
+            // the failure log is unavailable, so the transactions are logged instead
+            error!("broker: unable to append to: {}, error: {}", failure_log, error);
+            for dmp_line in dmp_lines {
+                error!("broker: failed transaction: {}", dmp_line);
+            }
+        }
+    }
+}
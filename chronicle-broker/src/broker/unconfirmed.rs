@@ -0,0 +1,122 @@
+use std::{
+    collections::{
+        HashMap,
+        VecDeque,
+    },
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+/// The default max number of unconfirmed transactions kept in memory.
+pub const DEFAULT_UNCONFIRMED_CAPACITY: usize = 100_000;
+/// The default seconds an unconfirmed transaction is kept in memory.
+pub const DEFAULT_UNCONFIRMED_TTL: u64 = 600;
+
+/// The recent transactions received from the trytes topic, which await a confirmation from the sn topic. A transaction
+/// is either held (only_confirmed mode) until it gets confirmed, or already stored so its confirmation only updates
+/// the milestone column. They expire after the ttl, and the oldest are evicted first once the capacity is reached.
+#[derive(Clone)]
+pub struct Unconfirmed(Arc<Mutex<Buffer>>);
+
+#[derive(Debug, PartialEq)]
+pub enum Confirmation {
+    /// The trytes of the held transaction, it should be stored with the milestone.
+    Held(String),
+    /// The transaction is stored, only its milestone column should be updated.
+    Stored,
+}
+
+struct Buffer {
+    capacity: usize,
+    ttl: Duration,
+    transactions: HashMap<String, (Instant, Confirmation)>,
+    order: VecDeque<(Instant, String)>,
+}
+
+impl Unconfirmed {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Unconfirmed(Arc::new(Mutex::new(Buffer {
+            capacity: capacity.max(1),
+            ttl,
+            transactions: HashMap::new(),
+            order: VecDeque::new(),
+        })))
+    }
+    /// Hold the unconfirmed transaction until it gets confirmed (or expired).
+    pub fn hold(&self, hash: &str, trytes: &str) {
+        self.0
+            .lock()
+            .unwrap()
+            .insert(hash, Confirmation::Held(trytes.to_string()), Instant::now());
+    }
+    /// Remember the stored transaction, so its confirmation updates the milestone column.
+    pub fn stored(&self, hash: &str) {
+        self.0
+            .lock()
+            .unwrap()
+            .insert(hash, Confirmation::Stored, Instant::now());
+    }
+    /// Take the transaction confirmed by a milestone, returns None if it's unknown (or expired).
+    pub fn confirm(&self, hash: &str) -> Option<Confirmation> {
+        self.0
+            .lock()
+            .unwrap()
+            .transactions
+            .remove(hash)
+            .map(|(_, confirmation)| confirmation)
+    }
+}
+
+impl Buffer {
+    fn insert(&mut self, hash: &str, confirmation: Confirmation, now: Instant) {
+        // drop the expired transactions, and the oldest ones if the buffer is full
+        while let Some((since, oldest)) = self.order.front() {
+            let expired = now.duration_since(*since) >= self.ttl;
+            if !expired && self.transactions.len() < self.capacity {
+                break;
+            }
+            // the hash could be confirmed or inserted again since then
+            if self
+                .transactions
+                .get(oldest)
+                .map_or(false, |(inserted, _)| inserted == since)
+            {
+                self.transactions.remove(oldest);
+            }
+            self.order.pop_front();
+        }
+        self.transactions.insert(hash.to_string(), (now, confirmation));
+        self.order.push_back((now, hash.to_string()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hold_until_confirmed_or_expired() {
+        let now = Instant::now();
+        let ttl = Duration::from_secs(10);
+        let unconfirmed = Unconfirmed::new(2, ttl);
+        let mut buffer = unconfirmed.0.lock().unwrap();
+        buffer.insert("A", Confirmation::Held("trytes".to_string()), now);
+        buffer.insert("B", Confirmation::Stored, now);
+        // the capacity is reached, so the oldest is evicted
+        buffer.insert("C", Confirmation::Stored, now + Duration::from_secs(1));
+        assert!(!buffer.transactions.contains_key("A"));
+        // B expired
+        buffer.insert("D", Confirmation::Held("trytes".to_string()), now + ttl);
+        assert!(!buffer.transactions.contains_key("B"));
+        drop(buffer);
+        assert_eq!(unconfirmed.confirm("C"), Some(Confirmation::Stored));
+        assert_eq!(unconfirmed.confirm("D"), Some(Confirmation::Held("trytes".to_string())));
+        assert_eq!(unconfirmed.confirm("A"), None);
+    }
+}
@@ -0,0 +1,285 @@
+// HTTP client of the IOTA node API, used to fetch the transactions missing in the permanode.
+use chronicle_storage::transaction::Transaction;
+use hyper::{
+    body,
+    client::HttpConnector,
+    http::uri::InvalidUri,
+    Body,
+    Client,
+    Method,
+    Request,
+    StatusCode,
+    Uri,
+};
+use serde::{
+    de::DeserializeOwned,
+    Deserialize,
+};
+use serde_json::{
+    json,
+    Value as JsonValue,
+};
+use std::{
+    collections::HashMap,
+    error::Error as StdError,
+    fmt,
+    ops::RangeInclusive,
+};
+
+// the max number of hashes requested from the node at once.
+const MAX_GET_TRYTES: usize = 100;
+
+pub struct NodeClient {
+    client: Client<HttpConnector>,
+    uri: Uri,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Uri(InvalidUri),
+    Http(hyper::Error),
+    Status(StatusCode, String),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Uri(error) => write!(f, "invalid node uri: {}", error),
+            Error::Http(error) => write!(f, "node http error: {}", error),
+            Error::Status(status, body) => write!(f, "node responded with {}: {}", status, body),
+            Error::Json(error) => write!(f, "invalid node response: {}", error),
+        }
+    }
+}
+
+impl StdError for Error {}
+
+impl From<hyper::Error> for Error {
+    fn from(error: hyper::Error) -> Self {
+        Error::Http(error)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Error::Json(error)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeInfo {
+    pub latest_milestone: String,
+    pub latest_milestone_index: u64,
+    pub latest_solid_subtangle_milestone: String,
+    pub latest_solid_subtangle_milestone_index: u64,
+}
+
+#[derive(Deserialize)]
+struct ResTrytes {
+    trytes: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ResHashes {
+    hashes: Vec<String>,
+}
+
+impl NodeClient {
+    pub fn new(url: &str) -> Result<Self, Error> {
+        Ok(NodeClient {
+            client: Client::new(),
+            uri: url.parse().map_err(Error::Uri)?,
+        })
+    }
+    async fn call<T: DeserializeOwned>(&self, command: JsonValue) -> Result<T, Error> {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(self.uri.clone())
+            .header("Content-Type", "application/json")
+            .header("X-IOTA-API-Version", "1")
+            .body(Body::from(command.to_string()))
+            .unwrap();
+        let response = self.client.request(request).await?;
+        let status = response.status();
+        let bytes = body::to_bytes(response.into_body()).await?;
+        if status.is_success() {
+            Ok(serde_json::from_slice(&bytes)?)
+        } else {
+            Err(Error::Status(status, String::from_utf8_lossy(&bytes).into_owned()))
+        }
+    }
+    pub async fn get_node_info(&self) -> Result<NodeInfo, Error> {
+        self.call(json!({ "command": "getNodeInfo" })).await
+    }
+    /// Find the hashes of the transactions with any of the addresses.
+    pub async fn find_transactions(&self, addresses: &[String]) -> Result<Vec<String>, Error> {
+        let res: ResHashes = self
+            .call(json!({ "command": "findTransactions", "addresses": addresses }))
+            .await?;
+        Ok(res.hashes)
+    }
+    /// Get the trytes of the hashes in the same order, None for the transactions unknown to the node.
+    pub async fn get_trytes(&self, hashes: &[String]) -> Result<Vec<Option<String>>, Error> {
+        let res: ResTrytes = self.call(json!({ "command": "getTrytes", "hashes": hashes })).await?;
+        // the node responds with all 9s trytes for unknown transactions
+        Ok(res
+            .trytes
+            .into_iter()
+            .map(|trytes| {
+                if trytes.bytes().all(|t| t == b'9') {
+                    None
+                } else {
+                    Some(trytes)
+                }
+            })
+            .collect())
+    }
+    /// Find the hashes of the milestones within the index range from the tail transactions of the coordinator,
+    /// the trytes are fetched page by page and only until every milestone of the range is found.
+    pub async fn milestones(
+        &self,
+        coordinator: &str,
+        range: RangeInclusive<u64>,
+    ) -> Result<HashMap<u64, String>, Error> {
+        let mut milestones = HashMap::new();
+        let count = (range.end() + 1).saturating_sub(*range.start()) as usize;
+        let hashes = self.find_transactions(&[coordinator.to_string()]).await?;
+        for page in hashes.chunks(MAX_GET_TRYTES) {
+            for (hash, trytes) in page.iter().zip(self.get_trytes(page).await?) {
+                if let Some(Ok(transaction)) = trytes.as_ref().map(|trytes| Transaction::parse(hash, trytes)) {
+                    // the milestone index is encoded in the obsolete tag of the tail transaction
+                    let index = transaction.milestone_index();
+                    if transaction.is_tail() && range.contains(&index) {
+                        milestones.insert(index, hash.clone());
+                    }
+                }
+            }
+            if milestones.len() == count {
+                break;
+            }
+        }
+        Ok(milestones)
+    }
+}
+
+/// A mock node which knows the transactions (hash -> trytes), findTransactions responds with all of them, and the
+/// first `failures` calls are answered with an internal error.
+#[cfg(test)]
+pub(crate) mod mock {
+    use super::*;
+    use hyper::{
+        service::{
+            make_service_fn,
+            service_fn,
+        },
+        Response,
+        Server,
+    };
+    use std::{
+        convert::Infallible,
+        sync::{
+            atomic::{
+                AtomicUsize,
+                Ordering,
+            },
+            Arc,
+        },
+    };
+
+    /// Serve the mock node on a random local port, and return its url.
+    pub(crate) fn mock_node(transactions: HashMap<String, String>, failures: usize) -> String {
+        let node = Arc::new((transactions, AtomicUsize::new(failures)));
+        let make_service = make_service_fn(move |_| {
+            let node = node.clone();
+            async move { Ok::<_, Infallible>(service_fn(move |request| respond(node.clone(), request))) }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        url
+    }
+
+    async fn respond(
+        node: Arc<(HashMap<String, String>, AtomicUsize)>,
+        request: Request<Body>,
+    ) -> Result<Response<Body>, Infallible> {
+        let (transactions, failures) = &*node;
+        if failures
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |failures| failures.checked_sub(1))
+            .is_ok()
+        {
+            return Ok(Response::builder()
+                .status(500)
+                .body(Body::from("mock failure"))
+                .unwrap());
+        }
+        let bytes = body::to_bytes(request.into_body()).await.unwrap();
+        let command: JsonValue = serde_json::from_slice(&bytes).unwrap();
+        let res = match command["command"].as_str().unwrap() {
+            "getNodeInfo" => json!({
+                "latestMilestone": "M".repeat(81),
+                "latestMilestoneIndex": 2,
+                "latestSolidSubtangleMilestone": "S".repeat(81),
+                "latestSolidSubtangleMilestoneIndex": 1,
+            }),
+            "getTrytes" => {
+                let trytes: Vec<String> = command["hashes"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|hash| {
+                        transactions
+                            .get(hash.as_str().unwrap())
+                            .cloned()
+                            .unwrap_or_else(|| "9".repeat(2673))
+                    })
+                    .collect();
+                json!({ "trytes": trytes })
+            }
+            "findTransactions" => json!({ "hashes": transactions.keys().collect::<Vec<_>>() }),
+            _ => {
+                return Ok(Response::builder()
+                    .status(400)
+                    .body(Body::from("unknown command"))
+                    .unwrap())
+            }
+        };
+        Ok(Response::new(Body::from(res.to_string())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        mock::mock_node,
+        *,
+    };
+    use chronicle_storage::transaction::OBSOLETE_TAG;
+
+    // the trytes of the tail transaction of milestone 1
+    fn milestone_trytes() -> String {
+        let mut trytes = "9".repeat(2673);
+        trytes.replace_range(OBSOLETE_TAG.start..OBSOLETE_TAG.start + 1, "A");
+        trytes
+    }
+
+    #[tokio::test]
+    async fn node_client_with_mock_node() {
+        let mut transactions = HashMap::new();
+        transactions.insert("A".repeat(81), milestone_trytes());
+        let client = NodeClient::new(&mock_node(transactions, 1)).unwrap();
+        // the first call fails
+        assert!(matches!(client.get_node_info().await, Err(Error::Status(..))));
+        let node_info = client.get_node_info().await.unwrap();
+        assert_eq!(node_info.latest_solid_subtangle_milestone_index, 1);
+        let trytes = client.get_trytes(&["A".repeat(81), "C".repeat(81)]).await.unwrap();
+        assert_eq!(trytes, vec![Some(milestone_trytes()), None]);
+        let hashes = client.find_transactions(&["C".repeat(81)]).await.unwrap();
+        assert_eq!(hashes, vec!["A".repeat(81)]);
+        let milestones = client.milestones(&"C".repeat(81), 1..=2).await.unwrap();
+        assert_eq!(milestones.get(&1), Some(&"A".repeat(81)));
+        assert_eq!(milestones.len(), 1);
+    }
+}
@@ -0,0 +1,541 @@
+// The cone walk shared by the solidifier and the backfill, it walks the trunk/branch references from a milestone
+// through tangle.transaction until reaching the transactions confirmed by older milestones, the stored transactions
+// are confirmed by the milestone (milestone column) and the missing ones are fetched from a node and inserted.
+use crate::{
+    client::{
+        self,
+        NodeClient,
+    },
+    curl,
+    retry,
+    tracker::TrackerTx,
+};
+use chronicle_common::actor;
+use chronicle_cql::{
+    compression::MyCompression,
+    frame::{
+        batch::BatchTypes,
+        consistency::Consistency,
+        decoder::{
+            ColumnDecoder,
+            Decoder,
+            Frame,
+        },
+        header::Header,
+        query::Query,
+        queryflags::{
+            SKIP_METADATA,
+            VALUES,
+        },
+    },
+    rows,
+};
+use chronicle_storage::{
+    ring::{
+        Ring,
+        Token,
+    },
+    stage::reporter,
+    transaction::{
+        self,
+        Transaction,
+    },
+    worker,
+};
+use log::*;
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+    },
+    error::Error,
+    ops::RangeInclusive,
+};
+use tokio::{
+    sync::mpsc,
+    time::delay_for,
+};
+
+type Sender = mpsc::UnboundedSender<Event>;
+type Receiver = mpsc::UnboundedReceiver<Event>;
+/// The default max number of transactions confirmed or inserted per walk.
+pub const DEFAULT_MAX_WALK: usize = 100_000;
+/// The hash referenced by the genesis transactions.
+pub const NULL_HASH: &str = "999999999999999999999999999999999999999999999999999999999999999999999999999999999";
+// the max number of hashes requested from the node at once.
+const MAX_GET_TRYTES: usize = 100;
+
+actor!(WalkerBuilder {
+    node: String,
+    max_walk: usize,
+    max_retries: usize,
+    batch_type: Option<BatchTypes>,
+    tracker: TrackerTx
+});
+
+impl WalkerBuilder {
+    pub fn build(self) -> Walker {
+        let (tx, rx) = mpsc::unbounded_channel::<Event>();
+        let node = self.node.unwrap();
+        Walker {
+            client: NodeClient::new(&node).expect("invalid node url"),
+            node,
+            max_walk: self.max_walk.unwrap_or(DEFAULT_MAX_WALK),
+            max_retries: self.max_retries.unwrap(),
+            batch_type: self.batch_type.unwrap_or(None),
+            tracker: self.tracker,
+            send,
+            pid: Some(Box::new(WalkerId(tx))),
+            rx,
+        }
+    }
+}
+
+pub struct Walker {
+    client: NodeClient,
+    node: String,
+    max_walk: usize,
+    max_retries: usize,
+    batch_type: Option<BatchTypes>,
+    tracker: Option<TrackerTx>,
+    send: fn(Token, reporter::Event, u32),
+    pid: Option<Box<WalkerId>>,
+    rx: Receiver,
+}
+
+#[derive(Debug)]
+pub struct WalkerId(Sender);
+
+pub enum Event {
+    Response { decoder: Decoder, pid: Box<WalkerId> },
+    Error { kind: worker::Error, pid: Box<WalkerId> },
+}
+
+/// The progress of walking the cone of a milestone.
+#[derive(Debug, Default)]
+pub struct Progress {
+    pub milestone: u64,
+    /// the walked transactions.
+    pub visited: usize,
+    /// the stored transactions which got confirmed by the milestone.
+    pub confirmed: usize,
+    /// the missing transactions fetched from the node.
+    pub fetched: usize,
+    /// the missing transactions unknown to the node (or with invalid hash).
+    pub unknown: usize,
+    /// false if the walk stopped at max_walk, the next walk of the milestone continues from where it stopped.
+    pub complete: bool,
+}
+
+impl Walker {
+    pub fn client(&self) -> &NodeClient {
+        &self.client
+    }
+
+    /// Find the milestone hashes in the range from the coordinator transactions.
+    pub async fn milestones(
+        &mut self,
+        coordinator: &str,
+        range: RangeInclusive<u64>,
+    ) -> Result<HashMap<u64, String>, client::Error> {
+        let mut attempt = 0;
+        loop {
+            match self.client.milestones(coordinator, range.clone()).await {
+                Ok(milestones) => return Ok(milestones),
+                Err(error) => {
+                    attempt += 1;
+                    self.retry_node(error, attempt).await?;
+                }
+            }
+        }
+    }
+
+    /// Walk the cone of the milestone until reaching the transactions confirmed by older milestones.
+    /// The transactions confirmed by the same milestone are walked through, so a walk stopped at max_walk is
+    /// continued by walking the milestone again.
+    pub async fn walk(&mut self, milestone_hash: String, milestone: u64) -> Result<Progress, Box<dyn Error>> {
+        let mut progress = Progress {
+            milestone,
+            complete: true,
+            ..Default::default()
+        };
+        let mut visited = HashSet::new();
+        let mut pending = vec![milestone_hash];
+        let mut missing = Vec::new();
+        while !pending.is_empty() || !missing.is_empty() {
+            while let Some(hash) = pending.pop() {
+                if hash == NULL_HASH || !visited.insert(hash.clone()) {
+                    continue;
+                }
+                if progress.confirmed + progress.fetched >= self.max_walk {
+                    progress.complete = false;
+                    warn!("milestone: {} exceeded max_walk: {}", milestone, self.max_walk);
+                    return Ok(progress);
+                }
+                progress.visited += 1;
+                match self.parents(&hash).await? {
+                    // confirmed by an older milestone, so its cone got confirmed before
+                    Some(Parents {
+                        milestone: Some(confirmed),
+                        ..
+                    }) if confirmed != milestone as i64 => {}
+                    // confirmed by a previous walk of the same milestone
+                    Some(Parents {
+                        trunk,
+                        branch,
+                        milestone: Some(_),
+                        ..
+                    }) => {
+                        pending.push(trunk);
+                        pending.push(branch);
+                    }
+                    Some(Parents { trunk, branch, .. }) => {
+                        let payload = transaction::update_milestone(&hash, milestone);
+                        self.request(transaction::token(hash.as_bytes()), payload).await?;
+                        progress.confirmed += 1;
+                        pending.push(trunk);
+                        pending.push(branch);
+                    }
+                    None => missing.push(hash),
+                }
+            }
+            // fetch the missing transactions, and continue walking from them
+            let fetch: Vec<String> = missing.drain(..missing.len().min(MAX_GET_TRYTES)).collect();
+            if fetch.is_empty() {
+                continue;
+            }
+            for (hash, trytes) in fetch.iter().zip(self.get_trytes(&fetch).await?) {
+                match trytes {
+                    Some(trytes) if curl::verify_transaction(hash, &trytes) => {
+                        let transaction = Transaction::parse(hash, &trytes)?;
+                        for (token, payload) in transaction.insert_queries(Some(milestone), self.batch_type) {
+                            self.request(token, payload).await?;
+                        }
+                        if let Some(tracker) = self.tracker.as_ref() {
+                            tracker.stored(&transaction);
+                        }
+                        progress.fetched += 1;
+                        pending.push(transaction.trunk().to_string());
+                        pending.push(transaction.branch().to_string());
+                    }
+                    _ => {
+                        progress.unknown += 1;
+                        warn!("milestone: {}, unable to fetch transaction: {}", milestone, hash);
+                    }
+                }
+            }
+        }
+        Ok(progress)
+    }
+
+    async fn get_trytes(&mut self, hashes: &[String]) -> Result<Vec<Option<String>>, client::Error> {
+        let mut attempt = 0;
+        loop {
+            match self.client.get_trytes(hashes).await {
+                Ok(trytes) => return Ok(trytes),
+                Err(error) => {
+                    attempt += 1;
+                    self.retry_node(error, attempt).await?;
+                }
+            }
+        }
+    }
+
+    /// Wait for the backoff of the nth attempt of a node call, or fail once the call exhausted max_retries.
+    async fn retry_node(&self, error: client::Error, attempt: u32) -> Result<(), client::Error> {
+        if attempt as usize > self.max_retries {
+            return Err(error);
+        }
+        warn!("node: {}, error: {}, retry: {}", self.node, error, attempt);
+        delay_for(retry::backoff(attempt)).await;
+        Ok(())
+    }
+
+    /// Lookup the parents and the milestone column of a stored transaction.
+    async fn parents(&mut self, hash: &str) -> Result<Option<Parents>, worker::Error> {
+        let decoder = self
+            .request(transaction::token(hash.as_bytes()), select_parents(hash))
+            .await?;
+        Ok(Parents::new(decoder, String::new(), String::new(), None)
+            .decode()
+            .finalize())
+    }
+
+    /// Send the request to its token owner and await the response, each request has its own retry budget of
+    /// max_retries, the retryable errors are retried with backoff using send_global_random_replica strategy.
+    async fn request(&mut self, token: i64, payload: Vec<u8>) -> Result<Decoder, worker::Error> {
+        let request = reporter::Event::Request {
+            payload: payload.clone(),
+            worker: self.pid.take().unwrap(),
+        };
+        (self.send)(token, request, 0);
+        let mut attempt = 0;
+        loop {
+            match self.rx.recv().await.unwrap() {
+                Event::Response { decoder, pid } => {
+                    self.pid.replace(pid);
+                    return Ok(decoder);
+                }
+                Event::Error { kind, pid } => {
+                    attempt += 1;
+                    if !kind.is_retryable() || attempt as usize > self.max_retries {
+                        self.pid.replace(pid);
+                        return Err(kind);
+                    }
+                    let request = reporter::Event::Request {
+                        payload: payload.clone(),
+                        worker: pid,
+                    };
+                    (self.send)(token, request, attempt);
+                }
+            }
+        }
+    }
+}
+
+/// Send the nth attempt of a request, the first attempt goes to a local replica and the retries wait for their backoff.
+fn send(token: Token, request: reporter::Event, attempt: u32) {
+    if attempt == 0 {
+        Ring::send_local_random_replica(token, request);
+    } else {
+        retry::retry(token, request, attempt);
+    }
+}
+
+impl worker::Worker for WalkerId {
+    fn send_response(self: Box<Self>, _: &Option<reporter::Sender>, giveload: Vec<u8>) {
+        let decoder = Decoder::new(giveload, MyCompression::get());
+        unsafe {
+            let raw = Box::into_raw(self);
+            let pid = Box::from_raw(raw);
+            let event;
+            if decoder.is_error() {
+                let error = decoder.get_error();
+                event = Event::Error {
+                    kind: worker::Error::Cql(error),
+                    pid,
+                }
+            } else {
+                event = Event::Response { decoder, pid };
+            }
+            let _ = (*raw).0.send(event);
+        }
+    }
+    fn send_error(self: Box<Self>, kind: worker::Error) {
+        unsafe {
+            let raw = Box::into_raw(self);
+            let pid = Box::from_raw(raw);
+            let event = Event::Error { kind, pid };
+            let _ = (*raw).0.send(event);
+        }
+    }
+}
+
+rows!(
+    rows: Parents {trunk: String, branch: String, milestone: Option<i64>},
+    row: Row(
+        Trunk,
+        Branch,
+        Milestone
+    ),
+    column_decoder: ParentsDecoder
+);
+
+trait Rows {
+    fn decode(self) -> Self;
+    fn finalize(self) -> Option<Parents>;
+}
+
+impl Rows for Parents {
+    fn decode(mut self) -> Self {
+        self.next();
+        self
+    }
+    fn finalize(self) -> Option<Parents> {
+        if self.rows_count == 1 {
+            Some(self)
+        } else {
+            None
+        }
+    }
+}
+
+impl ParentsDecoder for Trunk {
+    fn decode_column(start: usize, length: i32, acc: &mut Parents) {
+        acc.trunk = String::from_utf8(acc.buffer()[start..(start + length as usize)].to_vec()).unwrap();
+    }
+    fn handle_null(_: &mut Parents) {
+        unreachable!()
+    }
+}
+impl ParentsDecoder for Branch {
+    fn decode_column(start: usize, length: i32, acc: &mut Parents) {
+        acc.branch = String::from_utf8(acc.buffer()[start..(start + length as usize)].to_vec()).unwrap();
+    }
+    fn handle_null(_: &mut Parents) {
+        unreachable!()
+    }
+}
+impl ParentsDecoder for Milestone {
+    fn decode_column(start: usize, length: i32, acc: &mut Parents) {
+        acc.milestone = Some(i64::decode(&acc.buffer()[start..], length as usize));
+    }
+    fn handle_null(acc: &mut Parents) {
+        acc.milestone = None;
+    }
+}
+
+/// Create a query frame to lookup for the parents and the milestone of a transaction
+fn select_parents(hash: &str) -> Vec<u8> {
+    let Query(payload) = Query::new()
+        .version()
+        .flags(MyCompression::flag())
+        .stream(0)
+        .opcode()
+        .length()
+        .statement("SELECT trunk, branch, milestone FROM tangle.transaction WHERE hash = ?")
+        .consistency(Consistency::One)
+        .query_flags(SKIP_METADATA | VALUES)
+        .value_count(1)
+        .value(hash)
+        .build(MyCompression::get());
+    payload
+}
+
+/// A mock store which answers the requests of a walker on the current thread instead of the ring, it keeps the
+/// trunk, branch and milestone of the transactions like tangle.transaction.
+#[cfg(test)]
+pub(crate) mod mock {
+    use super::*;
+    use crate::curl::tests::trits_to_trytes;
+    use std::{
+        cell::RefCell,
+        convert::TryInto,
+        ops::Range,
+    };
+
+    pub(crate) type Stored = (String, String, Option<i64>);
+
+    #[derive(Default)]
+    struct Store {
+        transactions: HashMap<String, Stored>,
+        failures: usize,
+    }
+
+    thread_local! {
+        static STORE: RefCell<Store> = RefCell::new(Store::default());
+    }
+
+    /// Fill the mock store with the transactions, the first `failures` requests fail with overload.
+    pub(crate) fn fill_store(transactions: HashMap<String, Stored>, failures: usize) {
+        STORE.with(|store| store.replace(Store { transactions, failures }));
+    }
+
+    /// Send the requests of the walker to the mock store.
+    pub(crate) fn mock_store(walker: &mut Walker) {
+        walker.send = send_to_store;
+    }
+
+    /// Get the stored transaction from the mock store.
+    pub(crate) fn stored(hash: &str) -> Option<Stored> {
+        STORE.with(|store| store.borrow().transactions.get(hash).cloned())
+    }
+
+    /// Get the number of failures left in the mock store.
+    pub(crate) fn failures() -> usize {
+        STORE.with(|store| store.borrow().failures)
+    }
+
+    /// Create the trytes of a transaction with the fields (the others are 9s), and return its hash with the trytes.
+    pub(crate) fn transaction(fields: &[(Range<usize>, &str)]) -> (String, String) {
+        let mut trytes = "9".repeat(transaction::TRANSACTION_TRYTES_LENGTH);
+        for (field, value) in fields {
+            trytes.replace_range(field.start..field.start + value.len(), value);
+        }
+        (trits_to_trytes(&curl::transaction_hash(&trytes).unwrap()), trytes)
+    }
+
+    fn send_to_store(_: Token, request: reporter::Event, _: u32) {
+        if let reporter::Event::Request { worker: pid, payload } = request {
+            let giveload = STORE.with(|store| {
+                let mut store = store.borrow_mut();
+                if store.failures > 0 {
+                    store.failures -= 1;
+                    return None;
+                }
+                let (statement, values) = parse_query(&payload);
+                let text = |value: Option<&[u8]>| String::from_utf8(value.unwrap().to_vec()).unwrap();
+                let int = |value: Option<&[u8]>| value.map(|value| i64::from_be_bytes(value.try_into().unwrap()));
+                if statement.starts_with("SELECT trunk, branch, milestone") {
+                    return Some(rows_frame(store.transactions.get(&text(values[0]))));
+                }
+                if statement == transaction::UPDATE_TANGLE_TX_MILESTONE_STATMENT {
+                    if let Some(stored) = store.transactions.get_mut(&text(values[1])) {
+                        stored.2 = int(values[0]);
+                    }
+                } else if statement == transaction::INSERT_TANGLE_TX_QUERY {
+                    let stored = (text(values[9]), text(values[10]), int(values[16]));
+                    store.transactions.insert(text(values[0]), stored);
+                }
+                Some(frame(1, Vec::new()))
+            });
+            match giveload {
+                Some(giveload) => pid.send_response(&None, giveload),
+                None => pid.send_error(worker::Error::Overload),
+            }
+        }
+    }
+
+    // the statement and the values (None for null/unset) of an uncompressed query frame.
+    fn parse_query(payload: &[u8]) -> (String, Vec<Option<&[u8]>>) {
+        let int = |start: usize| i32::from_be_bytes(payload[start..start + 4].try_into().unwrap());
+        let statement_end = 13 + int(9) as usize;
+        let statement = String::from_utf8(payload[13..statement_end].to_vec()).unwrap();
+        // the consistency and the query flags precede the value count
+        let mut start = statement_end + 5;
+        let mut values = Vec::new();
+        while start < payload.len() {
+            let length = int(start);
+            start += 4;
+            if length < 0 {
+                values.push(None);
+            } else {
+                values.push(Some(&payload[start..start + length as usize]));
+                start += length as usize;
+            }
+        }
+        (statement, values)
+    }
+
+    // a result frame (opcode 0x08) of the kind with the body.
+    fn frame(kind: i32, body: Vec<u8>) -> Vec<u8> {
+        let mut frame = vec![0x84, 0, 0, 0, 0x08];
+        frame.extend(&i32::to_be_bytes(4 + body.len() as i32));
+        frame.extend(&i32::to_be_bytes(kind));
+        frame.extend(body);
+        frame
+    }
+
+    // the rows result (kind 2) of the parents query without metadata.
+    fn rows_frame(stored: Option<&Stored>) -> Vec<u8> {
+        // the no_metadata flag (0x0004) and the columns count
+        let mut body = Vec::new();
+        body.extend(&i32::to_be_bytes(4));
+        body.extend(&i32::to_be_bytes(3));
+        body.extend(&i32::to_be_bytes(stored.is_some() as i32));
+        if let Some((trunk, branch, milestone)) = stored {
+            for column in &[trunk, branch] {
+                body.extend(&i32::to_be_bytes(column.len() as i32));
+                body.extend(column.as_bytes());
+            }
+            match milestone {
+                Some(milestone) => {
+                    body.extend(&i32::to_be_bytes(8));
+                    body.extend(&i64::to_be_bytes(*milestone));
+                }
+                None => body.extend(&i32::to_be_bytes(-1)),
+            }
+        }
+        frame(2, body)
+    }
+}
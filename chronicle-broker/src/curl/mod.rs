@@ -0,0 +1,149 @@
+// Curl-P-81, the sponge used by IRI to derive the transaction hash from the transaction trytes.
+use bee_ternary::{
+    t1b1::T1B1Buf,
+    TritBuf,
+    TryteBuf,
+};
+use chronicle_storage::transaction::{
+    HASH_TRYTES_LENGTH,
+    TRANSACTION_TRYTES_LENGTH,
+};
+use std::sync::atomic::{
+    AtomicU64,
+    Ordering,
+};
+
+const HASH_LENGTH: usize = 243;
+const STATE_LENGTH: usize = 3 * HASH_LENGTH;
+const NUMBER_OF_ROUNDS: usize = 81;
+const TRUTH_TABLE: [i8; 11] = [1, 0, -1, 2, 1, -1, 0, 2, -1, 1, 0];
+
+/// The total number of transactions rejected by the hash verification (broker and importer).
+static REJECTED: AtomicU64 = AtomicU64::new(0);
+
+pub struct CurlP81 {
+    state: [i8; STATE_LENGTH],
+}
+
+impl CurlP81 {
+    pub fn new() -> Self {
+        CurlP81 {
+            state: [0; STATE_LENGTH],
+        }
+    }
+    /// Absorb the trits in chunks of HASH_LENGTH, the last chunk is allowed to be shorter.
+    pub fn absorb(&mut self, trits: &[i8]) {
+        for chunk in trits.chunks(HASH_LENGTH) {
+            self.state[..chunk.len()].copy_from_slice(chunk);
+            self.transform();
+        }
+    }
+    /// Squeeze the first HASH_LENGTH trits of the state.
+    pub fn squeeze(&mut self) -> [i8; HASH_LENGTH] {
+        let mut hash = [0; HASH_LENGTH];
+        hash.copy_from_slice(&self.state[..HASH_LENGTH]);
+        self.transform();
+        hash
+    }
+    fn transform(&mut self) {
+        let mut scratchpad = [0; STATE_LENGTH];
+        for _ in 0..NUMBER_OF_ROUNDS {
+            scratchpad.copy_from_slice(&self.state);
+            let mut index = 0;
+            for trit in self.state.iter_mut() {
+                let prev = index;
+                if index < 365 {
+                    index += 364;
+                } else {
+                    index -= 365;
+                }
+                *trit = TRUTH_TABLE[(scratchpad[prev] + (scratchpad[index] << 2) + 5) as usize];
+            }
+        }
+    }
+}
+
+impl Default for CurlP81 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Convert trytes to trits, returns None if the trytes are invalid.
+fn trytes_to_trits(trytes: &str) -> Option<Vec<i8>> {
+    let trytes = TryteBuf::try_from_str(trytes).ok()?;
+    let trit_buf: TritBuf<T1B1Buf> = trytes.as_trits().encode();
+    Some(trit_buf.iter().map(i8::from).collect())
+}
+
+/// Compute the Curl-P-81 hash (as trits) of the transaction trytes, returns None if the trytes are invalid.
+pub fn transaction_hash(txtrytes: &str) -> Option<[i8; HASH_LENGTH]> {
+    if txtrytes.len() != TRANSACTION_TRYTES_LENGTH {
+        return None;
+    }
+    let trits = trytes_to_trits(txtrytes)?;
+    let mut curl = CurlP81::new();
+    curl.absorb(&trits);
+    Some(curl.squeeze())
+}
+
+/// Verify that the hash is the Curl-P-81 hash of the transaction trytes, any rejected transaction is counted.
+pub fn verify_transaction(hash: &str, txtrytes: &str) -> bool {
+    let verified = hash.len() == HASH_TRYTES_LENGTH
+        && match (trytes_to_trits(hash), transaction_hash(txtrytes)) {
+            (Some(expected), Some(computed)) => expected[..] == computed[..],
+            _ => false,
+        };
+    if !verified {
+        REJECTED.fetch_add(1, Ordering::Relaxed);
+    }
+    verified
+}
+
+/// Get the total number of rejected transactions.
+pub fn rejected_transactions() -> u64 {
+    REJECTED.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn trits_to_trytes(trits: &[i8]) -> String {
+        const ALPHABET: &[u8] = b"9ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+        trits
+            .chunks(3)
+            .map(|t| ALPHABET[((t[0] + 3 * t[1] + 9 * t[2] + 27) % 27) as usize] as char)
+            .collect()
+    }
+
+    #[test]
+    fn verify_transaction_hash() {
+        let mut txtrytes = "9".repeat(TRANSACTION_TRYTES_LENGTH);
+        txtrytes.replace_range(..9, "CHRONICLE");
+        let hash = trits_to_trytes(&transaction_hash(&txtrytes).unwrap());
+        assert_eq!(hash.len(), HASH_TRYTES_LENGTH);
+        assert!(verify_transaction(&hash, &txtrytes));
+        // tampered trytes
+        txtrytes.replace_range(..9, "CHRONICLF");
+        assert!(!verify_transaction(&hash, &txtrytes));
+        // invalid trytes
+        txtrytes.replace_range(..9, "chronicle");
+        assert!(!verify_transaction(&hash, &txtrytes));
+        assert!(rejected_transactions() >= 2);
+    }
+
+    #[test]
+    fn reference_vector() {
+        // every tryte value cycled over the whole transaction, the hash is computed by the reference Curl-P-81
+        // implementation of the IOTA Foundation (iota-crypto, curl_p::CurlP).
+        let txtrytes: String = "9ABCDEFGHIJKLMNOPQRSTUVWXYZ"
+            .chars()
+            .cycle()
+            .take(TRANSACTION_TRYTES_LENGTH)
+            .collect();
+        let hash = "AHZJBPRUKRJJHSPELBUDHFBESKIAMBYXVRAKBXVTM9HNTEYIEXUJEBC9XSOFKEKAZWQQWQXXXCLQWMIIV";
+        assert_eq!(trits_to_trytes(&transaction_hash(&txtrytes).unwrap()), hash);
+        assert!(verify_transaction(hash, &txtrytes));
+    }
+}
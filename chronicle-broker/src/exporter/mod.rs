@@ -0,0 +1,475 @@
+// The exporter dumps the stored transactions back to a dmp file (hash,trytes,milestone lines) which the importer can
+// import again. The transaction table is scanned by token range, the ranges of the ring vnodes are scanned in parallel
+// and every range is paged, the lines of a page are appended to the file once it's received.
+// NOTE: the lines are written in token order rather than in milestone order, so an exported file doesn't fit the
+// importer features which rely on the milestone order of the dmp files: validate_only reports the lines behind a
+// previous milestone as MilestoneBehind, and attribute_unconfirmed credits an unconfirmed line to whichever confirmed
+// line precedes it. A plain import is unaffected, every line carries its own milestone.
+use crate::retry;
+use chronicle_common::actor;
+use chronicle_cql::{
+    compression::MyCompression,
+    frame::{
+        consistency::Consistency,
+        decoder::{
+            ColumnDecoder,
+            Decoder,
+            Frame,
+        },
+        header::Header,
+        query::Query,
+        queryflags::{
+            PAGE_SIZE,
+            PAGING_STATE,
+            SKIP_METADATA,
+            VALUES,
+        },
+    },
+    rows,
+};
+use chronicle_storage::{
+    ring::{
+        Ring,
+        Token,
+    },
+    stage::reporter,
+    worker,
+};
+use log::*;
+use std::error::Error;
+use tokio::{
+    fs::File,
+    io::AsyncWriteExt,
+    sync::mpsc,
+};
+
+type Sender = mpsc::UnboundedSender<Event>;
+type Receiver = mpsc::UnboundedReceiver<Event>;
+/// The default number of transactions fetched per page.
+pub const DEFAULT_PAGE_SIZE: i32 = 1000;
+/// The default number of token ranges scanned in parallel.
+pub const DEFAULT_PARALLELISM: usize = 8;
+
+actor!(ExporterBuilder {
+    path: String,
+    milestone_range: (u64, u64),
+    parallelism: usize,
+    page_size: i32,
+    max_retries: usize
+});
+
+impl ExporterBuilder {
+    pub fn build(self) -> Exporter {
+        let (tx, rx) = mpsc::unbounded_channel::<Event>();
+        Exporter {
+            path: self.path.unwrap(),
+            milestone_range: self.milestone_range,
+            parallelism: self.parallelism.unwrap_or(DEFAULT_PARALLELISM).max(1),
+            page_size: self.page_size.unwrap_or(DEFAULT_PAGE_SIZE),
+            max_retries: self.max_retries.unwrap(),
+            tx,
+            rx,
+        }
+    }
+}
+
+pub struct Exporter {
+    path: String,
+    milestone_range: Option<(u64, u64)>,
+    parallelism: usize,
+    page_size: i32,
+    max_retries: usize,
+    tx: Sender,
+    rx: Receiver,
+}
+
+/// The pid of a token range scan, with the index of the range and the paging state of its next page, the paging
+/// state is only replaced by the response, so a retry requests the same page again.
+#[derive(Debug)]
+pub struct ExporterId(Sender, usize, Option<Vec<u8>>);
+
+pub enum Event {
+    Response { decoder: Decoder, pid: Box<ExporterId> },
+    Error { kind: worker::Error, pid: Box<ExporterId> },
+}
+
+/// The summary of an export.
+#[derive(Debug, Default)]
+pub struct Report {
+    /// the scanned token ranges.
+    pub ranges: usize,
+    /// the exported transactions.
+    pub transactions: usize,
+}
+
+impl Exporter {
+    pub async fn run(mut self) -> Result<Report, Box<dyn Error>> {
+        let ranges = Ring::token_ranges();
+        if ranges.is_empty() {
+            return Err("the ring is not built yet".into());
+        }
+        let mut file = File::create(&self.path).await?;
+        let mut report = Report::default();
+        // each range has its own retry budget of max_retries
+        let mut attempts = vec![0; ranges.len()];
+        // start the first scans, the following ranges are scanned once a scan is done
+        let mut next_range = self.parallelism.min(ranges.len());
+        for range_id in 0..next_range {
+            let pid = Box::new(ExporterId(self.tx.clone(), range_id, None));
+            self.scan(ranges[range_id], pid);
+        }
+        let mut scanning = next_range;
+        while scanning > 0 {
+            match self.rx.recv().await.unwrap() {
+                Event::Response { decoder, mut pid } => {
+                    let mut page = Transactions::new(decoder, String::new(), 0).decode();
+                    pid.2 = page.take_paging_state();
+                    let (lines, count) = page.finalize();
+                    file.write_all(lines.as_bytes()).await?;
+                    report.transactions += count;
+                    if pid.2.is_some() {
+                        // fetch the next page of the range
+                        self.scan(ranges[pid.1], pid);
+                        continue;
+                    }
+                    report.ranges += 1;
+                    info!(
+                        "exporter: scanned {}/{} token ranges, exported {} transactions",
+                        report.ranges,
+                        ranges.len(),
+                        report.transactions
+                    );
+                    if next_range < ranges.len() {
+                        pid.1 = next_range;
+                        self.scan(ranges[next_range], pid);
+                        next_range += 1;
+                    } else {
+                        scanning -= 1;
+                    }
+                }
+                Event::Error { kind, pid } => {
+                    let range_id = pid.1;
+                    attempts[range_id] += 1;
+                    if attempts[range_id] > self.max_retries {
+                        return Err(format!(
+                            "failed to scan the token range: {:?}, error: {:?}",
+                            ranges[range_id], kind
+                        )
+                        .into());
+                    }
+                    self.retry(ranges[range_id], pid, attempts[range_id] as u32);
+                }
+            }
+        }
+        file.flush().await?;
+        Ok(report)
+    }
+
+    /// Request the next page of the token range from the range owner.
+    fn scan(&self, (left, right): (Token, Token), pid: Box<ExporterId>) {
+        let payload = select_transactions(left, right, self.milestone_range, self.page_size, &pid.2);
+        let request = reporter::Event::Request { payload, worker: pid };
+        Ring::send_local_random_replica(right, request);
+    }
+
+    /// Request the same page of the token range again from a random replica, once its backoff elapsed.
+    fn retry(&self, (left, right): (Token, Token), pid: Box<ExporterId>, attempt: u32) {
+        let payload = select_transactions(left, right, self.milestone_range, self.page_size, &pid.2);
+        let request = reporter::Event::Request { payload, worker: pid };
+        retry::retry(right, request, attempt);
+    }
+}
+
+impl worker::Worker for ExporterId {
+    fn send_response(self: Box<Self>, _: &Option<reporter::Sender>, giveload: Vec<u8>) {
+        let decoder = Decoder::new(giveload, MyCompression::get());
+        unsafe {
+            let raw = Box::into_raw(self);
+            let pid = Box::from_raw(raw);
+            let event;
+            if decoder.is_error() {
+                let error = decoder.get_error();
+                event = Event::Error {
+                    kind: worker::Error::Cql(error),
+                    pid,
+                }
+            } else {
+                event = Event::Response { decoder, pid };
+            }
+            let _ = (*raw).0.send(event);
+        }
+    }
+    fn send_error(self: Box<Self>, kind: worker::Error) {
+        unsafe {
+            let raw = Box::into_raw(self);
+            let pid = Box::from_raw(raw);
+            let event = Event::Error { kind, pid };
+            let _ = (*raw).0.send(event);
+        }
+    }
+}
+
+mod transactions {
+    use super::*;
+    use std::fmt::Write;
+
+    // the columns are selected in the order of the transaction trytes, so the lines are formed by concatenation.
+    rows!(
+        rows: Transactions {lines: String, count: usize},
+        row: Row(
+            Hash,
+            Payload,
+            Address,
+            Value,
+            ObsoleteTag,
+            Timestamp,
+            CurrentIndex,
+            LastIndex,
+            Bundle,
+            Trunk,
+            Branch,
+            Tag,
+            AttachmentTimestamp,
+            AttachmentTimestampLower,
+            AttachmentTimestampUpper,
+            Nonce,
+            Milestone
+        ),
+        column_decoder: TransactionsDecoder
+    );
+
+    impl Transactions {
+        pub fn decode(mut self) -> Self {
+            while let Some(_) = self.next() {}
+            self
+        }
+        /// Returns the dmp lines of the page, and the number of transactions.
+        pub fn finalize(self) -> (String, usize) {
+            (self.lines, self.count)
+        }
+        pub fn take_paging_state(&mut self) -> Option<Vec<u8>> {
+            self.metadata.take_paging_state()
+        }
+        fn push_str(&mut self, start: usize, length: i32) {
+            let column = &self.decoder.buffer_as_ref()[start..(start + length as usize)];
+            self.lines.push_str(std::str::from_utf8(column).unwrap());
+        }
+    }
+
+    impl TransactionsDecoder for Hash {
+        fn decode_column(start: usize, length: i32, acc: &mut Transactions) {
+            acc.push_str(start, length);
+            acc.lines.push(',');
+            acc.count += 1;
+        }
+        fn handle_null(_: &mut Transactions) {
+            unreachable!()
+        }
+    }
+
+    macro_rules! trytes_columns {
+        ($($column:ident),*) => {
+            $(
+                impl TransactionsDecoder for $column {
+                    fn decode_column(start: usize, length: i32, acc: &mut Transactions) {
+                        acc.push_str(start, length);
+                    }
+                    fn handle_null(_: &mut Transactions) {
+                        unreachable!()
+                    }
+                }
+            )*
+        };
+    }
+
+    trytes_columns!(
+        Payload,
+        Address,
+        Value,
+        ObsoleteTag,
+        Timestamp,
+        CurrentIndex,
+        LastIndex,
+        Bundle,
+        Trunk,
+        Branch,
+        Tag,
+        AttachmentTimestamp,
+        AttachmentTimestampLower,
+        AttachmentTimestampUpper,
+        Nonce
+    );
+
+    impl TransactionsDecoder for Milestone {
+        fn decode_column(start: usize, length: i32, acc: &mut Transactions) {
+            let milestone = i64::decode(&acc.buffer()[start..], length as usize);
+            writeln!(&mut acc.lines, ",{}", milestone).unwrap();
+        }
+        fn handle_null(acc: &mut Transactions) {
+            // the importer reads the milestone 0 as unconfirmed
+            acc.lines.push_str(",0\n");
+        }
+    }
+}
+use transactions::Transactions;
+
+/// Create a query frame to fetch a page of the transactions within the token range (left, right], confirmed within
+/// the milestone range if any.
+fn select_transactions(
+    left: Token,
+    right: Token,
+    milestone_range: Option<(u64, u64)>,
+    page_size: i32,
+    paging_state: &Option<Vec<u8>>,
+) -> Vec<u8> {
+    let query_flags = match paging_state {
+        Some(_) => SKIP_METADATA | VALUES | PAGE_SIZE | PAGING_STATE,
+        None => SKIP_METADATA | VALUES | PAGE_SIZE,
+    };
+    let query = Query::new()
+        .version()
+        .flags(MyCompression::flag())
+        .stream(0)
+        .opcode()
+        .length();
+    let query = match milestone_range {
+        Some((from, to)) => query
+            .statement(SELECT_MILESTONE_RANGE_QUERY)
+            .consistency(Consistency::One)
+            .query_flags(query_flags)
+            .value_count(4)
+            .value(left)
+            .value(right)
+            .value(from as i64)
+            .value(to as i64),
+        None => query
+            .statement(SELECT_QUERY)
+            .consistency(Consistency::One)
+            .query_flags(query_flags)
+            .value_count(2)
+            .value(left)
+            .value(right),
+    };
+    let Query(payload) = query
+        .page_size(page_size)
+        .paging_state(paging_state)
+        .build(MyCompression::get());
+    payload
+}
+
+const SELECT_QUERY: &str = r#"
+  SELECT hash, payload, address, value, obsolete_tag, timestamp, current_index, last_index, bundle, trunk, branch,
+  tag, attachment_timestamp, attachment_timestamp_lower, attachment_timestamp_upper, nonce, milestone
+  FROM tangle.transaction WHERE token(hash) > ? AND token(hash) <= ?;
+"#;
+
+const SELECT_MILESTONE_RANGE_QUERY: &str = r#"
+  SELECT hash, payload, address, value, obsolete_tag, timestamp, current_index, last_index, bundle, trunk, branch,
+  tag, attachment_timestamp, attachment_timestamp_lower, attachment_timestamp_upper, nonce, milestone
+  FROM tangle.transaction WHERE token(hash) > ? AND token(hash) <= ? AND milestone >= ? AND milestone <= ?
+  ALLOW FILTERING;
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cone::mock::transaction,
+        curl,
+        importer::line,
+    };
+    use chronicle_storage::transaction::{
+        Transaction,
+        ADDRESS,
+        ATTACHMENT_TIMESTAMP,
+        ATTACHMENT_TIMESTAMP_LOWER,
+        ATTACHMENT_TIMESTAMP_UPPER,
+        BRANCH,
+        BUNDLE,
+        CURRENT_INDEX,
+        LAST_INDEX,
+        NONCE,
+        OBSOLETE_TAG,
+        PAYLOAD,
+        TAG,
+        TIMESTAMP,
+        TRUNK,
+        VALUE,
+    };
+
+    // a rows result frame of the transactions query without metadata and without more pages.
+    fn page(rows: &[(String, String, Option<i64>)]) -> Decoder {
+        let mut body: Vec<u8> = Vec::new();
+        // the rows kind, the no_metadata flag, the columns count and the rows count
+        for int in &[2, 4, 17, rows.len() as i32] {
+            body.extend(&i32::to_be_bytes(*int));
+        }
+        let fields = [
+            PAYLOAD,
+            ADDRESS,
+            VALUE,
+            OBSOLETE_TAG,
+            TIMESTAMP,
+            CURRENT_INDEX,
+            LAST_INDEX,
+            BUNDLE,
+            TRUNK,
+            BRANCH,
+            TAG,
+            ATTACHMENT_TIMESTAMP,
+            ATTACHMENT_TIMESTAMP_LOWER,
+            ATTACHMENT_TIMESTAMP_UPPER,
+            NONCE,
+        ];
+        for (hash, trytes, milestone) in rows {
+            let columns = std::iter::once(hash.as_str()).chain(fields.iter().map(|field| &trytes[field.clone()]));
+            for column in columns {
+                body.extend(&i32::to_be_bytes(column.len() as i32));
+                body.extend(column.as_bytes());
+            }
+            match milestone {
+                Some(milestone) => {
+                    body.extend(&i32::to_be_bytes(8));
+                    body.extend(&i64::to_be_bytes(*milestone));
+                }
+                None => body.extend(&i32::to_be_bytes(-1)),
+            }
+        }
+        // the header of a result frame (opcode 0x08)
+        let mut frame = vec![0x84, 0, 0, 0, 0x08];
+        frame.extend(&i32::to_be_bytes(body.len() as i32));
+        frame.extend(body);
+        Decoder::new(frame, MyCompression::get())
+    }
+
+    #[test]
+    fn import_exported_lines() {
+        let confirmed = transaction(&[(TAG, "CONFIRMED"), (TIMESTAMP, "ABC")]);
+        let unconfirmed = transaction(&[(TAG, "UNCONFIRMED"), (VALUE, "Z")]);
+        let rows = vec![
+            (confirmed.0.clone(), confirmed.1.clone(), Some(1_234_567)),
+            (unconfirmed.0.clone(), unconfirmed.1.clone(), None),
+        ];
+        let mut page = Transactions::new(page(&rows), String::new(), 0).decode();
+        assert!(page.take_paging_state().is_none());
+        let (lines, count) = page.finalize();
+        assert_eq!(count, 2);
+        // the importer reads the lines back, the unconfirmed one with milestone 0
+        let imported: Vec<(String, String, Option<u64>)> = lines
+            .split_terminator('\n')
+            .map(|line| {
+                let line = line::split(line).unwrap();
+                assert!(Transaction::parse(line.hash, line.trytes).is_ok());
+                assert!(curl::verify_transaction(line.hash, line.trytes));
+                (line.hash.to_string(), line.trytes.to_string(), line.milestone)
+            })
+            .collect();
+        assert_eq!(
+            imported,
+            vec![
+                (confirmed.0, confirmed.1, Some(1_234_567)),
+                (unconfirmed.0, unconfirmed.1, Some(0))
+            ]
+        );
+    }
+}
@@ -0,0 +1,108 @@
+// The checkpoint of a dmp import is persisted next to the dmp file, so a restarted import resumes from the last line
+// whose queries got acknowledged.
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use std::{
+    error::Error,
+    io::SeekFrom,
+};
+use tokio::{
+    fs::{
+        self,
+        File,
+    },
+    io::AsyncReadExt,
+};
+
+/// The default seconds between two persisted checkpoints.
+pub const DEFAULT_CHECKPOINT_INTERVAL: u64 = 10;
+// the number of bytes at the start of the dmp file covered by the checksum (in addition to the file length).
+const CHECKSUM_BYTES: u64 = 1 << 20;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// The path of the dmp file.
+    pub filepath: String,
+    /// The offset right after the last acknowledged line.
+    pub offset: u64,
+    /// The milestone of the last acknowledged line, the following lines without a milestone inherit it.
+    pub milestone: u64,
+    /// The checksum of the dmp file, a checkpoint of a replaced file is ignored.
+    pub checksum: u64,
+}
+
+impl Checkpoint {
+    /// The path of the checkpoint of a dmp file.
+    pub fn path(filepath: &str) -> String {
+        format!("{}.checkpoint", filepath)
+    }
+    /// Load the checkpoint of the dmp file, returns None if it's absent, unreadable or doesn't match the file.
+    pub async fn load(filepath: &str, checksum: u64) -> Option<Self> {
+        let text = fs::read_to_string(Self::path(filepath)).await.ok()?;
+        let checkpoint: Checkpoint = serde_json::from_str(&text).ok()?;
+        if checkpoint.filepath == filepath && checkpoint.checksum == checksum {
+            Some(checkpoint)
+        } else {
+            None
+        }
+    }
+    /// Persist the checkpoint, it's written to a temporary file first so a crash never leaves a partial checkpoint.
+    pub async fn save(&self) -> Result<(), Box<dyn Error>> {
+        let path = Self::path(&self.filepath);
+        let tmp_path = format!("{}.tmp", path);
+        fs::write(&tmp_path, serde_json::to_string(self)?).await?;
+        fs::rename(&tmp_path, &path).await?;
+        Ok(())
+    }
+}
+
+/// Compute the checksum of the dmp file from its length and its first bytes, rather than the whole (multi GB) file.
+/// NOTE: it leaves the file cursor at the start of the file.
+pub async fn checksum(file: &mut File, len: u64) -> Result<u64, Box<dyn Error>> {
+    let mut head = Vec::with_capacity(CHECKSUM_BYTES.min(len) as usize);
+    (&mut *file).take(CHECKSUM_BYTES).read_to_end(&mut head).await?;
+    file.seek(SeekFrom::Start(0)).await?;
+    Ok(fnv1a(&len.to_be_bytes(), &head))
+}
+
+// the 64-bit FNV-1a hash of the length and the head of the file.
+fn fnv1a(len: &[u8], head: &[u8]) -> u64 {
+    len.iter().chain(head).fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn resume_from_checkpoint() {
+        let filepath = std::env::temp_dir()
+            .join(format!("chronicle-checkpoint-{}.dmp", std::process::id()))
+            .to_str()
+            .unwrap()
+            .to_string();
+        fs::write(&filepath, "line 1\nline 2\n").await.unwrap();
+        let mut file = File::open(&filepath).await.unwrap();
+        let fingerprint = checksum(&mut file, 14).await.unwrap();
+        assert_eq!(Checkpoint::load(&filepath, fingerprint).await, None);
+        let checkpoint = Checkpoint {
+            filepath: filepath.clone(),
+            offset: 7,
+            milestone: 42,
+            checksum: fingerprint,
+        };
+        checkpoint.save().await.unwrap();
+        assert_eq!(Checkpoint::load(&filepath, fingerprint).await, Some(checkpoint));
+        // the checkpoint of a replaced file is ignored
+        fs::write(&filepath, "line 3\nline 4\n").await.unwrap();
+        let mut file = File::open(&filepath).await.unwrap();
+        let fingerprint = checksum(&mut file, 14).await.unwrap();
+        assert_eq!(Checkpoint::load(&filepath, fingerprint).await, None);
+        fs::remove_file(Checkpoint::path(&filepath)).await.unwrap();
+        fs::remove_file(&filepath).await.unwrap();
+    }
+}
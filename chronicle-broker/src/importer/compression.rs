@@ -0,0 +1,137 @@
+// The dmp files can be imported as distributed by the archive (gzip, zstd or xz compressed), they're decompressed on
+// the fly while the read (compressed) bytes are counted for the progress bar.
+use async_compression::tokio_02::bufread::{
+    GzipDecoder,
+    XzDecoder,
+    ZstdDecoder,
+};
+use std::{
+    error::Error,
+    io::{
+        self,
+        SeekFrom,
+    },
+    pin::Pin,
+    sync::{
+        atomic::{
+            AtomicU64,
+            Ordering,
+        },
+        Arc,
+    },
+    task::{
+        Context,
+        Poll,
+    },
+};
+use tokio::{
+    fs::File,
+    io::{
+        AsyncBufRead,
+        AsyncRead,
+        AsyncReadExt,
+        BufReader,
+    },
+};
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const XZ_MAGIC: &[u8] = &[0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+impl Compression {
+    /// Detect the compression of the dmp file by its magic bytes, or by its extension if the file is too short.
+    pub fn detect(filepath: &str, magic: &[u8]) -> Self {
+        if magic.starts_with(GZIP_MAGIC) {
+            Compression::Gzip
+        } else if magic.starts_with(ZSTD_MAGIC) {
+            Compression::Zstd
+        } else if magic.starts_with(XZ_MAGIC) {
+            Compression::Xz
+        } else if magic.len() >= XZ_MAGIC.len() {
+            Compression::None
+        } else if filepath.ends_with(".gz") {
+            Compression::Gzip
+        } else if filepath.ends_with(".zst") {
+            Compression::Zstd
+        } else if filepath.ends_with(".xz") {
+            Compression::Xz
+        } else {
+            Compression::None
+        }
+    }
+    /// Read the magic bytes of the dmp file to detect its compression.
+    /// NOTE: it leaves the file cursor at the start of the file.
+    pub async fn of(filepath: &str, file: &mut File) -> Result<Self, Box<dyn Error>> {
+        let mut magic = Vec::with_capacity(XZ_MAGIC.len());
+        (&mut *file).take(XZ_MAGIC.len() as u64).read_to_end(&mut magic).await?;
+        file.seek(SeekFrom::Start(0)).await?;
+        Ok(Self::detect(filepath, &magic))
+    }
+    /// Wrap the file into a line reader of its decompressed content, the read bytes of the file are added to
+    /// read_bytes.
+    pub fn reader(self, file: File, read_bytes: Arc<AtomicU64>) -> Box<dyn AsyncBufRead + Unpin + Send> {
+        let file = BufReader::new(ReadBytes {
+            inner: file,
+            read_bytes,
+        });
+        match self {
+            Compression::None => Box::new(file),
+            Compression::Gzip => {
+                let mut decoder = GzipDecoder::new(file);
+                // the concatenated gzip files are valid gzip files as well
+                decoder.multiple_members(true);
+                Box::new(BufReader::new(decoder))
+            }
+            Compression::Zstd => Box::new(BufReader::new(ZstdDecoder::new(file))),
+            Compression::Xz => Box::new(BufReader::new(XzDecoder::new(file))),
+        }
+    }
+}
+
+// a reader which counts the bytes read from its inner reader.
+struct ReadBytes<R> {
+    inner: R,
+    read_bytes: Arc<AtomicU64>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ReadBytes<R> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(read)) = poll {
+            this.read_bytes.fetch_add(read as u64, Ordering::Relaxed);
+        }
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_compression() {
+        assert_eq!(
+            Compression::detect("a.dmp", &[0x1f, 0x8b, 8, 0, 0, 0]),
+            Compression::Gzip
+        );
+        assert_eq!(
+            Compression::detect("a.dmp", &[0x28, 0xb5, 0x2f, 0xfd, 0, 0]),
+            Compression::Zstd
+        );
+        assert_eq!(Compression::detect("a.dmp", XZ_MAGIC), Compression::Xz);
+        // the magic bytes win over the extension
+        assert_eq!(Compression::detect("a.dmp.gz", b"HASH9,"), Compression::None);
+        // the extension is used for the (too) short files
+        assert_eq!(Compression::detect("a.dmp.zst", b""), Compression::Zstd);
+        assert_eq!(Compression::detect("a.dmp", b""), Compression::None);
+    }
+}
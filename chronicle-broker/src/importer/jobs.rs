@@ -0,0 +1,465 @@
+// The dmp imports run as jobs of a queue, so they can be queued, paused, resumed and cancelled at runtime through the
+// storage dashboard. The job list, with the progress of each import, is reported to the dashboard(s) through the
+// launcher as the importer metrics.
+use super::ImporterBuilder;
+use chronicle_common::{
+    actor,
+    traits::{
+        importer::ImporterTx,
+        launcher::LauncherTx,
+    },
+};
+use log::*;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{
+            AtomicU64,
+            Ordering,
+        },
+        Arc,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+use tokio::sync::{
+    mpsc,
+    oneshot,
+    watch,
+};
+
+type Receiver = mpsc::UnboundedReceiver<Event>;
+type JobId = usize;
+/// The default interval (in seconds) to report the jobs to the launcher/dashboard.
+pub const DEFAULT_REPORT_INTERVAL: u64 = 5;
+// the max number of finished jobs kept in the job list, the oldest ones are pruned.
+const MAX_FINISHED_JOBS: usize = 100;
+
+/// Create the importer of a dmp file (filepath, milestone) with the import settings.
+pub type ImporterFactory = Box<dyn Fn(String, u64) -> ImporterBuilder + Send>;
+
+/// The command followed by a running importer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Control {
+    Run,
+    /// Stop reading the dmp file once the transactions in flight are acknowledged.
+    Pause,
+    /// Stop the import once the transactions in flight are acknowledged, its checkpoint is kept.
+    Cancel,
+}
+
+/// The progress of an import, updated by its importer.
+#[derive(Debug, Default)]
+pub struct Progress {
+    pub total_bytes: AtomicU64,
+    /// the read bytes of the dmp file, compressed bytes if it's compressed.
+    pub bytes: AtomicU64,
+    /// the stored transactions.
+    pub transactions: AtomicU64,
+    pub rejected: AtomicU64,
+    /// the transactions rejected once their retries got exhausted.
+    pub exhausted: AtomicU64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum Status {
+    Queued,
+    Running,
+    Paused,
+    Cancelled,
+    Done,
+    Failed(String),
+}
+
+pub enum Event {
+    Import {
+        filepath: String,
+        milestone: u64,
+        /// Import the file even if the importers are configured to validate only.
+        force_import: bool,
+        done: Option<oneshot::Sender<Status>>,
+    },
+    Pause(JobId),
+    Resume(JobId),
+    Cancel(JobId),
+    Report,
+    Finished(JobId, Result<(), String>),
+}
+
+#[derive(Clone)]
+pub struct Sender(mpsc::UnboundedSender<Event>);
+
+impl Sender {
+    /// Queue the import of the dmp file, the returned receiver resolves to the final status of the job.
+    pub fn queue(&self, filepath: String, milestone: u64) -> oneshot::Receiver<Status> {
+        let (done, status) = oneshot::channel();
+        let _ = self.0.send(Event::Import {
+            filepath,
+            milestone,
+            force_import: false,
+            done: Some(done),
+        });
+        status
+    }
+    /// Queue the import of the dmp file like queue, but it's imported even if the importers are configured to
+    /// validate only (ie the watcher, which records and moves the file once it's processed).
+    pub fn queue_import(&self, filepath: String, milestone: u64) -> oneshot::Receiver<Status> {
+        let (done, status) = oneshot::channel();
+        let _ = self.0.send(Event::Import {
+            filepath,
+            milestone,
+            force_import: true,
+            done: Some(done),
+        });
+        status
+    }
+}
+
+impl ImporterTx for Sender {
+    fn import(&mut self, filepath: String, milestone: u64) {
+        let _ = self.0.send(Event::Import {
+            filepath,
+            milestone,
+            force_import: false,
+            done: None,
+        });
+    }
+    fn pause(&mut self, job_id: usize) {
+        let _ = self.0.send(Event::Pause(job_id));
+    }
+    fn resume(&mut self, job_id: usize) {
+        let _ = self.0.send(Event::Resume(job_id));
+    }
+    fn cancel(&mut self, job_id: usize) {
+        let _ = self.0.send(Event::Cancel(job_id));
+    }
+    fn jobs(&mut self) {
+        let _ = self.0.send(Event::Report);
+    }
+}
+
+actor!(JobsBuilder {
+    importer: ImporterFactory,
+    parallel_files: usize,
+    report_interval: u64
+});
+
+impl JobsBuilder {
+    pub fn build(self) -> Jobs {
+        let (tx, rx) = mpsc::unbounded_channel::<Event>();
+        Jobs {
+            importer: self.importer.unwrap(),
+            parallel_files: self.parallel_files.unwrap_or(1).max(1),
+            report_interval: Duration::from_secs(self.report_interval.unwrap_or(DEFAULT_REPORT_INTERVAL)),
+            jobs: BTreeMap::new(),
+            next_id: 0,
+            tx: Sender(tx),
+            rx,
+        }
+    }
+}
+
+pub struct Jobs {
+    importer: ImporterFactory,
+    parallel_files: usize,
+    report_interval: Duration,
+    /// The jobs by id, the ids are never reused, so the pruned jobs don't shift the others.
+    jobs: BTreeMap<JobId, Job>,
+    next_id: JobId,
+    tx: Sender,
+    rx: Receiver,
+}
+
+struct Job {
+    filepath: String,
+    milestone: u64,
+    force_import: bool,
+    status: Status,
+    progress: Arc<Progress>,
+    /// The control of the importer, it's only set while the importer is running (or paused).
+    control: Option<watch::Sender<Control>>,
+    done: Option<oneshot::Sender<Status>>,
+    /// The time, read bytes and stored transactions at the previous rates update.
+    last: (Instant, u64, u64),
+    /// The read bytes and stored transactions per second.
+    rates: (f64, f64),
+}
+
+/// A job as reported to the dashboard.
+#[derive(Serialize)]
+struct JobReport<'a> {
+    id: JobId,
+    filepath: &'a str,
+    milestone: u64,
+    status: &'a Status,
+    bytes: u64,
+    total_bytes: u64,
+    transactions: u64,
+    transactions_per_second: f64,
+    /// the estimated seconds left, if the job is running.
+    eta: Option<u64>,
+    rejected: u64,
+    exhausted: u64,
+}
+
+impl Jobs {
+    pub async fn run(mut self, mut launcher_tx: Box<dyn LauncherTx>) {
+        let mut interval = tokio::time::interval(self.report_interval);
+        loop {
+            tokio::select! {
+                event = self.rx.recv() => {
+                    // the jobs hold a sender, so the channel is never closed
+                    self.handle(event.unwrap());
+                }
+                _ = interval.tick() => self.update_rates(),
+            }
+            self.start_queued();
+            launcher_tx.app_metrics("importer".to_string(), self.render());
+        }
+    }
+    pub fn clone_tx(&self) -> Sender {
+        self.tx.clone()
+    }
+    fn handle(&mut self, event: Event) {
+        match event {
+            Event::Import {
+                filepath,
+                milestone,
+                force_import,
+                done,
+            } => {
+                info!("importer: queued job: {}, {}", self.next_id, filepath);
+                self.jobs.insert(
+                    self.next_id,
+                    Job {
+                        filepath,
+                        milestone,
+                        force_import,
+                        status: Status::Queued,
+                        progress: Arc::new(Progress::default()),
+                        control: None,
+                        done,
+                        last: (Instant::now(), 0, 0),
+                        rates: (0.0, 0.0),
+                    },
+                );
+                self.next_id += 1;
+            }
+            Event::Pause(id) => {
+                if let Some(job) = self.job(id) {
+                    match job.status {
+                        Status::Running => {
+                            job.command(Control::Pause);
+                            job.status = Status::Paused;
+                        }
+                        Status::Queued => job.status = Status::Paused,
+                        _ => warn!("importer: unable to pause job: {}, status: {:?}", id, job.status),
+                    }
+                }
+            }
+            Event::Resume(id) => {
+                if let Some(job) = self.job(id) {
+                    match job.status {
+                        Status::Paused if job.control.is_some() => {
+                            job.command(Control::Run);
+                            job.status = Status::Running;
+                        }
+                        Status::Paused => job.status = Status::Queued,
+                        _ => warn!("importer: unable to resume job: {}, status: {:?}", id, job.status),
+                    }
+                }
+            }
+            Event::Cancel(id) => {
+                if let Some(job) = self.job(id) {
+                    match job.status {
+                        // the running importer is done once its transactions in flight are acknowledged
+                        Status::Running | Status::Paused if job.control.is_some() => {
+                            job.command(Control::Cancel);
+                            job.status = Status::Cancelled;
+                        }
+                        Status::Queued | Status::Paused => job.finish(Status::Cancelled),
+                        _ => warn!("importer: unable to cancel job: {}, status: {:?}", id, job.status),
+                    }
+                }
+            }
+            Event::Report => {}
+            Event::Finished(id, result) => {
+                // a running job is never pruned
+                let job = self.jobs.get_mut(&id).unwrap();
+                job.control = None;
+                let status = match result {
+                    Ok(()) if job.status == Status::Cancelled => Status::Cancelled,
+                    Ok(()) => Status::Done,
+                    Err(error) => {
+                        error!("importer: failed to import: {}, error: {}", job.filepath, error);
+                        Status::Failed(error)
+                    }
+                };
+                job.finish(status);
+            }
+        }
+        self.prune();
+    }
+    fn job(&mut self, id: JobId) -> Option<&mut Job> {
+        let job = self.jobs.get_mut(&id);
+        if job.is_none() {
+            warn!("importer: unknown job: {}", id);
+        }
+        job
+    }
+    // drop the oldest finished jobs beyond MAX_FINISHED_JOBS, so the job list doesn't grow with every import.
+    fn prune(&mut self) {
+        let finished: Vec<JobId> = self
+            .jobs
+            .iter()
+            .filter(|(_, job)| job.is_finished())
+            .map(|(id, _)| *id)
+            .collect();
+        for id in finished.iter().take(finished.len().saturating_sub(MAX_FINISHED_JOBS)) {
+            self.jobs.remove(id);
+        }
+    }
+    // start the queued jobs in order while fewer than parallel_files importers are running (or paused).
+    fn start_queued(&mut self) {
+        let mut running = self.jobs.values().filter(|job| job.control.is_some()).count();
+        for (&id, job) in self.jobs.iter_mut() {
+            if running >= self.parallel_files {
+                break;
+            }
+            if job.status != Status::Queued {
+                continue;
+            }
+            let (control_tx, control_rx) = watch::channel(Control::Run);
+            let mut importer = (self.importer)(job.filepath.clone(), job.milestone);
+            if job.force_import {
+                importer = importer.validate_only(false);
+            }
+            let importer = importer.progress(job.progress.clone()).control(control_rx).build();
+            let tx = self.tx.0.clone();
+            tokio::spawn(async move {
+                let result = importer.run().await.map_err(|error| error.to_string());
+                let _ = tx.send(Event::Finished(id, result));
+            });
+            info!("importer: started job: {}, {}", id, job.filepath);
+            job.control.replace(control_tx);
+            job.status = Status::Running;
+            job.last = (Instant::now(), 0, 0);
+            running += 1;
+        }
+    }
+    fn update_rates(&mut self) {
+        for job in self.jobs.values_mut().filter(|job| job.status == Status::Running) {
+            let (bytes, transactions) = (
+                job.progress.bytes.load(Ordering::Relaxed),
+                job.progress.transactions.load(Ordering::Relaxed),
+            );
+            let (last_time, last_bytes, last_transactions) = job.last;
+            let elapsed = last_time.elapsed().as_secs_f64();
+            // the first bytes of a resumed import are skipped rather than read
+            if last_bytes != 0 && elapsed > 0.0 {
+                job.rates = (
+                    bytes.saturating_sub(last_bytes) as f64 / elapsed,
+                    transactions.saturating_sub(last_transactions) as f64 / elapsed,
+                );
+            }
+            job.last = (Instant::now(), bytes, transactions);
+        }
+    }
+    fn render(&self) -> String {
+        let reports: Vec<JobReport> = self
+            .jobs
+            .iter()
+            .map(|(&id, job)| {
+                let bytes = job.progress.bytes.load(Ordering::Relaxed);
+                let total_bytes = job.progress.total_bytes.load(Ordering::Relaxed);
+                let eta = match job.status {
+                    Status::Running if job.rates.0 > 0.0 => {
+                        Some((total_bytes.saturating_sub(bytes) as f64 / job.rates.0) as u64)
+                    }
+                    _ => None,
+                };
+                JobReport {
+                    id,
+                    filepath: &job.filepath,
+                    milestone: job.milestone,
+                    status: &job.status,
+                    bytes,
+                    total_bytes,
+                    transactions: job.progress.transactions.load(Ordering::Relaxed),
+                    transactions_per_second: job.rates.1,
+                    eta,
+                    rejected: job.progress.rejected.load(Ordering::Relaxed),
+                    exhausted: job.progress.exhausted.load(Ordering::Relaxed),
+                }
+            })
+            .collect();
+        serde_json::to_string(&reports).unwrap()
+    }
+}
+
+impl Job {
+    fn is_finished(&self) -> bool {
+        self.control.is_none() && matches!(self.status, Status::Cancelled | Status::Done | Status::Failed(_))
+    }
+    fn command(&mut self, control: Control) {
+        let _ = self.control.as_ref().unwrap().broadcast(control);
+    }
+    fn finish(&mut self, status: Status) {
+        info!("importer: {}: {:?}", self.filepath, status);
+        if let Some(done) = self.done.take() {
+            let _ = done.send(status.clone());
+        }
+        self.status = status;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn control_queued_jobs() {
+        let mut jobs = JobsBuilder::new()
+            .importer(Box::new(|filepath, milestone| {
+                ImporterBuilder::new().filepath(filepath).milestone(milestone)
+            }))
+            .build();
+        let mut done = jobs.clone_tx().queue("a.dmp".to_string(), 1);
+        let event = jobs.rx.try_recv().unwrap();
+        jobs.handle(event);
+        assert_eq!(jobs.jobs[&0].status, Status::Queued);
+        // a queued job is paused and resumed without running it
+        jobs.handle(Event::Pause(0));
+        assert_eq!(jobs.jobs[&0].status, Status::Paused);
+        jobs.handle(Event::Resume(0));
+        assert_eq!(jobs.jobs[&0].status, Status::Queued);
+        jobs.handle(Event::Cancel(0));
+        assert_eq!(done.try_recv(), Ok(Status::Cancelled));
+        // a finished job can't be resumed, and an unknown job is ignored
+        jobs.handle(Event::Resume(0));
+        jobs.handle(Event::Cancel(1));
+        assert_eq!(jobs.jobs[&0].status, Status::Cancelled);
+        assert!(jobs
+            .render()
+            .starts_with(r#"[{"id":0,"filepath":"a.dmp","milestone":1,"status":"Cancelled""#));
+        // the watcher imports its files even if the importers are configured to validate only
+        let _done = jobs.clone_tx().queue_import("b.dmp".to_string(), 2);
+        let event = jobs.rx.try_recv().unwrap();
+        jobs.handle(event);
+        assert!(!jobs.jobs[&0].force_import && jobs.jobs[&1].force_import);
+        // the oldest finished jobs are pruned, the ids of the kept jobs don't change
+        for _ in 0..MAX_FINISHED_JOBS {
+            let _done = jobs.clone_tx().queue("c.dmp".to_string(), 3);
+            let event = jobs.rx.try_recv().unwrap();
+            jobs.handle(event);
+            jobs.handle(Event::Cancel(jobs.next_id - 1));
+        }
+        assert_eq!(jobs.jobs.len(), MAX_FINISHED_JOBS + 1);
+        assert!(!jobs.jobs.contains_key(&0));
+        assert_eq!(jobs.jobs[&1].status, Status::Queued);
+        assert_eq!(jobs.jobs.keys().last(), Some(&(MAX_FINISHED_JOBS + 1)));
+    }
+}
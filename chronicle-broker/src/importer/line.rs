@@ -0,0 +1,91 @@
+// A dmp line is `hash,trytes[,milestone]`, it's split without any fixed slicing so a malformed line is rejected
+// rather than panicking the importer.
+use chronicle_storage::transaction;
+use std::fmt;
+
+/// The fields of a dmp line, the hash and trytes are checked by the transaction parser.
+pub struct Line<'a> {
+    pub hash: &'a str,
+    pub trytes: &'a str,
+    pub milestone: Option<u64>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LineError {
+    MissingTrytes,
+    InvalidMilestone(String),
+    /// The milestone of the line is ahead of the milestone of the dmp file.
+    MilestoneAhead(u64),
+    /// The milestone of the line is behind the milestone of a previous line.
+    MilestoneBehind {
+        milestone: u64,
+        previous: u64,
+    },
+    Transaction(transaction::Error),
+    InvalidHash,
+}
+
+impl fmt::Display for LineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LineError::MissingTrytes => write!(f, "missing transaction trytes"),
+            LineError::InvalidMilestone(milestone) => write!(f, "invalid milestone: {:?}", milestone),
+            LineError::MilestoneAhead(milestone) => {
+                write!(f, "milestone: {} is ahead of the milestone of the file", milestone)
+            }
+            LineError::MilestoneBehind { milestone, previous } => {
+                write!(
+                    f,
+                    "milestone: {} is behind the milestone: {} of a previous line",
+                    milestone, previous
+                )
+            }
+            LineError::Transaction(error) => write!(f, "{}", error),
+            LineError::InvalidHash => write!(f, "invalid hash"),
+        }
+    }
+}
+
+/// Split the dmp line into its fields.
+pub fn split(line: &str) -> Result<Line<'_>, LineError> {
+    let mut fields = line.trim_end_matches(|c| c == '\n' || c == '\r').splitn(3, ',');
+    // splitn always yields a first field
+    let hash = fields.next().unwrap();
+    let trytes = fields.next().ok_or(LineError::MissingTrytes)?;
+    let milestone = match fields.next() {
+        Some(milestone) => Some(
+            milestone
+                .parse::<u64>()
+                .map_err(|_| LineError::InvalidMilestone(milestone.to_string()))?,
+        ),
+        None => None,
+    };
+    Ok(Line {
+        hash,
+        trytes,
+        milestone,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_malformed_lines() {
+        let line = split("HASH,TRYTES,42\n").unwrap();
+        assert_eq!((line.hash, line.trytes, line.milestone), ("HASH", "TRYTES", Some(42)));
+        assert_eq!(split("HASH,TRYTES\r\n").unwrap().milestone, None);
+        assert_eq!(split("HASH\n").err(), Some(LineError::MissingTrytes));
+        assert_eq!(
+            split("HASH,TRYTES,4é\n").err(),
+            Some(LineError::InvalidMilestone("4é".to_string()))
+        );
+        // the fields are checked by the transaction parser, a short line doesn't panic
+        let line = split("é,TRYTES").unwrap();
+        assert_eq!(
+            transaction::Transaction::parse(line.hash, line.trytes).err(),
+            Some(transaction::Error::HashLength(2))
+        );
+    }
+}
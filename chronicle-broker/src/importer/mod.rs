@@ -1,90 +1,126 @@
-// TODO compute token to enable shard_awareness.
-use bee_ternary::{
-    t1b1::T1B1Buf,
-    TritBuf,
-    TryteBuf,
+pub mod checkpoint;
+mod compression;
+pub mod jobs;
+pub(crate) mod line;
+mod range;
+pub mod watcher;
+
+use crate::{
+    curl,
+    retry,
+    selective::{
+        self,
+        Decision,
+        SelectiveRules,
+        Selector,
+    },
 };
+use checkpoint::Checkpoint;
 use chronicle_common::actor;
-use chronicle_cql::frame::encoder::{
-    ColumnEncoder,
-    UNSET_VALUE,
-};
 use chronicle_storage::{
     ring::Ring,
     stage::reporter,
+    transaction::Transaction,
     worker,
 };
-use chrono::{
-    Datelike,
-    NaiveDateTime,
-};
+use compression::Compression;
 use indicatif::{
     ProgressBar,
+    ProgressDrawTarget,
     ProgressStyle,
 };
+use jobs::{
+    Control,
+    Progress,
+};
+use line::LineError;
+use log::*;
+use range::MilestoneRange;
 use std::{
-    convert::TryFrom,
+    collections::BTreeMap,
     error::Error,
+    io::SeekFrom,
+    sync::{
+        atomic::{
+            AtomicU64,
+            Ordering,
+        },
+        Arc,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
 };
 use tokio::{
-    fs::File,
-    sync::mpsc,
+    fs::{
+        File,
+        OpenOptions,
+    },
+    sync::{
+        mpsc,
+        watch,
+    },
 };
 
 use chronicle_cql::{
     compression::MyCompression,
     frame::{
-        consistency::Consistency,
+        batch::BatchTypes,
         decoder::{
             Decoder,
             Frame,
         },
-        header::Header,
-        query::Query,
-        queryflags::{
-            SKIP_METADATA,
-            VALUES,
-        },
     },
 };
 
 use tokio::io::{
+    self as tokio_io,
+    AsyncBufRead,
     AsyncBufReadExt,
-    BufReader,
+    AsyncReadExt,
+    AsyncWriteExt,
 };
-const BE_3_BYTES_LENGTH: [u8; 4] = [0, 0, 0, 3];
 type Sender = mpsc::UnboundedSender<Event>;
 type Receiver = mpsc::UnboundedReceiver<Event>;
-pub struct YearMonth(u16, u8);
-impl YearMonth {
-    pub fn new(year: u16, month: u8) -> Self {
-        YearMonth(year, month)
-    }
-}
-impl chronicle_cql::frame::encoder::ColumnEncoder for YearMonth {
-    fn encode(&self, buffer: &mut Vec<u8>) {
-        buffer.extend(&BE_3_BYTES_LENGTH);
-        buffer.extend(&u16::to_be_bytes(self.0));
-        buffer.push(self.1);
-    }
-}
+type TransactionId = usize;
+/// The default max number of transactions in flight, whose queries are sent but not fully acknowledged yet.
+pub const DEFAULT_MAX_IN_FLIGHT: usize = 1000;
+// the max number of bad lines listed in the validation report, the rest are only counted.
+const MAX_REPORTED_LINES: usize = 10_000;
 
 #[derive(Debug)]
-pub struct ImporterId(Sender, u8);
+pub struct ImporterId(Sender, TransactionId, u8);
 impl ImporterId {
-    fn query_id(mut self: Box<Self>, query_id: u8) -> Box<Self> {
-        self.1 = query_id;
+    fn query_id(mut self: Box<Self>, id: TransactionId, query_id: u8) -> Box<Self> {
+        self.1 = id;
+        self.2 = query_id;
         self
     }
-    fn get_query_id(&self) -> u8 {
+    fn get_transaction_id(&self) -> TransactionId {
         self.1
     }
+    fn get_query_id(&self) -> u8 {
+        self.2
+    }
 }
 actor!(ImporterBuilder {
     filepath: String,
     milestone: u64,
     only_confirmed: bool,
-    max_retries: usize
+    verify_hash: bool,
+    selective: SelectiveRules,
+    batch_type: BatchTypes,
+    max_retries: usize,
+    checkpoint_interval: u64,
+    max_in_flight: usize,
+    show_progress: bool,
+    validate_only: bool,
+    progress: Arc<Progress>,
+    control: watch::Receiver<Control>,
+    from_milestone: u64,
+    to_milestone: u64,
+    attribute_unconfirmed: bool
 });
 
 pub enum Event {
@@ -95,35 +131,115 @@ pub enum Event {
 impl ImporterBuilder {
     pub fn build(self) -> Importer {
         let (tx, rx) = mpsc::unbounded_channel::<Event>();
-        let mut pids = Vec::new();
-        // create 7 pids in advance to enable us to send 6 concurrent queries without the cost for heap-reallocation
-        for _ in 0..7 {
-            pids.push(Box::new(ImporterId(tx.clone(), 0)));
+        let max_in_flight = self.max_in_flight.unwrap_or(DEFAULT_MAX_IN_FLIGHT).max(1);
+        // create pids in advance for the whole window, each transaction needs at most 8 pids
+        let mut pids = Vec::with_capacity(8 * max_in_flight);
+        for _ in 0..(8 * max_in_flight) {
+            pids.push(Box::new(ImporterId(tx.clone(), 0, 0)));
         }
+        let milestone_range = match (self.from_milestone, self.to_milestone) {
+            (None, None) => None,
+            (from, to) => Some(MilestoneRange {
+                from: from.unwrap_or(0),
+                to: to.unwrap_or(u64::MAX),
+                attribute_unconfirmed: self.attribute_unconfirmed.unwrap_or(false),
+            }),
+        };
         Importer {
+            tx,
             rx,
             filepath: self.filepath.unwrap(),
             processed_bytes: 0,
             milestone: self.milestone.unwrap(),
+            file_milestone: self.milestone.unwrap(),
+            last_confirmed: self.milestone.unwrap(),
+            previous_milestone: 0,
+            milestone_range,
+            line_number: 0,
             only_confirmed: self.only_confirmed.unwrap(),
+            verify_hash: self.verify_hash.unwrap_or(true),
+            rejected: 0,
+            exhausted: 0,
+            validate_only: self.validate_only.unwrap_or(false),
+            report: Vec::new(),
+            selector: self
+                .selective
+                .map(|rules| Selector::new(rules, selective::DEFAULT_MAX_BUNDLES)),
+            batch_type: self.batch_type,
             pids,
             progress_bar: None,
-            pending: 0,
+            read_bytes: None,
+            show_progress: self.show_progress.unwrap_or(true),
+            progress: self.progress,
+            control: self.control,
+            in_flight: BTreeMap::new(),
+            reject_file: None,
+            max_in_flight,
+            next_id: 0,
             max_retries: self.max_retries.unwrap(),
+            checksum: 0,
+            checkpoint_interval: Duration::from_secs(
+                self.checkpoint_interval
+                    .unwrap_or(checkpoint::DEFAULT_CHECKPOINT_INTERVAL),
+            ),
+            last_checkpoint: Instant::now(),
         }
     }
 }
 
 pub struct Importer {
+    tx: Sender,
     rx: Receiver,
     filepath: String,
     processed_bytes: u64,
     milestone: u64,
+    file_milestone: u64,
+    /// The last non zero milestone of the lines, the unconfirmed transactions can be attributed to it.
+    last_confirmed: u64,
+    /// The last non zero milestone of the valid lines, checked in the validate_only mode.
+    previous_milestone: u64,
+    milestone_range: Option<MilestoneRange>,
+    line_number: u64,
     only_confirmed: bool,
+    verify_hash: bool,
+    rejected: u64,
+    /// The transactions rejected once their retry budget got exhausted.
+    exhausted: u64,
+    /// Parse and check every line without inserting them, the bad lines are reported in <filepath>.report.
+    validate_only: bool,
+    report: Vec<String>,
+    selector: Option<Selector>,
+    batch_type: Option<BatchTypes>,
     pids: Vec<Box<ImporterId>>,
     progress_bar: Option<ProgressBar>,
-    pending: usize,
+    /// The read bytes of a compressed file, as its processed bytes are counted in decompressed bytes.
+    read_bytes: Option<Arc<AtomicU64>>,
+    show_progress: bool,
+    /// The progress and the control of the import when it runs as a job.
+    progress: Option<Arc<Progress>>,
+    control: Option<watch::Receiver<Control>>,
+    in_flight: BTreeMap<TransactionId, InFlight>,
+    /// The dmp lines of the transactions which failed with a fatal error, opened on the first one.
+    reject_file: Option<File>,
+    max_in_flight: usize,
+    next_id: TransactionId,
+    /// The max retries of each transaction.
     max_retries: usize,
+    checksum: u64,
+    checkpoint_interval: Duration,
+    last_checkpoint: Instant,
+}
+
+/// The bookkeeping of a transaction whose queries are not fully acknowledged yet.
+struct InFlight {
+    queries: Vec<(i64, Vec<u8>)>,
+    pending: usize,
+    /// The offset of its dmp line, and the milestone inherited by the line, where a checkpoint can resume from.
+    line_start: (u64, u64),
+    /// The dmp line of the transaction with its milestone, written to the reject file on a fatal error.
+    line: String,
+    retries: usize,
+    rejected: bool,
 }
 
 impl Importer {
@@ -143,290 +259,419 @@ impl Importer {
                 )
                 .progress_chars("#>-"),
         );
-        self.progress_bar.replace(pb);
+        if !self.show_progress {
+            pb.set_draw_target(ProgressDrawTarget::hidden());
+        }
+        if let Some(progress) = self.progress.as_ref() {
+            progress.total_bytes.store(len, Ordering::Relaxed);
+        }
+        let compression = Compression::of(&self.filepath, &mut file).await?;
+        // resume from the checkpoint of the file (if any), the offset is in the decompressed content
+        self.checksum = checkpoint::checksum(&mut file, len).await?;
+        let checkpoint = match self.validate_only {
+            false => Checkpoint::load(&self.filepath, self.checksum).await,
+            true => None,
+        };
+        if let Some(checkpoint) = checkpoint {
+            if compression != Compression::None || checkpoint.offset <= len {
+                info!(
+                    "{}: resuming from offset: {}, milestone: {}",
+                    self.filepath, checkpoint.offset, checkpoint.milestone
+                );
+                if compression == Compression::None {
+                    file.seek(SeekFrom::Start(checkpoint.offset)).await?;
+                }
+                self.processed_bytes = checkpoint.offset;
+                self.milestone = checkpoint.milestone;
+                if checkpoint.milestone != 0 {
+                    self.last_confirmed = checkpoint.milestone;
+                }
+            }
+        }
         // create buffer reader to enable us to read line by line
-        let reader = BufReader::new(&mut file);
+        let read_bytes = Arc::new(AtomicU64::new(0));
+        let mut reader = compression.reader(file, read_bytes.clone());
+        if compression != Compression::None {
+            // a compressed file can't be seeked, so the imported lines are decompressed and skipped instead
+            tokio_io::copy(&mut (&mut reader).take(self.processed_bytes), &mut tokio_io::sink()).await?;
+            // the progress of a compressed file is reported in compressed bytes
+            self.read_bytes.replace(read_bytes);
+        }
+        self.progress_bar.replace(pb);
+        self.set_progress();
         self.handle_dmp(reader).await
     }
-    async fn handle_dmp(&mut self, mut reader: BufReader<&mut File>) -> Result<(), Box<dyn Error>> {
-        let mut try_attachment_timestamp = false;
-        if self.milestone > 337541 {
-            try_attachment_timestamp = true;
-        }
+    async fn handle_dmp(&mut self, mut reader: Box<dyn AsyncBufRead + Unpin + Send>) -> Result<(), Box<dyn Error>> {
         let mut line = String::new();
         // start processing the file line by line
         loop {
+            if !self.follow_control().await? {
+                return self.cancel().await;
+            }
             let line_length = reader.read_line(&mut line).await?;
             // break if EOF
             if line_length == 0 {
                 break;
             }
-            let hash = &line[..81];
-            let txtrytes = &line[82..2755];
-            // check if milestone is in the line
-            if line_length > 2756 {
-                self.milestone = line[2756..(line_length - 1)].parse::<u64>().unwrap();
-            }
-            // check whether to skip the transaction(line) if only_confirmed or not.
-            if self.only_confirmed && self.milestone == 0 {
-                continue;
+            self.line_number += 1;
+            let line_start = (self.processed_bytes, self.milestone);
+            let mut transaction = None;
+            match line::split(&line) {
+                Ok(fields) => {
+                    let mut error = None;
+                    // check if milestone is in the line
+                    if let Some(milestone) = fields.milestone {
+                        if self.validate_only && milestone != 0 {
+                            error = self.check_milestone(milestone);
+                        }
+                        self.milestone = milestone;
+                        if milestone != 0 {
+                            self.last_confirmed = milestone;
+                        }
+                    }
+                    // check whether to skip the transaction(line) if only_confirmed or out of the milestone range,
+                    // every line is checked in the validate_only mode, a bad line is rejected once.
+                    if let Some(error) = error {
+                        self.reject(error);
+                    } else if self.validate_only || ((!self.only_confirmed || self.milestone != 0) && self.in_range()) {
+                        // reject the transaction(line) if it's malformed or its hash doesn't match the trytes
+                        match Transaction::parse(fields.hash, fields.trytes) {
+                            Ok(_) if self.verify_hash && !curl::verify_transaction(fields.hash, fields.trytes) => {
+                                self.reject(LineError::InvalidHash);
+                            }
+                            Ok(parsed) => transaction = Some(parsed),
+                            Err(error) => self.reject(LineError::Transaction(error)),
+                        }
+                    }
+                }
+                Err(error) => self.reject(error),
             }
-            self.pending += 6; // 1 tx_query + 5 edge_table queries
-            let tx_query = insert_to_tx_table(hash, txtrytes, self.milestone);
-            let request = reporter::Event::Request {
-                payload: tx_query,
-                worker: self.pids.pop().unwrap().query_id(1),
+            let transaction = match transaction {
+                Some(transaction) if !self.validate_only => transaction,
+                _ => {
+                    line.clear();
+                    self.processed(line_length).await;
+                    continue;
+                }
             };
-            Ring::send_local_random_replica(rand::random::<i64>(), request);
-            // extract the transaction value
-            let value = trytes_to_i64(&txtrytes[2268..2295]);
-            // extract the timestamp and year, month
-            let timestamp = trytes_to_i64(&txtrytes[2322..2331]);
-            let mut naive = NaiveDateTime::from_timestamp(timestamp, 0);
-            let mut year = naive.year() as u16;
-            let mut month = naive.month() as u8;
-            if try_attachment_timestamp {
-                let attachment_timestamp = trytes_to_i64(&txtrytes[2619..2628]);
-                if attachment_timestamp != 0 {
-                    naive = NaiveDateTime::from_timestamp(attachment_timestamp, 0);
-                    year = naive.year() as u16;
-                    month = naive.month() as u8
+            let mut skip = false;
+            // the selector (if any) decides whether to store the transaction and the held members of its bundle
+            if let Some(selector) = self.selector.as_mut() {
+                match selector.select_at(&transaction, Some(self.milestone), Some(line_start)) {
+                    Decision::Store(released) => {
+                        for held in released {
+                            let held_transaction = Transaction::parse(&held.hash, &held.trytes)?;
+                            let held_line_start = held.position.unwrap_or(line_start);
+                            self.insert_transaction(held_transaction, held.milestone.unwrap(), held_line_start)
+                                .await?;
+                        }
+                    }
+                    Decision::Skip => skip = true,
                 }
             }
-            // create queries related to the transaction value
-            match value {
-                0 => {
-                    // create insert hint queries
-                    let hint_query =
-                        insert_to_edge_table(&txtrytes[2187..2268], "hint", 0, "0", value, YearMonth(year, month));
-                    let request = reporter::Event::Request {
-                        payload: hint_query,
-                        worker: self.pids.pop().unwrap().query_id(2),
-                    };
-                    Ring::send_local_random_replica(rand::random::<i64>(), request);
-                    let address_query =
-                        insert_to_data_table(&txtrytes[2187..2268], year, month, "address", timestamp, hash);
-                    let request = reporter::Event::Request {
-                        payload: address_query,
-                        worker: self.pids.pop().unwrap().query_id(3),
-                    };
-                    Ring::send_local_random_replica(rand::random::<i64>(), request);
-                    // because it is a hint
-                    self.pending += 1;
-                }
-                v if v > 0 => {
-                    // create insert output query
-                    let output_query =
-                        insert_to_edge_table(&txtrytes[2187..2268], "output", timestamp, hash, value, UNSET_VALUE);
-                    let request = reporter::Event::Request {
-                        payload: output_query,
-                        worker: self.pids.pop().unwrap().query_id(4),
-                    };
-                    Ring::send_local_random_replica(rand::random::<i64>(), request);
+            if !skip {
+                self.insert_transaction(transaction, self.milestone, line_start).await?;
+            }
+            // clear line
+            line.clear();
+            self.processed(line_length).await;
+        }
+        if self.validate_only {
+            return self.finish_validation().await;
+        }
+        // wait for the transactions in flight
+        self.drain().await?;
+        // the bundles still held at the end of the file are never selected, so they don't hold back the final
+        // checkpoint, which marks the file as imported, so a restart skips it
+        self.selector.take();
+        self.checkpoint().await;
+        self.progress_bar.as_ref().unwrap().finish_with_message(&format!(
+            "{} is processed succesfully, rejected transactions: {}, exhausted retries: {}.",
+            self.filepath, self.rejected, self.exhausted
+        ));
+        Ok(())
+    }
+    // check the milestone of the line against the milestone of the file and the milestone of the previous lines, the
+    // milestones of a dmp file are ordered.
+    fn check_milestone(&mut self, milestone: u64) -> Option<LineError> {
+        if self.file_milestone != 0 && milestone > self.file_milestone {
+            Some(LineError::MilestoneAhead(milestone))
+        } else if milestone < self.previous_milestone {
+            Some(LineError::MilestoneBehind {
+                milestone,
+                previous: self.previous_milestone,
+            })
+        } else {
+            self.previous_milestone = milestone;
+            None
+        }
+    }
+    fn in_range(&self) -> bool {
+        match self.milestone_range.as_ref() {
+            Some(range) => range.contains(self.milestone, self.last_confirmed),
+            None => true,
+        }
+    }
+    // follow the command of the jobs queue (if any), a paused import waits for the next command once its transactions
+    // in flight are acknowledged and checkpointed. Returns false if the import is cancelled.
+    async fn follow_control(&mut self) -> Result<bool, Box<dyn Error>> {
+        let mut control = match self.control.take() {
+            Some(control) => control,
+            None => return Ok(true),
+        };
+        let command = *control.borrow();
+        let proceed = match command {
+            Control::Run => true,
+            Control::Cancel => false,
+            Control::Pause => {
+                self.drain().await?;
+                if !self.validate_only {
+                    self.checkpoint().await;
                 }
-                _ => {
-                    // create insert input query
-                    let input_query =
-                        insert_to_edge_table(&txtrytes[2187..2268], "input", timestamp, hash, value, UNSET_VALUE);
-                    let request = reporter::Event::Request {
-                        payload: input_query,
-                        worker: self.pids.pop().unwrap().query_id(5),
-                    };
-                    Ring::send_local_random_replica(rand::random::<i64>(), request);
+                info!("{}: paused at offset: {}", self.filepath, self.processed_bytes);
+                loop {
+                    // the jobs queue is gone if the sender is dropped
+                    match control.recv().await {
+                        Some(Control::Run) => break true,
+                        Some(Control::Pause) => continue,
+                        Some(Control::Cancel) | None => break false,
+                    }
                 }
             }
-            // insert queries not related to the transaction value
-            let trunk_query = insert_to_edge_table(&txtrytes[2430..2511], "trunk", timestamp, hash, value, UNSET_VALUE);
-            let request = reporter::Event::Request {
-                payload: trunk_query,
-                worker: self.pids.pop().unwrap().query_id(6),
-            };
-            Ring::send_local_random_replica(rand::random::<i64>(), request);
-            let branch_query =
-                insert_to_edge_table(&txtrytes[2511..2592], "branch", timestamp, hash, value, UNSET_VALUE);
-            let request = reporter::Event::Request {
-                payload: branch_query,
-                worker: self.pids.pop().unwrap().query_id(7),
-            };
-            Ring::send_local_random_replica(rand::random::<i64>(), request);
-            let bundle_query =
-                insert_to_edge_table(&txtrytes[2349..2430], "bundle", timestamp, hash, value, UNSET_VALUE);
-            let request = reporter::Event::Request {
-                payload: bundle_query,
-                worker: self.pids.pop().unwrap().query_id(8),
-            };
-            Ring::send_local_random_replica(rand::random::<i64>(), request);
-            let tag_query = insert_to_data_table(&txtrytes[2592..2619], year, month, "tag", timestamp, hash);
+        };
+        self.control.replace(control);
+        Ok(proceed)
+    }
+    // stop the import once the transactions in flight are acknowledged, the checkpoint is kept so a later import of the
+    // file resumes from it.
+    async fn cancel(&mut self) -> Result<(), Box<dyn Error>> {
+        self.drain().await?;
+        if !self.validate_only {
+            self.checkpoint().await;
+        }
+        self.progress_bar.as_ref().unwrap().abandon_with_message(&format!(
+            "{} is cancelled at offset: {}, rejected transactions: {}, exhausted retries: {}.",
+            self.filepath, self.processed_bytes, self.rejected, self.exhausted
+        ));
+        Ok(())
+    }
+    async fn drain(&mut self) -> Result<(), Box<dyn Error>> {
+        while !self.in_flight.is_empty() {
+            self.handle_event().await?;
+        }
+        Ok(())
+    }
+    // count the bad line, it's logged in the normal mode, and reported in the validate_only mode.
+    fn reject(&mut self, error: LineError) {
+        self.rejected += 1;
+        if !self.validate_only {
+            warn!(
+                "{}: rejected line at offset: {}, {}",
+                self.filepath, self.processed_bytes, error
+            );
+        } else if self.report.len() < MAX_REPORTED_LINES {
+            self.report.push(format!("line {}: {}", self.line_number, error));
+        }
+    }
+    async fn finish_validation(&mut self) -> Result<(), Box<dyn Error>> {
+        let path = format!("{}.report", self.filepath);
+        let mut report = format!(
+            "{}: lines: {}, bad lines: {}\n",
+            self.filepath, self.line_number, self.rejected
+        );
+        for bad_line in self.report.iter() {
+            report.push_str(bad_line);
+            report.push('\n');
+        }
+        if self.rejected as usize > self.report.len() {
+            report.push_str(&format!("... {} more\n", self.rejected as usize - self.report.len()));
+        }
+        tokio::fs::write(&path, report).await?;
+        self.progress_bar.as_ref().unwrap().finish_with_message(&format!(
+            "{} is validated, lines: {}, bad lines: {}, report: {}.",
+            self.filepath, self.line_number, self.rejected, path
+        ));
+        Ok(())
+    }
+    // update the progress bar, and persist the checkpoint once the interval elapsed since the previous one.
+    async fn processed(&mut self, line_length: usize) {
+        self.processed_bytes += line_length as u64;
+        self.set_progress();
+        if !self.validate_only && self.last_checkpoint.elapsed() >= self.checkpoint_interval {
+            self.checkpoint().await;
+        }
+    }
+    fn set_progress(&self) {
+        let position = match self.read_bytes.as_ref() {
+            Some(read_bytes) => read_bytes.load(Ordering::Relaxed),
+            None => self.processed_bytes,
+        };
+        self.progress_bar.as_ref().unwrap().set_position(position);
+        if let Some(progress) = self.progress.as_ref() {
+            progress.bytes.store(position, Ordering::Relaxed);
+            progress.rejected.store(self.rejected, Ordering::Relaxed);
+            progress.exhausted.store(self.exhausted, Ordering::Relaxed);
+        }
+    }
+    async fn checkpoint(&mut self) {
+        // resume from the oldest line in flight or held by the selector, so none of the unacknowledged or held
+        // transactions is skipped. NOTE: the released held transactions are in flight out of the line order.
+        let oldest_in_flight = self.in_flight.values().map(|in_flight| in_flight.line_start).min();
+        let oldest_held = self.selector.as_ref().and_then(Selector::oldest_held_position);
+        let (offset, milestone) = match (oldest_in_flight, oldest_held) {
+            (Some(in_flight), Some(held)) => in_flight.min(held),
+            (Some(line_start), None) | (None, Some(line_start)) => line_start,
+            (None, None) => (self.processed_bytes, self.milestone),
+        };
+        let checkpoint = Checkpoint {
+            filepath: self.filepath.clone(),
+            offset,
+            milestone,
+            checksum: self.checksum,
+        };
+        if let Err(error) = checkpoint.save().await {
+            warn!("{}: failed to persist the checkpoint, error: {}", self.filepath, error);
+        }
+        self.last_checkpoint = Instant::now();
+    }
+    async fn insert_transaction(
+        &mut self,
+        transaction: Transaction<'_>,
+        milestone: u64,
+        line_start: (u64, u64),
+    ) -> Result<(), Box<dyn Error>> {
+        // wait for a free slot in the window
+        while self.in_flight.len() >= self.max_in_flight {
+            self.handle_event().await?;
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        // the query_id of each query is its index in the queries
+        let queries = transaction.insert_queries(Some(milestone), self.batch_type);
+        let line = format!("{},{},{}\n", transaction.hash(), transaction.trytes(), milestone);
+        for (query_id, (token, payload)) in queries.iter().enumerate() {
             let request = reporter::Event::Request {
-                payload: tag_query,
-                worker: self.pids.pop().unwrap().query_id(9),
+                payload: payload.clone(),
+                worker: self.pid(id, query_id as u8),
             };
-            Ring::send_local_random_replica(rand::random::<i64>(), request);
-            // process the responses for the pending queries
-            while let Some(event) = self.rx.recv().await {
-                match event {
-                    Event::Response { decoder, pid } => {
-                        self.pending -= 1;
-                        self.pids.push(pid);
-                        assert!(decoder.is_void());
-                        if self.pending == 0 {
-                            break;
-                        }
-                    }
-                    Event::Error { kind, pid } => {
-                        if self.max_retries == 0 {
-                            self.pids.push(pid);
-                            return Err(Box::new(kind));
-                        } else {
-                            self.max_retries -= 1;
-                            // retry the specific query based on its query_id using send_global_random_replica strategy
-                            match pid.get_query_id() {
-                                1 => {
-                                    let tx_query = insert_to_tx_table(hash, txtrytes, self.milestone);
-                                    let request = reporter::Event::Request {
-                                        payload: tx_query,
-                                        worker: pid,
-                                    };
-                                    Ring::send_global_random_replica(rand::random::<i64>(), request);
-                                }
-                                2 => {
-                                    let hint_query = insert_to_edge_table(
-                                        &txtrytes[2187..2268],
-                                        "hint",
-                                        0,
-                                        "0",
-                                        value,
-                                        YearMonth(year, month),
-                                    );
-                                    let request = reporter::Event::Request {
-                                        payload: hint_query,
-                                        worker: pid,
-                                    };
-                                    Ring::send_global_random_replica(rand::random::<i64>(), request);
-                                }
-                                3 => {
-                                    let address_query = insert_to_data_table(
-                                        &txtrytes[2187..2268],
-                                        year,
-                                        month,
-                                        "address",
-                                        timestamp,
-                                        hash,
-                                    );
-                                    let request = reporter::Event::Request {
-                                        payload: address_query,
-                                        worker: pid,
-                                    };
-                                    Ring::send_global_random_replica(rand::random::<i64>(), request);
-                                }
-                                4 => {
-                                    let output_query = insert_to_edge_table(
-                                        &txtrytes[2187..2268],
-                                        "output",
-                                        timestamp,
-                                        hash,
-                                        value,
-                                        UNSET_VALUE,
-                                    );
-                                    let request = reporter::Event::Request {
-                                        payload: output_query,
-                                        worker: pid,
-                                    };
-                                    Ring::send_global_random_replica(rand::random::<i64>(), request);
-                                }
-                                5 => {
-                                    let input_query = insert_to_edge_table(
-                                        &txtrytes[2187..2268],
-                                        "input",
-                                        timestamp,
-                                        hash,
-                                        value,
-                                        UNSET_VALUE,
-                                    );
-                                    let request = reporter::Event::Request {
-                                        payload: input_query,
-                                        worker: pid,
-                                    };
-                                    Ring::send_global_random_replica(rand::random::<i64>(), request);
-                                }
-                                6 => {
-                                    let trunk_query = insert_to_edge_table(
-                                        &txtrytes[2430..2511],
-                                        "trunk",
-                                        timestamp,
-                                        hash,
-                                        value,
-                                        UNSET_VALUE,
-                                    );
-                                    let request = reporter::Event::Request {
-                                        payload: trunk_query,
-                                        worker: pid,
-                                    };
-                                    Ring::send_global_random_replica(rand::random::<i64>(), request);
-                                }
-                                7 => {
-                                    let branch_query = insert_to_edge_table(
-                                        &txtrytes[2511..2592],
-                                        "branch",
-                                        timestamp,
-                                        hash,
-                                        value,
-                                        UNSET_VALUE,
-                                    );
-                                    let request = reporter::Event::Request {
-                                        payload: branch_query,
-                                        worker: pid,
-                                    };
-                                    Ring::send_global_random_replica(rand::random::<i64>(), request);
-                                }
-                                8 => {
-                                    let bundle_query = insert_to_edge_table(
-                                        &txtrytes[2349..2430],
-                                        "bundle",
-                                        timestamp,
-                                        hash,
-                                        value,
-                                        UNSET_VALUE,
-                                    );
-                                    let request = reporter::Event::Request {
-                                        payload: bundle_query,
-                                        worker: pid,
-                                    };
-                                    Ring::send_global_random_replica(rand::random::<i64>(), request);
-                                }
-                                9 => {
-                                    let tag_query = insert_to_data_table(
-                                        &txtrytes[2592..2619],
-                                        year,
-                                        month,
-                                        "tag",
-                                        timestamp,
-                                        hash,
-                                    );
-                                    let request = reporter::Event::Request {
-                                        payload: tag_query,
-                                        worker: pid,
-                                    };
-                                    Ring::send_global_random_replica(rand::random::<i64>(), request);
-                                }
-                                _ => unreachable!("invalid query_id"),
-                            }
-                        }
-                    }
+            Ring::send_local_random_replica(*token, request);
+        }
+        self.in_flight.insert(
+            id,
+            InFlight {
+                pending: queries.len(),
+                queries,
+                line_start,
+                line,
+                retries: 0,
+                rejected: false,
+            },
+        );
+        Ok(())
+    }
+    // process the response of a query in flight, the retryable errors are retried with backoff until the
+    // max_retries of the transaction is exhausted, and the transaction is rejected on a fatal error or once its
+    // retries are exhausted.
+    async fn handle_event(&mut self) -> Result<(), Box<dyn Error>> {
+        // the importer holds a sender, so the channel is never closed
+        match self.rx.recv().await.unwrap() {
+            Event::Response { decoder, pid } => {
+                assert!(decoder.is_void());
+                let id = pid.get_transaction_id();
+                self.pids.push(pid);
+                self.acknowledge(id);
+            }
+            Event::Error { kind, pid } if kind.is_retryable() => {
+                let id = pid.get_transaction_id();
+                let in_flight = self.in_flight.get_mut(&id).unwrap();
+                if in_flight.retries == self.max_retries {
+                    self.pids.push(pid);
+                    self.reject_transaction(id, kind, true).await?;
+                    self.acknowledge(id);
+                    return Ok(());
+                }
+                in_flight.retries += 1;
+                // retry the specific query based on its query_id
+                let (token, payload) = &in_flight.queries[pid.get_query_id() as usize];
+                let token = *token;
+                let request = reporter::Event::Request {
+                    payload: payload.clone(),
+                    worker: pid,
                 };
+                retry::retry(token, request, in_flight.retries as u32);
+            }
+            Event::Error { kind, pid } => {
+                let id = pid.get_transaction_id();
+                self.pids.push(pid);
+                self.reject_transaction(id, kind, false).await?;
+                self.acknowledge(id);
             }
-            // clear line
-            line.clear();
-            // update the progresss bar
-            self.processed_bytes += line_length as u64;
-            self.progress_bar.as_ref().unwrap().set_position(self.processed_bytes);
         }
-        self.progress_bar
-            .as_ref()
-            .unwrap()
-            .finish_with_message(&format!("{} is processed succesfully.", self.filepath));
         Ok(())
     }
+    // count the acknowledged query, the transaction is done once all its queries are acknowledged.
+    fn acknowledge(&mut self, id: TransactionId) {
+        let in_flight = self.in_flight.get_mut(&id).unwrap();
+        in_flight.pending -= 1;
+        if in_flight.pending == 0 {
+            if let (false, Some(progress)) = (in_flight.rejected, self.progress.as_ref()) {
+                progress.transactions.fetch_add(1, Ordering::Relaxed);
+            }
+            self.in_flight.remove(&id);
+        }
+    }
+    // append the dmp line of the transaction to <filepath>.rejected, once even if more of its queries fail, so it can
+    // be imported again once the cause is fixed. The transactions whose retries got exhausted are counted apart from
+    // the ones failed with a fatal error.
+    async fn reject_transaction(
+        &mut self,
+        id: TransactionId,
+        kind: worker::Error,
+        exhausted: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let in_flight = self.in_flight.get_mut(&id).unwrap();
+        if in_flight.rejected {
+            return Ok(());
+        }
+        in_flight.rejected = true;
+        if exhausted {
+            self.exhausted += 1;
+            warn!(
+                "{}: rejected the transaction at offset: {}, exhausted {} retries, error: {:?}",
+                self.filepath, in_flight.line_start.0, self.max_retries, kind
+            );
+        } else {
+            self.rejected += 1;
+            warn!(
+                "{}: rejected the transaction at offset: {}, error: {:?}",
+                self.filepath, in_flight.line_start.0, kind
+            );
+        }
+        if self.reject_file.is_none() {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(format!("{}.rejected", self.filepath))
+                .await?;
+            self.reject_file.replace(file);
+        }
+        // flushed right away, so the rejected line is persisted before a checkpoint moves past it
+        let reject_file = self.reject_file.as_mut().unwrap();
+        reject_file.write_all(in_flight.line.as_bytes()).await?;
+        reject_file.flush().await?;
+        if let Some(progress) = self.progress.as_ref() {
+            progress.rejected.store(self.rejected, Ordering::Relaxed);
+            progress.exhausted.store(self.exhausted, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+    fn pid(&mut self, id: TransactionId, query_id: u8) -> Box<ImporterId> {
+        // take a pid from the preallocated pool, a new one is only needed if the pool is drained
+        match self.pids.pop() {
+            Some(pid) => pid.query_id(id, query_id),
+            None => Box::new(ImporterId(self.tx.clone(), id, query_id)),
+        }
+    }
 }
 
 impl worker::Worker for ImporterId {
@@ -458,135 +703,49 @@ impl worker::Worker for ImporterId {
     }
 }
 
-/// Create insert cql query in transaction table
-pub fn insert_to_tx_table(hash: &str, txtrytes: &str, milestone: impl ColumnEncoder) -> Vec<u8> {
-    let Query(payload) = Query::new()
-        .version()
-        .flags(MyCompression::flag())
-        .stream(0)
-        .opcode()
-        .length()
-        .statement(INSERT_TANGLE_TX_QUERY)
-        .consistency(Consistency::One)
-        .query_flags(SKIP_METADATA | VALUES)
-        .value_count(17) // the total value count
-        .value(hash)
-        .value(&txtrytes[..2187]) // PAYLOAD
-        .value(&txtrytes[2187..2268]) // ADDRESS
-        .value(&txtrytes[2268..2295]) // VALUE
-        .value(&txtrytes[2295..2322]) // OBSOLETE_TAG
-        .value(&txtrytes[2322..2331]) // TIMESTAMP
-        .value(&txtrytes[2331..2340]) // CURRENT_IDX
-        .value(&txtrytes[2340..2349]) // LAST_IDX
-        .value(&txtrytes[2349..2430]) // BUNDLE_HASH
-        .value(&txtrytes[2430..2511]) // TRUNK
-        .value(&txtrytes[2511..2592]) // BRANCH
-        .value(&txtrytes[2592..2619]) // TAG
-        .value(&txtrytes[2619..2628]) // ATCH_TIMESTAMP
-        .value(&txtrytes[2628..2637]) // ATCH_TIMESTAMP_LOWER
-        .value(&txtrytes[2637..2646]) // ATCH_TIMESTAMP_UPPER
-        .value(&txtrytes[2646..2673]) // Nonce
-        .value(milestone) // milestone
-        .build(MyCompression::get());
-    payload
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-/// Create insert(index) cql query in edge table
-pub fn insert_to_edge_table(
-    vertex: &str,
-    kind: &str,
-    timestamp: i64,
-    tx: &str,
-    value: i64,
-    extra: impl ColumnEncoder,
-) -> Vec<u8> {
-    let Query(payload) = Query::new()
-        .version()
-        .flags(MyCompression::flag())
-        .stream(0)
-        .opcode()
-        .length()
-        .statement(INSERT_TANGLE_EDGE_STATMENT)
-        .consistency(Consistency::One)
-        .query_flags(SKIP_METADATA | VALUES)
-        .value_count(6) // the total value count
-        .value(vertex) // vertex
-        .value(kind) // kind
-        .value(timestamp) // timestamp
-        .value(tx) // tx-hash
-        .value(value) // value
-        .value(extra) // extra
-        .build(MyCompression::get());
-    payload
-}
-/// Create insert(index) cql query in data table
-pub fn insert_to_data_table(vertex: &str, year: u16, month: u8, kind: &str, timestamp: i64, tx: &str) -> Vec<u8> {
-    let Query(payload) = Query::new()
-        .version()
-        .flags(MyCompression::flag())
-        .stream(0)
-        .opcode()
-        .length()
-        .statement(INSERT_TANGLE_DATA_STATMENT)
-        .consistency(Consistency::One)
-        .query_flags(SKIP_METADATA | VALUES)
-        .value_count(6) // the total value count
-        .value(vertex) // vertex
-        .value(year)
-        .value(month)
-        .value(kind) // kind
-        .value(timestamp) // timestamp
-        .value(tx) // tx-hash
-        .build(MyCompression::get());
-    payload
-}
-
-/// Convert valid trytes to i64
-pub fn trytes_to_i64(slice: &str) -> i64 {
-    let trytes = TryteBuf::try_from_str(slice);
-    let trit_buf: TritBuf<T1B1Buf> = trytes.unwrap().as_trits().encode();
-    i64::try_from(trit_buf).unwrap()
+    #[tokio::test]
+    async fn validate_milestone_order() {
+        let filepath = std::env::temp_dir()
+            .join(format!("chronicle-validate-{}.dmp", std::process::id()))
+            .to_str()
+            .unwrap()
+            .to_string();
+        let line = |milestone: u64| format!("{},{},{}\n", "9".repeat(81), "9".repeat(2673), milestone);
+        // the malformed line ahead of the file is rejected once, the line behind its previous line is rejected
+        let malformed = format!("{},{},{}\n", "9".repeat(81), "9".repeat(10), 20);
+        let dmp = [line(5), malformed, line(7), line(0), line(6), line(10)].concat();
+        tokio::fs::write(&filepath, dmp).await.unwrap();
+        ImporterBuilder::new()
+            .filepath(filepath.clone())
+            .milestone(10)
+            .only_confirmed(false)
+            .verify_hash(false)
+            .max_retries(0)
+            .show_progress(false)
+            .validate_only(true)
+            .build()
+            .run()
+            .await
+            .unwrap();
+        let report_path = format!("{}.report", filepath);
+        let report = tokio::fs::read_to_string(&report_path).await.unwrap();
+        assert_eq!(
+            report,
+            format!(
+                "{}: lines: 6, bad lines: 2\nline 2: {}\nline 5: {}\n",
+                filepath,
+                LineError::MilestoneAhead(20),
+                LineError::MilestoneBehind {
+                    milestone: 6,
+                    previous: 7
+                }
+            )
+        );
+        tokio::fs::remove_file(&report_path).await.unwrap();
+        tokio::fs::remove_file(&filepath).await.unwrap();
+    }
 }
-pub const INSERT_TANGLE_TX_QUERY: &str = r#"
-  INSERT INTO tangle.transaction (
-    hash,
-    payload,
-    address,
-    value,
-    obsolete_tag,
-    timestamp,
-    current_index,
-    last_index,
-    bundle,
-    trunk,
-    branch,
-    tag,
-    attachment_timestamp,
-    attachment_timestamp_lower,
-    attachment_timestamp_upper,
-    nonce,
-    milestone
-) VALUES (?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?);
-"#;
-
-pub const INSERT_TANGLE_EDGE_STATMENT: &str = r#"
-  INSERT INTO tangle.edge (
-    vertex,
-    kind,
-    timestamp,
-    tx,
-    value,
-    extra
-) VALUES (?,?,?,?,?,?);
-"#;
-
-pub const INSERT_TANGLE_DATA_STATMENT: &str = r#"
-  INSERT INTO tangle.data (
-    vertex,
-    year,
-    month,
-    kind,
-    timestamp,
-    tx
-) VALUES (?,?,?,?,?,?);
-"#;
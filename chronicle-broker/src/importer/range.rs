@@ -0,0 +1,47 @@
+// The milestone range of an import, the lines out of the range are skipped right after splitting them, before the
+// transaction is parsed or inserted.
+
+/// The inclusive milestone range of the imported transactions.
+#[derive(Debug, Clone, Copy)]
+pub struct MilestoneRange {
+    pub from: u64,
+    pub to: u64,
+    /// Attribute the unconfirmed transactions to the last confirmed milestone before them in the dmp file, rather than
+    /// skipping them.
+    pub attribute_unconfirmed: bool,
+}
+
+impl MilestoneRange {
+    /// Returns true if the transaction confirmed by the milestone (zero if unconfirmed) is within the range.
+    pub fn contains(&self, milestone: u64, last_confirmed: u64) -> bool {
+        let milestone = match milestone {
+            0 if self.attribute_unconfirmed => last_confirmed,
+            milestone => milestone,
+        };
+        milestone != 0 && milestone >= self.from && milestone <= self.to
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn milestone_range() {
+        let mut range = MilestoneRange {
+            from: 1_000_000,
+            to: 1_050_000,
+            attribute_unconfirmed: false,
+        };
+        assert!(range.contains(1_000_000, 0));
+        assert!(range.contains(1_050_000, 0));
+        assert!(!range.contains(999_999, 0));
+        assert!(!range.contains(1_050_001, 0));
+        assert!(!range.contains(0, 1_000_100));
+        // the unconfirmed transactions follow the last confirmed milestone
+        range.attribute_unconfirmed = true;
+        assert!(range.contains(0, 1_000_100));
+        assert!(!range.contains(0, 1_050_001));
+        assert!(!range.contains(0, 0));
+    }
+}
@@ -0,0 +1,387 @@
+// The watcher imports the dmp files arriving in a folder, in milestone order, through the import jobs. The imported
+// files are moved to the done subfolder and the others to the failed subfolder, and every processed file is recorded
+// in the dmp_file table, so a file processed already is not imported again after a restart.
+use super::{
+    checkpoint,
+    jobs::{
+        self,
+        Status,
+    },
+};
+use crate::retry;
+use chronicle_common::actor;
+use chronicle_cql::{
+    compression::MyCompression,
+    frame::{
+        consistency::Consistency,
+        decoder::{
+            ColumnDecoder,
+            Decoder,
+            Frame,
+        },
+        header::Header,
+        query::Query,
+        queryflags::{
+            SKIP_METADATA,
+            VALUES,
+        },
+    },
+    rows,
+};
+use chronicle_storage::{
+    ring::Ring,
+    stage::reporter,
+    transaction,
+    worker,
+};
+use log::*;
+use std::{
+    collections::HashMap,
+    error::Error,
+    path::{
+        Path,
+        PathBuf,
+    },
+    time::Duration,
+};
+use tokio::{
+    fs::{
+        self,
+        File,
+    },
+    sync::mpsc,
+    time::delay_for,
+};
+
+type Sender = mpsc::UnboundedSender<Event>;
+type Receiver = mpsc::UnboundedReceiver<Event>;
+/// The default interval (in seconds) between two scans of the watched folder.
+pub const DEFAULT_WATCH_INTERVAL: u64 = 10;
+// the files of the importer next to a dmp file, moved along with it.
+const COMPANION_SUFFIXES: &[&str] = &[".checkpoint", ".rejected", ".report"];
+
+actor!(WatcherBuilder {
+    folder: String,
+    interval: u64,
+    jobs_tx: jobs::Sender,
+    max_retries: usize
+});
+
+impl WatcherBuilder {
+    pub fn build(self) -> Watcher {
+        let (tx, rx) = mpsc::unbounded_channel::<Event>();
+        Watcher {
+            folder: PathBuf::from(self.folder.unwrap()),
+            interval: Duration::from_secs(self.interval.unwrap_or(DEFAULT_WATCH_INTERVAL)),
+            jobs_tx: self.jobs_tx.unwrap(),
+            max_retries: self.max_retries.unwrap(),
+            sizes: HashMap::new(),
+            pid: Some(Box::new(WatcherId(tx))),
+            rx,
+        }
+    }
+}
+
+pub struct Watcher {
+    folder: PathBuf,
+    interval: Duration,
+    jobs_tx: jobs::Sender,
+    max_retries: usize,
+    /// The sizes of the files at the previous scan, a file is only imported once its size is stable.
+    sizes: HashMap<PathBuf, u64>,
+    pid: Option<Box<WatcherId>>,
+    rx: Receiver,
+}
+
+#[derive(Debug)]
+pub struct WatcherId(Sender);
+
+pub enum Event {
+    Response { decoder: Decoder, pid: Box<WatcherId> },
+    Error { kind: worker::Error, pid: Box<WatcherId> },
+}
+
+impl Watcher {
+    pub async fn run(mut self) {
+        for subfolder in &["done", "failed"] {
+            if let Err(error) = fs::create_dir_all(self.folder.join(subfolder)).await {
+                error!(
+                    "watcher: unable to create {:?}, error: {}",
+                    self.folder.join(subfolder),
+                    error
+                );
+                return;
+            }
+        }
+        info!("watcher: watching {:?} for dmp files", self.folder);
+        loop {
+            if let Err(error) = self.scan().await {
+                error!("watcher: failed to scan {:?}, error: {}", self.folder, error);
+            }
+            delay_for(self.interval).await;
+        }
+    }
+
+    // queue the new (stable) dmp files in milestone order, and move them once they are processed.
+    async fn scan(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut files = Vec::new();
+        let mut sizes = HashMap::new();
+        let mut entries = fs::read_dir(&self.folder).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            let path = entry.path();
+            let milestone = match path.file_name().and_then(|name| name.to_str()).and_then(milestone) {
+                Some(milestone) if metadata.is_file() => milestone,
+                _ => continue,
+            };
+            if self.sizes.get(&path) == Some(&metadata.len()) {
+                files.push((milestone, path.clone()));
+            }
+            sizes.insert(path, metadata.len());
+        }
+        self.sizes = sizes;
+        files.sort();
+        // the files are checked before queueing any of them, so a failed scan doesn't leave queued files behind
+        let mut new_files = Vec::new();
+        for (milestone, path) in files {
+            let name = path.file_name().unwrap().to_str().unwrap().to_string();
+            let mut file = File::open(&path).await?;
+            let len = file.metadata().await?.len();
+            let checksum = checkpoint::checksum(&mut file, len).await?;
+            if self.is_recorded(&name, checksum).await? {
+                info!("watcher: {} is imported already", name);
+                move_file(&path, &self.folder.join("done")).await;
+            } else {
+                new_files.push((name, path, milestone, checksum));
+            }
+        }
+        let imports: Vec<_> = new_files
+            .into_iter()
+            .map(|(name, path, milestone, checksum)| {
+                // the files are imported even in the validate_only mode, as they are recorded and moved once processed
+                let import = self.jobs_tx.queue_import(path.to_str().unwrap().to_string(), milestone);
+                (name, path, milestone, checksum, import)
+            })
+            .collect();
+        for (name, path, milestone, checksum, import) in imports {
+            let status = match import.await {
+                Ok(Status::Done) => "done",
+                Ok(Status::Cancelled) => "cancelled",
+                _ => "failed",
+            };
+            info!("watcher: {}: {}", name, status);
+            if let Err(error) = self.record(&name, checksum, milestone, status).await {
+                error!("watcher: unable to record {}, error: {:?}", name, error);
+            }
+            let subfolder = if status == "done" { "done" } else { "failed" };
+            move_file(&path, &self.folder.join(subfolder)).await;
+            self.sizes.remove(&path);
+        }
+        Ok(())
+    }
+
+    /// Check whether the file (with the same checksum) is recorded as imported.
+    async fn is_recorded(&mut self, name: &str, checksum: u64) -> Result<bool, worker::Error> {
+        let decoder = self
+            .request(transaction::token(name.as_bytes()), select_file(name))
+            .await?;
+        Ok(match Recorded::new(decoder, None).decode().finalize() {
+            Some((recorded_checksum, status)) => recorded_checksum == checksum as i64 && status == "done",
+            None => false,
+        })
+    }
+
+    async fn record(&mut self, name: &str, checksum: u64, milestone: u64, status: &str) -> Result<(), worker::Error> {
+        let payload = insert_file(name, checksum, milestone, status);
+        self.request(transaction::token(name.as_bytes()), payload).await?;
+        Ok(())
+    }
+
+    /// Send the request to its token owner and await the response, the retryable errors are retried with backoff
+    /// using send_global_random_replica strategy as long as max_retries is not exhausted.
+    async fn request(&mut self, token: i64, payload: Vec<u8>) -> Result<Decoder, worker::Error> {
+        let request = reporter::Event::Request {
+            payload: payload.clone(),
+            worker: self.pid.take().unwrap(),
+        };
+        Ring::send_local_random_replica(token, request);
+        let mut attempt = 0;
+        loop {
+            match self.rx.recv().await.unwrap() {
+                Event::Response { decoder, pid } => {
+                    self.pid.replace(pid);
+                    return Ok(decoder);
+                }
+                Event::Error { kind, pid } => {
+                    attempt += 1;
+                    if !kind.is_retryable() || attempt > self.max_retries {
+                        self.pid.replace(pid);
+                        return Err(kind);
+                    }
+                    let request = reporter::Event::Request {
+                        payload: payload.clone(),
+                        worker: pid,
+                    };
+                    retry::retry(token, request, attempt as u32);
+                }
+            }
+        }
+    }
+}
+
+/// The milestone of a dmp file from its name, which must be `[<prefix>-|_]<milestone>.dmp[.gz|.zst|.xz]` (ie
+/// 1050000.dmp or mainnet-1050000.dmp.gz), the other files are ignored.
+pub fn milestone(name: &str) -> Option<u64> {
+    let stem = match name.rfind(".dmp") {
+        Some(end) if matches!(&name[end..], ".dmp" | ".dmp.gz" | ".dmp.zst" | ".dmp.xz") => &name[..end],
+        _ => return None,
+    };
+    let digits = match stem.rfind(|c| c == '-' || c == '_') {
+        Some(separator) => &stem[separator + 1..],
+        None => stem,
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+// move the file and its companion files (if any) to the folder.
+async fn move_file(path: &Path, folder: &Path) {
+    let name = path.file_name().unwrap().to_str().unwrap();
+    if let Err(error) = fs::rename(path, folder.join(name)).await {
+        error!("watcher: unable to move {:?} to {:?}, error: {}", path, folder, error);
+    }
+    for suffix in COMPANION_SUFFIXES {
+        let companion = format!("{}{}", path.to_str().unwrap(), suffix);
+        if fs::metadata(&companion).await.is_ok() {
+            let _ = fs::rename(&companion, folder.join(format!("{}{}", name, suffix))).await;
+        }
+    }
+}
+
+impl worker::Worker for WatcherId {
+    fn send_response(self: Box<Self>, _: &Option<reporter::Sender>, giveload: Vec<u8>) {
+        let decoder = Decoder::new(giveload, MyCompression::get());
+        unsafe {
+            let raw = Box::into_raw(self);
+            let pid = Box::from_raw(raw);
+            let event;
+            if decoder.is_error() {
+                let error = decoder.get_error();
+                event = Event::Error {
+                    kind: worker::Error::Cql(error),
+                    pid,
+                }
+            } else {
+                event = Event::Response { decoder, pid };
+            }
+            let _ = (*raw).0.send(event);
+        }
+    }
+    fn send_error(self: Box<Self>, kind: worker::Error) {
+        unsafe {
+            let raw = Box::into_raw(self);
+            let pid = Box::from_raw(raw);
+            let event = Event::Error { kind, pid };
+            let _ = (*raw).0.send(event);
+        }
+    }
+}
+
+mod recorded {
+    use super::*;
+
+    rows!(
+        rows: Recorded {recorded: Option<(i64, String)>},
+        row: Row(
+            Checksum,
+            Status
+        ),
+        column_decoder: RecordedDecoder
+    );
+
+    impl Recorded {
+        pub fn decode(mut self) -> Self {
+            self.next();
+            self
+        }
+        pub fn finalize(self) -> Option<(i64, String)> {
+            self.recorded
+        }
+    }
+
+    impl RecordedDecoder for Checksum {
+        fn decode_column(start: usize, length: i32, acc: &mut Recorded) {
+            acc.recorded = Some((i64::decode(&acc.buffer()[start..], length as usize), String::new()));
+        }
+        fn handle_null(_: &mut Recorded) {
+            unreachable!()
+        }
+    }
+    impl RecordedDecoder for Status {
+        fn decode_column(start: usize, length: i32, acc: &mut Recorded) {
+            let status = String::from_utf8(acc.buffer()[start..(start + length as usize)].to_vec()).unwrap();
+            acc.recorded.as_mut().unwrap().1 = status;
+        }
+        fn handle_null(_: &mut Recorded) {}
+    }
+}
+use recorded::Recorded;
+
+/// Create a query frame to lookup for the record of a dmp file
+fn select_file(name: &str) -> Vec<u8> {
+    let Query(payload) = Query::new()
+        .version()
+        .flags(MyCompression::flag())
+        .stream(0)
+        .opcode()
+        .length()
+        .statement("SELECT checksum, status FROM tangle.dmp_file WHERE name = ?")
+        .consistency(Consistency::One)
+        .query_flags(SKIP_METADATA | VALUES)
+        .value_count(1)
+        .value(name)
+        .build(MyCompression::get());
+    payload
+}
+
+/// Create a query frame to record a processed dmp file
+fn insert_file(name: &str, checksum: u64, milestone: u64, status: &str) -> Vec<u8> {
+    let Query(payload) = Query::new()
+        .version()
+        .flags(MyCompression::flag())
+        .stream(0)
+        .opcode()
+        .length()
+        .statement("INSERT INTO tangle.dmp_file (name, checksum, milestone, status) VALUES (?, ?, ?, ?)")
+        .consistency(Consistency::One)
+        .query_flags(SKIP_METADATA | VALUES)
+        .value_count(4)
+        .value(name)
+        .value(checksum as i64)
+        .value(milestone as i64)
+        .value(status)
+        .build(MyCompression::get());
+    payload
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn milestone_from_file_name() {
+        assert_eq!(milestone("1050000.dmp"), Some(1_050_000));
+        assert_eq!(milestone("mainnet-2-1050000.dmp.gz"), Some(1_050_000));
+        assert_eq!(milestone("dump_6000.dmp.zst"), Some(6000));
+        // the milestone must be the last field of the name
+        assert_eq!(milestone("dump_6000_v2.dmp.zst"), None);
+        assert_eq!(milestone("v1050000.dmp"), None);
+        // the companion files of the importer and the files without milestone are ignored
+        assert_eq!(milestone("1050000.dmp.checkpoint"), None);
+        assert_eq!(milestone("1050000.dmp.rejected"), None);
+        assert_eq!(milestone("export.dmp"), None);
+        assert_eq!(milestone("1050000.txt"), None);
+    }
+}
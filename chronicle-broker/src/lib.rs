@@ -1,2 +1,13 @@
+pub mod backfill;
 pub mod broker;
+pub mod client;
+pub mod cone;
+pub mod curl;
+pub mod exporter;
 pub mod importer;
+pub mod metrics;
+pub mod retry;
+pub mod selective;
+pub mod snapshot;
+pub mod solidifier;
+pub mod tracker;
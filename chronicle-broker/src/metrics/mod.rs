@@ -0,0 +1,381 @@
+// The ingestion metrics of the broker, collected per peer (zmq or mqtt), reported to the launcher/dashboard and
+// served in the prometheus text format.
+use chronicle_common::traits::launcher::LauncherTx;
+use chronicle_storage::worker;
+use hyper::{
+    service::{
+        make_service_fn,
+        service_fn,
+    },
+    Body,
+    Method,
+    Request,
+    Response,
+    Server,
+    StatusCode,
+};
+use log::*;
+use serde::Serialize;
+use std::{
+    collections::{
+        BTreeMap,
+        HashMap,
+    },
+    convert::Infallible,
+    fmt::Write,
+    net::SocketAddr,
+    sync::{
+        atomic::{
+            AtomicU64,
+            Ordering,
+        },
+        Arc,
+        Mutex,
+    },
+    time::{
+        Duration,
+        SystemTime,
+    },
+};
+use tokio::sync::watch;
+
+/// The default interval (in seconds) to report the metrics to the launcher/dashboard.
+pub const DEFAULT_REPORT_INTERVAL: u64 = 10;
+// the upper bounds (in milliseconds) of the insert latency histogram buckets, the last bucket is +Inf.
+const LATENCY_BUCKETS: [u64; 11] = [1, 5, 10, 25, 50, 100, 250, 500, 1_000, 2_500, 5_000];
+
+/// The metrics of a peer, updated by its worker.
+#[derive(Default)]
+pub struct PeerMetrics {
+    peer: String,
+    topic: String,
+    received: AtomicU64,
+    stored: AtomicU64,
+    failed: AtomicU64,
+    rejected: AtomicU64,
+    duplicates: AtomicU64,
+    latency_buckets: [AtomicU64; 12],
+    latency_sum_ms: AtomicU64,
+    errors: Mutex<BTreeMap<String, u64>>,
+    // the unix timestamp (in seconds) of the last received message, zero if none.
+    last_message: AtomicU64,
+}
+
+impl PeerMetrics {
+    pub fn received(&self) {
+        self.received.fetch_add(1, Ordering::Relaxed);
+        self.last_message.store(now(), Ordering::Relaxed);
+    }
+    /// A transaction rejected by the hash verification or the parser.
+    pub fn rejected(&self) {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+    /// A transaction received again from the peer while it's in flight or recently stored, it's not inserted again.
+    pub fn duplicate(&self) {
+        self.duplicates.fetch_add(1, Ordering::Relaxed);
+    }
+    /// An error response of an insert query, labeled by the cql error code (or the worker error).
+    pub fn error(&self, error: &worker::Error) {
+        let code = match error {
+            worker::Error::Cql(cql_error) => format!("{:?}", cql_error.code),
+            worker::Error::Io(_) => "Io".to_string(),
+            worker::Error::Overload => "Overload".to_string(),
+            worker::Error::Lost => "Lost".to_string(),
+            worker::Error::NoRing => "NoRing".to_string(),
+        };
+        *self.errors.lock().unwrap().entry(code).or_insert(0) += 1;
+    }
+    /// All the inserts of a transaction got responded, latency is measured from sending the first insert.
+    pub fn inserted(&self, latency: Duration, stored: bool) {
+        if stored {
+            self.stored.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.failed.fetch_add(1, Ordering::Relaxed);
+        }
+        let millis = latency.as_millis() as u64;
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| millis <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.latency_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.latency_sum_ms.fetch_add(millis, Ordering::Relaxed);
+    }
+    fn labels(&self) -> String {
+        format!("peer=\"{}\",topic=\"{}\"", self.peer, self.topic)
+    }
+    fn snapshot(&self) -> PeerSnapshot {
+        let inserted = self.stored.load(Ordering::Relaxed) + self.failed.load(Ordering::Relaxed);
+        let last_message = self.last_message.load(Ordering::Relaxed);
+        PeerSnapshot {
+            peer: self.peer.clone(),
+            topic: self.topic.clone(),
+            received: self.received.load(Ordering::Relaxed),
+            messages_per_sec: 0.0,
+            stored: self.stored.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            duplicates: self.duplicates.load(Ordering::Relaxed),
+            avg_latency_ms: if inserted == 0 {
+                0.0
+            } else {
+                self.latency_sum_ms.load(Ordering::Relaxed) as f64 / inserted as f64
+            },
+            errors: self.errors.lock().unwrap().clone(),
+            seconds_since_last_message: if last_message == 0 {
+                None
+            } else {
+                Some(now().saturating_sub(last_message))
+            },
+        }
+    }
+}
+
+/// The metrics of a peer as reported to the dashboard.
+#[derive(Serialize, Debug)]
+pub struct PeerSnapshot {
+    pub peer: String,
+    pub topic: String,
+    pub received: u64,
+    /// the received messages per second since the previous report.
+    pub messages_per_sec: f64,
+    pub stored: u64,
+    pub failed: u64,
+    pub rejected: u64,
+    /// the transactions received again (the dedup hits).
+    pub duplicates: u64,
+    pub avg_latency_ms: f64,
+    pub errors: BTreeMap<String, u64>,
+    pub seconds_since_last_message: Option<u64>,
+}
+
+/// The registry of the peers metrics, shared by the broker supervisor, the reporter and the prometheus endpoint.
+#[derive(Clone, Default)]
+pub struct Metrics(Arc<Mutex<Vec<Arc<PeerMetrics>>>>);
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+    /// Register the metrics of a peer.
+    pub fn register(&self, peer: &str, topic: &str) -> Arc<PeerMetrics> {
+        let peer_metrics = Arc::new(PeerMetrics {
+            peer: peer.to_string(),
+            topic: topic.to_string(),
+            ..Default::default()
+        });
+        self.0.lock().unwrap().push(peer_metrics.clone());
+        peer_metrics
+    }
+    pub fn snapshot(&self) -> Vec<PeerSnapshot> {
+        self.0.lock().unwrap().iter().map(|peer| peer.snapshot()).collect()
+    }
+    /// Render the metrics in the prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let peers = self.0.lock().unwrap();
+        let mut text = String::new();
+        let counters: [(&str, &str, fn(&PeerMetrics) -> &AtomicU64); 5] = [
+            ("messages_received_total", "The received messages.", |p| &p.received),
+            ("transactions_stored_total", "The stored transactions.", |p| &p.stored),
+            (
+                "transactions_failed_total",
+                "The transactions failed to be stored.",
+                |p| &p.failed,
+            ),
+            (
+                "transactions_rejected_total",
+                "The rejected (invalid) transactions.",
+                |p| &p.rejected,
+            ),
+            (
+                "transactions_duplicate_total",
+                "The transactions received again while in flight or recently stored.",
+                |p| &p.duplicates,
+            ),
+        ];
+        for (name, help, counter) in counters.iter() {
+            header(&mut text, name, help, "counter");
+            for peer in peers.iter() {
+                let value = counter(peer).load(Ordering::Relaxed);
+                let _ = writeln!(text, "chronicle_broker_{}{{{}}} {}", name, peer.labels(), value);
+            }
+        }
+        header(
+            &mut text,
+            "insert_errors_total",
+            "The error responses of the insert queries.",
+            "counter",
+        );
+        for peer in peers.iter() {
+            for (code, count) in peer.errors.lock().unwrap().iter() {
+                let _ = writeln!(
+                    text,
+                    "chronicle_broker_insert_errors_total{{{},code=\"{}\"}} {}",
+                    peer.labels(),
+                    code,
+                    count
+                );
+            }
+        }
+        header(
+            &mut text,
+            "insert_latency_seconds",
+            "The latency of storing a transaction.",
+            "histogram",
+        );
+        for peer in peers.iter() {
+            let mut cumulative = 0;
+            for (bucket, count) in peer.latency_buckets.iter().enumerate() {
+                cumulative += count.load(Ordering::Relaxed);
+                let le = match LATENCY_BUCKETS.get(bucket) {
+                    Some(bound) => (*bound as f64 / 1000.0).to_string(),
+                    None => "+Inf".to_string(),
+                };
+                let _ = writeln!(
+                    text,
+                    "chronicle_broker_insert_latency_seconds_bucket{{{},le=\"{}\"}} {}",
+                    peer.labels(),
+                    le,
+                    cumulative
+                );
+            }
+            let sum = peer.latency_sum_ms.load(Ordering::Relaxed) as f64 / 1000.0;
+            let _ = writeln!(
+                text,
+                "chronicle_broker_insert_latency_seconds_sum{{{}}} {}",
+                peer.labels(),
+                sum
+            );
+            let _ = writeln!(
+                text,
+                "chronicle_broker_insert_latency_seconds_count{{{}}} {}",
+                peer.labels(),
+                cumulative
+            );
+        }
+        header(
+            &mut text,
+            "seconds_since_last_message",
+            "The seconds since the last received message.",
+            "gauge",
+        );
+        for peer in peers.iter() {
+            let last_message = peer.last_message.load(Ordering::Relaxed);
+            if last_message != 0 {
+                let _ = writeln!(
+                    text,
+                    "chronicle_broker_seconds_since_last_message{{{}}} {}",
+                    peer.labels(),
+                    now().saturating_sub(last_message)
+                );
+            }
+        }
+        text
+    }
+    /// Report the metrics to the launcher (which forwards them to the dashboards) every interval seconds,
+    /// until the stop sender is dropped.
+    pub async fn report(self, mut launcher_tx: Box<dyn LauncherTx>, interval: u64, mut stop: watch::Receiver<()>) {
+        let mut received = HashMap::new();
+        loop {
+            tokio::select! {
+                _ = tokio::time::delay_for(Duration::from_secs(interval)) => {}
+                _ = stopped(&mut stop) => break,
+            }
+            let mut snapshot = self.snapshot();
+            for peer in snapshot.iter_mut() {
+                let key = (peer.peer.clone(), peer.topic.clone());
+                let previous = received.insert(key, peer.received).unwrap_or(0);
+                peer.messages_per_sec = (peer.received - previous) as f64 / interval as f64;
+            }
+            launcher_tx.app_metrics("broker".to_string(), serde_json::to_string(&snapshot).unwrap());
+        }
+    }
+    /// Serve the metrics at GET /metrics, until the stop sender is dropped.
+    pub async fn serve(self, address: SocketAddr, mut stop: watch::Receiver<()>) {
+        let make_service = make_service_fn(move |_| {
+            let metrics = self.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let metrics = metrics.clone();
+                    async move { Ok::<_, Infallible>(metrics.respond(request)) }
+                }))
+            }
+        });
+        match Server::try_bind(&address) {
+            Ok(builder) => {
+                info!("metrics: serving prometheus metrics at http://{}/metrics", address);
+                let server = builder
+                    .serve(make_service)
+                    .with_graceful_shutdown(async move { stopped(&mut stop).await });
+                if let Err(error) = server.await {
+                    error!("metrics: server error: {}", error);
+                }
+            }
+            Err(error) => error!("metrics: unable to bind {}, error: {}", address, error),
+        }
+    }
+    fn respond(&self, request: Request<Body>) -> Response<Body> {
+        if request.method() == Method::GET && request.uri().path() == "/metrics" {
+            Response::builder()
+                .header("Content-Type", "text/plain; version=0.0.4")
+                .body(Body::from(self.render()))
+                .unwrap()
+        } else {
+            Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::empty())
+                .unwrap()
+        }
+    }
+}
+
+fn header(text: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(text, "# HELP chronicle_broker_{} {}", name, help);
+    let _ = writeln!(text, "# TYPE chronicle_broker_{} {}", name, kind);
+}
+
+/// Resolve once the stop sender is dropped.
+pub(crate) async fn stopped(stop: &mut watch::Receiver<()>) {
+    while stop.recv().await.is_some() {}
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_prometheus_text() {
+        let metrics = Metrics::new();
+        let peer = metrics.register("tcp://localhost:5556", "trytes");
+        peer.received();
+        peer.inserted(Duration::from_millis(3), true);
+        peer.error(&worker::Error::Lost);
+        peer.duplicate();
+        let text = metrics.render();
+        let labels = r#"peer="tcp://localhost:5556",topic="trytes""#;
+        assert!(text.contains(&format!("chronicle_broker_messages_received_total{{{}}} 1", labels)));
+        assert!(text.contains(&format!(
+            "chronicle_broker_transactions_duplicate_total{{{}}} 1",
+            labels
+        )));
+        assert!(text.contains(&format!(
+            "chronicle_broker_insert_errors_total{{{},code=\"Lost\"}} 1",
+            labels
+        )));
+        assert!(text.contains(&format!(
+            "chronicle_broker_insert_latency_seconds_bucket{{{},le=\"0.005\"}} 1",
+            labels
+        )));
+        assert!(text.contains(&format!(
+            "chronicle_broker_insert_latency_seconds_count{{{}}} 1",
+            labels
+        )));
+        assert_eq!(metrics.snapshot()[0].stored, 1);
+    }
+}
@@ -0,0 +1,47 @@
+// The failed queries are retried with an exponential backoff, each request has its own retry budget so the transient
+// errors spread over a long job (ie an import) don't add up to abort it.
+use chronicle_storage::{
+    ring::{
+        Ring,
+        Token,
+    },
+    stage::reporter,
+};
+use std::time::Duration;
+use tokio::time::delay_for;
+
+/// The delay before the first retry of a query.
+pub const RETRY_BASE_DELAY: Duration = Duration::from_millis(100);
+/// The max delay before a retry.
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// The delay before the nth retry (starting from 1), it's doubled on every retry up to MAX_RETRY_DELAY.
+pub fn backoff(attempt: u32) -> Duration {
+    let factor = 1u32.checked_shl(attempt.saturating_sub(1)).unwrap_or(u32::MAX);
+    RETRY_BASE_DELAY
+        .checked_mul(factor)
+        .map_or(MAX_RETRY_DELAY, |delay| delay.min(MAX_RETRY_DELAY))
+}
+
+/// Send the request again once its backoff elapsed, using send_global_random_replica strategy.
+/// NOTE: the caller keeps processing the other responses meanwhile.
+pub fn retry(token: Token, request: reporter::Event, attempt: u32) {
+    tokio::spawn(async move {
+        delay_for(backoff(attempt)).await;
+        Ring::send_global_random_replica(token, request);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exponential_backoff() {
+        assert_eq!(backoff(1), RETRY_BASE_DELAY);
+        assert_eq!(backoff(2), RETRY_BASE_DELAY * 2);
+        assert_eq!(backoff(4), RETRY_BASE_DELAY * 8);
+        assert_eq!(backoff(10), MAX_RETRY_DELAY);
+        assert_eq!(backoff(u32::MAX), MAX_RETRY_DELAY);
+    }
+}
@@ -0,0 +1,332 @@
+// Selective permanode: the rules that decide per transaction whether to store it or not.
+use chronicle_storage::transaction::Transaction;
+use log::*;
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+        VecDeque,
+    },
+    sync::{
+        Arc,
+        Mutex,
+        RwLock,
+    },
+};
+
+/// The default number of bundles a Selector keeps track of (for both held and selected bundles).
+pub const DEFAULT_MAX_BUNDLES: usize = 10_000;
+
+/// The selective rules, a transaction is selected if any rule matches:
+/// - its address is in the address allow-list.
+/// - its tag is in the tag allow-list.
+/// - its absolute value is greater than or equal to the value threshold.
+/// if whole_bundle is enabled, all the members of a bundle are selected once any member got selected.
+#[derive(Default, Clone, Debug)]
+pub struct Rules {
+    addresses: HashSet<String>,
+    tags: HashSet<String>,
+    min_value: Option<u64>,
+    whole_bundle: bool,
+}
+
+impl Rules {
+    pub fn new() -> Self {
+        Rules::default()
+    }
+    /// Add addresses to the allow-list, the checksum (if any) is ignored.
+    pub fn addresses(mut self, addresses: Vec<String>) -> Self {
+        for mut address in addresses {
+            address.truncate(81);
+            self.addresses.insert(address);
+        }
+        self
+    }
+    /// Add tags to the allow-list, short tags are padded with 9s.
+    pub fn tags(mut self, tags: Vec<String>) -> Self {
+        for tag in tags {
+            self.tags.insert(format!("{:9<27}", tag));
+        }
+        self
+    }
+    pub fn min_value(mut self, min_value: u64) -> Self {
+        self.min_value.replace(min_value);
+        self
+    }
+    pub fn whole_bundle(mut self, whole_bundle: bool) -> Self {
+        self.whole_bundle = whole_bundle;
+        self
+    }
+    /// Check whether the transaction matches any rule.
+    pub fn is_match(&self, transaction: &Transaction) -> bool {
+        if self.addresses.contains(transaction.address()) || self.tags.contains(transaction.tag()) {
+            return true;
+        }
+        if let Some(min_value) = self.min_value {
+            let value = transaction.value();
+            value != 0 && value.abs() as u64 >= min_value
+        } else {
+            false
+        }
+    }
+}
+
+/// A shared handle to the rules, cloned into every peer worker and importer, and reloadable at runtime.
+#[derive(Clone, Default)]
+pub struct SelectiveRules(Arc<RwLock<Rules>>);
+
+impl SelectiveRules {
+    pub fn new(rules: Rules) -> Self {
+        SelectiveRules(Arc::new(RwLock::new(rules)))
+    }
+    /// Replace the rules, the new rules apply to the next processed transaction.
+    pub fn reload(&self, rules: Rules) {
+        *self.0.write().unwrap() = rules;
+    }
+}
+
+/// A transaction held until its bundle got selected.
+pub struct HeldTransaction {
+    pub hash: String,
+    pub trytes: String,
+    pub milestone: Option<u64>,
+    /// The offset of its dmp line and the milestone inherited by the line, if it's read from a dmp file.
+    pub position: Option<(u64, u64)>,
+}
+
+pub enum Decision {
+    /// Store the transaction, in addition to the held members of its bundle (if any).
+    Store(Vec<HeldTransaction>),
+    /// Don't store the transaction (it's dropped or held).
+    Skip,
+}
+
+/// Applies the rules to a stream of transactions, owned by the importer or shared by the peer workers.
+/// The bundle caches are bounded, the oldest bundles are evicted first.
+pub struct Selector {
+    rules: SelectiveRules,
+    max_bundles: usize,
+    held: HashMap<String, Vec<HeldTransaction>>,
+    held_order: VecDeque<String>,
+    selected: HashSet<String>,
+    selected_order: VecDeque<String>,
+    evicted: u64,
+}
+
+impl Selector {
+    pub fn new(rules: SelectiveRules, max_bundles: usize) -> Self {
+        Selector {
+            rules,
+            max_bundles: max_bundles.max(1),
+            held: HashMap::new(),
+            held_order: VecDeque::new(),
+            selected: HashSet::new(),
+            selected_order: VecDeque::new(),
+            evicted: 0,
+        }
+    }
+    /// The number of bundles evicted from the bundle caches so far.
+    pub fn evicted(&self) -> u64 {
+        self.evicted
+    }
+    /// The earliest position of the held transactions read from a dmp file (if any), the importer must not
+    /// checkpoint past it.
+    pub fn oldest_held_position(&self) -> Option<(u64, u64)> {
+        // the members of a bundle are held in the order of the file
+        self.held
+            .values()
+            .filter_map(|members| members.first().and_then(|held| held.position))
+            .min()
+    }
+    pub fn select(&mut self, transaction: &Transaction, milestone: Option<u64>) -> Decision {
+        self.select_at(transaction, milestone, None)
+    }
+    /// Select the transaction read at the position of a dmp file, it's kept with the transaction if it's held.
+    pub fn select_at(
+        &mut self,
+        transaction: &Transaction,
+        milestone: Option<u64>,
+        position: Option<(u64, u64)>,
+    ) -> Decision {
+        let rules = self.rules.0.read().unwrap();
+        if !rules.whole_bundle {
+            return if rules.is_match(transaction) {
+                Decision::Store(Vec::new())
+            } else {
+                Decision::Skip
+            };
+        }
+        let bundle = transaction.bundle();
+        if self.selected.contains(bundle) {
+            Decision::Store(Vec::new())
+        } else if rules.is_match(transaction) {
+            drop(rules);
+            // select the bundle and release its held members
+            if self.selected_order.len() == self.max_bundles {
+                let oldest = self.selected_order.pop_front().unwrap();
+                self.selected.remove(&oldest);
+                self.evicted += 1;
+                debug!(
+                    "selector: evicted selected bundle: {}, evicted so far: {}",
+                    oldest, self.evicted
+                );
+            }
+            self.selected.insert(bundle.to_string());
+            self.selected_order.push_back(bundle.to_string());
+            Decision::Store(self.held.remove(bundle).unwrap_or_default())
+        } else {
+            drop(rules);
+            // hold the transaction until any member of its bundle got selected
+            let held = HeldTransaction {
+                hash: transaction.hash().to_string(),
+                trytes: transaction.trytes().to_string(),
+                milestone,
+                position,
+            };
+            if let Some(members) = self.held.get_mut(bundle) {
+                members.push(held);
+            } else {
+                // released bundles are still in held_order, they get removed lazily
+                while self.held.len() >= self.max_bundles {
+                    let oldest = self.held_order.pop_front().unwrap();
+                    if let Some(members) = self.held.remove(&oldest) {
+                        self.evicted += 1;
+                        warn!(
+                            "selector: evicted held bundle: {}, dropped transactions: {}, evicted so far: {}",
+                            oldest,
+                            members.len(),
+                            self.evicted
+                        );
+                    }
+                }
+                if self.held_order.len() >= 2 * self.max_bundles {
+                    let held = &self.held;
+                    self.held_order.retain(|bundle| held.contains_key(bundle));
+                }
+                self.held.insert(bundle.to_string(), vec![held]);
+                self.held_order.push_back(bundle.to_string());
+            }
+            Decision::Skip
+        }
+    }
+}
+
+/// A Selector shared by the peer workers, the members of a bundle received by different workers (or peers) are held
+/// and selected together.
+#[derive(Clone)]
+pub struct SharedSelector(Arc<Mutex<Selector>>);
+
+impl SharedSelector {
+    pub fn new(selector: Selector) -> Self {
+        SharedSelector(Arc::new(Mutex::new(selector)))
+    }
+    pub fn select(&self, transaction: &Transaction, milestone: Option<u64>) -> Decision {
+        self.0.lock().unwrap().select(transaction, milestone)
+    }
+    /// The number of bundles evicted from the bundle caches so far.
+    pub fn evicted(&self) -> u64 {
+        self.0.lock().unwrap().evicted()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chronicle_storage::transaction;
+
+    fn txtrytes(address: char, bundle: char) -> String {
+        let mut txtrytes = "9".repeat(2673);
+        txtrytes.replace_range(transaction::ADDRESS, &address.to_string().repeat(81));
+        txtrytes.replace_range(transaction::BUNDLE, &bundle.to_string().repeat(81));
+        txtrytes
+    }
+
+    fn select(selector: &mut Selector, hash: char, txtrytes: &str) -> Decision {
+        let hash = hash.to_string().repeat(81);
+        selector.select(&Transaction::parse(&hash, txtrytes).unwrap(), None)
+    }
+
+    #[test]
+    fn select_whole_bundle() {
+        let rules = Rules::new().addresses(vec!["A".repeat(90)]).whole_bundle(true);
+        let mut selector = Selector::new(SelectiveRules::new(rules), DEFAULT_MAX_BUNDLES);
+        // a member of bundle B is held until a matching member of its bundle shows up
+        assert!(matches!(
+            select(&mut selector, 'X', &txtrytes('C', 'B')),
+            Decision::Skip
+        ));
+        match select(&mut selector, 'Y', &txtrytes('A', 'B')) {
+            Decision::Store(released) => assert_eq!(released[0].hash, "X".repeat(81)),
+            Decision::Skip => panic!("matching transaction is skipped"),
+        }
+        // any later member of the selected bundle is stored
+        assert!(matches!(
+            select(&mut selector, 'Z', &txtrytes('D', 'B')),
+            Decision::Store(_)
+        ));
+        // reloading the rules applies to the next transaction
+        selector.rules.reload(Rules::new());
+        assert!(matches!(
+            select(&mut selector, 'W', &txtrytes('A', 'E')),
+            Decision::Skip
+        ));
+    }
+    #[test]
+    fn oldest_held_position() {
+        let rules = Rules::new().addresses(vec!["A".repeat(81)]).whole_bundle(true);
+        let mut selector = Selector::new(SelectiveRules::new(rules), DEFAULT_MAX_BUNDLES);
+        let (x, y, z) = ("X".repeat(81), "Y".repeat(81), "Z".repeat(81));
+        for (hash, bundle, position) in &[(&x, 'B', (10, 1)), (&y, 'C', (20, 1)), (&z, 'B', (30, 2))] {
+            let trytes = txtrytes('C', *bundle);
+            let transaction = Transaction::parse(hash, &trytes).unwrap();
+            assert!(matches!(
+                selector.select_at(&transaction, Some(1), Some(*position)),
+                Decision::Skip
+            ));
+        }
+        assert_eq!(selector.oldest_held_position(), Some((10, 1)));
+        // releasing bundle B moves the oldest held position to the held member of bundle C
+        let trytes = txtrytes('A', 'B');
+        match selector.select_at(&Transaction::parse(&x, &trytes).unwrap(), Some(2), Some((40, 2))) {
+            Decision::Store(released) => assert_eq!(released.len(), 2),
+            Decision::Skip => panic!("matching transaction is skipped"),
+        }
+        assert_eq!(selector.oldest_held_position(), Some((20, 1)));
+    }
+    #[test]
+    fn shared_selector_evictions() {
+        let rules = Rules::new().addresses(vec!["A".repeat(81)]).whole_bundle(true);
+        let selector = SharedSelector::new(Selector::new(SelectiveRules::new(rules), 1));
+        let other_worker = selector.clone();
+        let (x, y) = ("X".repeat(81), "Y".repeat(81));
+        // a member held through one handle is released through the other one
+        let trytes = txtrytes('C', 'B');
+        let held = Transaction::parse(&x, &trytes).unwrap();
+        assert!(matches!(selector.select(&held, None), Decision::Skip));
+        let trytes = txtrytes('A', 'B');
+        let matching = Transaction::parse(&y, &trytes).unwrap();
+        match other_worker.select(&matching, None) {
+            Decision::Store(released) => assert_eq!(released[0].hash, x),
+            Decision::Skip => panic!("matching transaction is skipped"),
+        }
+        assert_eq!(selector.evicted(), 0);
+        // the held bundle D is evicted by the held bundle E, and the selected bundle B by the selected bundle E
+        let trytes = txtrytes('C', 'D');
+        assert!(matches!(
+            selector.select(&Transaction::parse(&x, &trytes).unwrap(), None),
+            Decision::Skip
+        ));
+        let trytes = txtrytes('C', 'E');
+        assert!(matches!(
+            selector.select(&Transaction::parse(&x, &trytes).unwrap(), None),
+            Decision::Skip
+        ));
+        assert_eq!(selector.evicted(), 1);
+        let trytes = txtrytes('A', 'E');
+        assert!(matches!(
+            selector.select(&Transaction::parse(&y, &trytes).unwrap(), None),
+            Decision::Store(_)
+        ));
+        assert_eq!(other_worker.evicted(), 2);
+    }
+}
@@ -0,0 +1,519 @@
+// The snapshot job computes the ledger state (the balance of every address) at a milestone from the confirmed
+// input/output edges, and writes it in the IRI snapshot format (address;balance lines). The balances can start from
+// a base snapshot, in which case only the edges confirmed after the base milestone are applied. The edge table is
+// scanned by token range (like the exporter) and every range is paged, the confirmation of the edges of a page is
+// looked up in parallel.
+use crate::retry;
+use chronicle_common::actor;
+use chronicle_cql::{
+    compression::MyCompression,
+    frame::{
+        consistency::Consistency,
+        decoder::{
+            ColumnDecoder,
+            Decoder,
+            Frame,
+        },
+        header::Header,
+        query::Query,
+        queryflags::{
+            PAGE_SIZE,
+            PAGING_STATE,
+            SKIP_METADATA,
+            VALUES,
+        },
+    },
+    rows,
+};
+use chronicle_storage::{
+    ring::{
+        Ring,
+        Token,
+    },
+    stage::reporter,
+    transaction,
+    worker,
+};
+use log::*;
+use std::{
+    collections::{
+        BTreeMap,
+        HashMap,
+    },
+    error::Error,
+    fmt::Write,
+};
+use tokio::{
+    fs,
+    sync::mpsc,
+};
+
+type Sender = mpsc::UnboundedSender<Event>;
+type Receiver = mpsc::UnboundedReceiver<Event>;
+/// The total supply of IOTA, the sum of the balances of every ledger state.
+pub const TOTAL_SUPPLY: i64 = 2_779_530_283_277_761;
+/// The default number of edges fetched per page.
+pub const DEFAULT_PAGE_SIZE: i32 = 5000;
+/// The max number of confirmation lookups in flight.
+pub const MAX_IN_FLIGHT_LOOKUPS: usize = 256;
+// the max number of negative balances listed in the log.
+const MAX_LOGGED_NEGATIVE: usize = 10;
+
+actor!(SnapshotBuilder {
+    milestone: u64,
+    path: String,
+    base_path: String,
+    base_milestone: u64,
+    page_size: i32,
+    max_retries: usize
+});
+
+impl SnapshotBuilder {
+    pub fn build(self) -> Snapshot {
+        let (tx, rx) = mpsc::unbounded_channel::<Event>();
+        Snapshot {
+            milestone: self.milestone.unwrap(),
+            path: self.path.unwrap(),
+            base_path: self.base_path,
+            base_milestone: self.base_milestone.unwrap_or(0),
+            page_size: self.page_size.unwrap_or(DEFAULT_PAGE_SIZE),
+            max_retries: self.max_retries.unwrap(),
+            pids: Vec::with_capacity(MAX_IN_FLIGHT_LOOKUPS),
+            tx,
+            rx,
+        }
+    }
+}
+
+pub struct Snapshot {
+    milestone: u64,
+    path: String,
+    base_path: Option<String>,
+    base_milestone: u64,
+    page_size: i32,
+    max_retries: usize,
+    pids: Vec<Box<SnapshotId>>,
+    tx: Sender,
+    rx: Receiver,
+}
+
+/// The pid of a request, with the index of the looked up edge within its page.
+#[derive(Debug)]
+pub struct SnapshotId(Sender, usize);
+
+pub enum Event {
+    Response { decoder: Decoder, pid: Box<SnapshotId> },
+    Error { kind: worker::Error, pid: Box<SnapshotId> },
+}
+
+/// The summary of a written snapshot.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub milestone: u64,
+    /// the addresses with a non zero balance.
+    pub addresses: usize,
+    /// the applied input/output edges.
+    pub applied: usize,
+    /// the skipped edges, unconfirmed or confirmed after the milestone (or before the base milestone).
+    pub skipped: usize,
+    /// the sum of the balances, it should be equal to the total supply.
+    pub supply: i64,
+    /// the addresses with a negative balance, it should be zero.
+    pub negative: usize,
+}
+
+impl Report {
+    /// Returns true if the total supply invariant holds.
+    pub fn is_consistent(&self) -> bool {
+        self.supply == TOTAL_SUPPLY && self.negative == 0
+    }
+}
+
+impl Snapshot {
+    pub async fn run(mut self) -> Result<Report, Box<dyn Error>> {
+        let mut balances = match self.base_path.as_ref() {
+            Some(base_path) => parse(&fs::read_to_string(base_path).await?)?,
+            None => HashMap::new(),
+        };
+        let ranges = Ring::token_ranges();
+        if ranges.is_empty() {
+            return Err("the ring is not built yet".into());
+        }
+        let mut report = Report {
+            milestone: self.milestone,
+            ..Default::default()
+        };
+        for (scanned, range) in ranges.iter().enumerate() {
+            for kind in &["output", "input"] {
+                let mut paging_state = None;
+                loop {
+                    let payload = select_edges(*range, kind, self.page_size, &paging_state);
+                    let decoder = self.request(range.1, payload).await?;
+                    let mut page = Edges::new(decoder, Vec::new()).decode();
+                    paging_state = page.take_paging_state();
+                    let edges = page.finalize();
+                    let confirmed = self.confirmed(&edges).await?;
+                    for ((address, _, value), confirmed) in edges.into_iter().zip(confirmed) {
+                        if confirmed {
+                            *balances.entry(address).or_insert(0) += value;
+                            report.applied += 1;
+                        } else {
+                            report.skipped += 1;
+                        }
+                    }
+                    if paging_state.is_none() {
+                        break;
+                    }
+                }
+            }
+            info!(
+                "snapshot: milestone: {}, scanned {}/{} token ranges",
+                self.milestone,
+                scanned + 1,
+                ranges.len()
+            );
+        }
+        let (supply, negative) = verify(&balances);
+        report.supply = supply;
+        report.negative = negative.len();
+        if supply != TOTAL_SUPPLY {
+            error!(
+                "snapshot: milestone: {}, supply: {} differs from the total supply by: {}",
+                self.milestone,
+                supply,
+                supply - TOTAL_SUPPLY
+            );
+        }
+        for (address, balance) in negative.iter().take(MAX_LOGGED_NEGATIVE) {
+            error!(
+                "snapshot: milestone: {}, address: {} has a negative balance: {}",
+                self.milestone, address, balance
+            );
+        }
+        let (text, addresses) = render(&balances);
+        report.addresses = addresses;
+        fs::write(&self.path, text).await?;
+        Ok(report)
+    }
+
+    /// Check whether the transactions of the edges are confirmed within (base_milestone, milestone], the lookups
+    /// are sent in parallel up to MAX_IN_FLIGHT_LOOKUPS, and each one has its own retry budget of max_retries.
+    async fn confirmed(&mut self, edges: &[(String, String, i64)]) -> Result<Vec<bool>, worker::Error> {
+        let mut confirmed = vec![false; edges.len()];
+        let mut attempts = vec![0; edges.len()];
+        let mut next = 0;
+        let mut pending = 0;
+        while next < edges.len() || pending > 0 {
+            while next < edges.len() && pending < MAX_IN_FLIGHT_LOOKUPS {
+                let hash = &edges[next].1;
+                let request = reporter::Event::Request {
+                    payload: select_milestone(hash),
+                    worker: self.pid(next),
+                };
+                Ring::send_local_random_replica(transaction::token(hash.as_bytes()), request);
+                next += 1;
+                pending += 1;
+            }
+            match self.rx.recv().await.unwrap() {
+                Event::Response { decoder, pid } => {
+                    if let Some(milestone) = Confirmed::new(decoder, None).decode().finalize() {
+                        confirmed[pid.1] = milestone > self.base_milestone as i64 && milestone <= self.milestone as i64;
+                    }
+                    self.pids.push(pid);
+                    pending -= 1;
+                }
+                Event::Error { kind, pid } => {
+                    let index = pid.1;
+                    attempts[index] += 1;
+                    if attempts[index] > self.max_retries {
+                        return Err(kind);
+                    }
+                    let hash = &edges[index].1;
+                    let request = reporter::Event::Request {
+                        payload: select_milestone(hash),
+                        worker: pid,
+                    };
+                    retry::retry(transaction::token(hash.as_bytes()), request, attempts[index] as u32);
+                }
+            }
+        }
+        Ok(confirmed)
+    }
+
+    /// Send the request to its token owner and await the response, the request has its own retry budget of
+    /// max_retries, the errors are retried with backoff using send_global_random_replica strategy (like the importer).
+    async fn request(&mut self, token: Token, payload: Vec<u8>) -> Result<Decoder, worker::Error> {
+        let request = reporter::Event::Request {
+            payload: payload.clone(),
+            worker: self.pid(0),
+        };
+        Ring::send_local_random_replica(token, request);
+        let mut attempt = 0;
+        loop {
+            match self.rx.recv().await.unwrap() {
+                Event::Response { decoder, pid } => {
+                    self.pids.push(pid);
+                    return Ok(decoder);
+                }
+                Event::Error { kind, pid } => {
+                    attempt += 1;
+                    if attempt > self.max_retries {
+                        self.pids.push(pid);
+                        return Err(kind);
+                    }
+                    let request = reporter::Event::Request {
+                        payload: payload.clone(),
+                        worker: pid,
+                    };
+                    retry::retry(token, request, attempt as u32);
+                }
+            }
+        }
+    }
+
+    fn pid(&mut self, index: usize) -> Box<SnapshotId> {
+        let mut pid = match self.pids.pop() {
+            Some(pid) => pid,
+            None => Box::new(SnapshotId(self.tx.clone(), 0)),
+        };
+        pid.1 = index;
+        pid
+    }
+}
+
+/// Parse a snapshot in the IRI format (address;balance lines).
+pub fn parse(text: &str) -> Result<HashMap<String, i64>, Box<dyn Error>> {
+    let mut balances = HashMap::new();
+    for (number, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut fields = line.trim().split(';');
+        match (fields.next(), fields.next().map(str::parse::<i64>)) {
+            (Some(address), Some(Ok(balance))) if address.len() == transaction::HASH_TRYTES_LENGTH => {
+                *balances.entry(address.to_string()).or_insert(0) += balance;
+            }
+            _ => return Err(format!("invalid snapshot line: {}", number + 1).into()),
+        }
+    }
+    Ok(balances)
+}
+
+/// Render the non zero balances in the IRI snapshot format sorted by address, returns the text and the number of
+/// addresses.
+pub fn render(balances: &HashMap<String, i64>) -> (String, usize) {
+    let sorted: BTreeMap<&String, &i64> = balances.iter().filter(|(_, balance)| **balance != 0).collect();
+    let mut text = String::with_capacity(sorted.len() * 100);
+    for (address, balance) in sorted.iter() {
+        writeln!(&mut text, "{};{}", address, balance).unwrap();
+    }
+    (text, sorted.len())
+}
+
+/// Returns the sum of the balances, and the addresses with a negative balance.
+pub fn verify(balances: &HashMap<String, i64>) -> (i64, Vec<(&String, i64)>) {
+    let supply = balances.values().sum();
+    let mut negative: Vec<(&String, i64)> = balances
+        .iter()
+        .filter(|(_, balance)| **balance < 0)
+        .map(|(address, balance)| (address, *balance))
+        .collect();
+    negative.sort();
+    (supply, negative)
+}
+
+impl worker::Worker for SnapshotId {
+    fn send_response(self: Box<Self>, _: &Option<reporter::Sender>, giveload: Vec<u8>) {
+        let decoder = Decoder::new(giveload, MyCompression::get());
+        unsafe {
+            let raw = Box::into_raw(self);
+            let pid = Box::from_raw(raw);
+            let event;
+            if decoder.is_error() {
+                let error = decoder.get_error();
+                event = Event::Error {
+                    kind: worker::Error::Cql(error),
+                    pid,
+                }
+            } else {
+                event = Event::Response { decoder, pid };
+            }
+            let _ = (*raw).0.send(event);
+        }
+    }
+    fn send_error(self: Box<Self>, kind: worker::Error) {
+        unsafe {
+            let raw = Box::into_raw(self);
+            let pid = Box::from_raw(raw);
+            let event = Event::Error { kind, pid };
+            let _ = (*raw).0.send(event);
+        }
+    }
+}
+
+mod edges {
+    use super::*;
+
+    rows!(
+        rows: Edges {edges: Vec<(String, String, i64)>},
+        row: Row(
+            Vertex,
+            Tx,
+            Value
+        ),
+        column_decoder: EdgesDecoder
+    );
+
+    pub trait Rows {
+        fn decode(self) -> Self;
+        fn finalize(self) -> Vec<(String, String, i64)>;
+    }
+
+    impl Rows for Edges {
+        fn decode(mut self) -> Self {
+            while let Some(_) = self.next() {}
+            self
+        }
+        fn finalize(self) -> Vec<(String, String, i64)> {
+            self.edges
+        }
+    }
+
+    impl Edges {
+        pub fn take_paging_state(&mut self) -> Option<Vec<u8>> {
+            self.metadata.take_paging_state()
+        }
+    }
+
+    impl EdgesDecoder for Vertex {
+        fn decode_column(start: usize, length: i32, acc: &mut Edges) {
+            let vertex = String::from_utf8(acc.buffer()[start..(start + length as usize)].to_vec()).unwrap();
+            acc.edges.push((vertex, String::new(), 0));
+        }
+        fn handle_null(_: &mut Edges) {
+            unreachable!()
+        }
+    }
+    impl EdgesDecoder for Tx {
+        fn decode_column(start: usize, length: i32, acc: &mut Edges) {
+            let tx = String::from_utf8(acc.buffer()[start..(start + length as usize)].to_vec()).unwrap();
+            acc.edges.last_mut().unwrap().1 = tx;
+        }
+        fn handle_null(_: &mut Edges) {
+            unreachable!()
+        }
+    }
+    impl EdgesDecoder for Value {
+        fn decode_column(start: usize, length: i32, acc: &mut Edges) {
+            acc.edges.last_mut().unwrap().2 = i64::decode(&acc.buffer()[start..], length as usize);
+        }
+        fn handle_null(_: &mut Edges) {}
+    }
+}
+
+mod confirmed {
+    use super::*;
+
+    rows!(
+        rows: Confirmed {milestone: Option<i64>},
+        row: Row(
+            Milestone
+        ),
+        column_decoder: ConfirmedDecoder
+    );
+
+    impl Confirmed {
+        pub fn decode(mut self) -> Self {
+            self.next();
+            self
+        }
+        pub fn finalize(self) -> Option<i64> {
+            self.milestone
+        }
+    }
+
+    impl ConfirmedDecoder for Milestone {
+        fn decode_column(start: usize, length: i32, acc: &mut Confirmed) {
+            acc.milestone = Some(i64::decode(&acc.buffer()[start..], length as usize));
+        }
+        fn handle_null(acc: &mut Confirmed) {
+            acc.milestone = None;
+        }
+    }
+}
+use confirmed::Confirmed;
+use edges::{
+    Edges,
+    Rows,
+};
+
+/// Create a query frame to fetch a page of the input or output edges within the token range (left, right]
+fn select_edges((left, right): (Token, Token), kind: &str, page_size: i32, paging_state: &Option<Vec<u8>>) -> Vec<u8> {
+    let query_flags = match paging_state {
+        Some(_) => SKIP_METADATA | VALUES | PAGE_SIZE | PAGING_STATE,
+        None => SKIP_METADATA | VALUES | PAGE_SIZE,
+    };
+    let Query(payload) = Query::new()
+        .version()
+        .flags(MyCompression::flag())
+        .stream(0)
+        .opcode()
+        .length()
+        .statement(SELECT_EDGES_QUERY)
+        .consistency(Consistency::One)
+        .query_flags(query_flags)
+        .value_count(3)
+        .value(left)
+        .value(right)
+        .value(kind)
+        .page_size(page_size)
+        .paging_state(paging_state)
+        .build(MyCompression::get());
+    payload
+}
+
+// kind is the first clustering column, so the filtering is done within the partitions of the token range.
+const SELECT_EDGES_QUERY: &str = r#"
+  SELECT vertex, tx, value FROM tangle.edge WHERE token(vertex) > ? AND token(vertex) <= ? AND kind = ?
+  ALLOW FILTERING;
+"#;
+
+/// Create a query frame to lookup for the milestone column of a transaction
+fn select_milestone(hash: &str) -> Vec<u8> {
+    let Query(payload) = Query::new()
+        .version()
+        .flags(MyCompression::flag())
+        .stream(0)
+        .opcode()
+        .length()
+        .statement("SELECT milestone FROM tangle.transaction WHERE hash = ?")
+        .consistency(Consistency::One)
+        .query_flags(SKIP_METADATA | VALUES)
+        .value_count(1)
+        .value(hash)
+        .build(MyCompression::get());
+    payload
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_supply_invariant() {
+        let (a, b) = ("A".repeat(81), "B".repeat(81));
+        let text = format!("{};{}\n{};0\n", a, TOTAL_SUPPLY, b);
+        let mut balances = parse(&text).unwrap();
+        assert_eq!(verify(&balances), (TOTAL_SUPPLY, Vec::new()));
+        // a confirmed transfer keeps the supply, the zero balances are not rendered
+        *balances.get_mut(&a).unwrap() -= 10;
+        *balances.get_mut(&b).unwrap() += 10;
+        let (text, addresses) = render(&balances);
+        assert_eq!(addresses, 2);
+        assert_eq!(text, format!("{};{}\n{};10\n", a, TOTAL_SUPPLY - 10, b));
+        // a missing output breaks the invariant
+        *balances.get_mut(&b).unwrap() -= 20;
+        assert_eq!(verify(&balances), (TOTAL_SUPPLY - 20, vec![(&b, -10)]));
+        assert!(parse("ADDRESS;1").is_err());
+    }
+}
@@ -0,0 +1,221 @@
+// The solidifier walks the trunk/branch references from the confirmed milestones through tangle.transaction,
+// it fetches the missing transactions from a node and confirms the walked cone (milestone column).
+// The milestones are solidified one by one in index order, and the last solidified one is saved in the progress file.
+use crate::{
+    cone::{
+        Progress,
+        Walker,
+        WalkerBuilder,
+    },
+    metrics::stopped,
+    tracker::TrackerTx,
+};
+use chronicle_common::actor;
+use chronicle_cql::frame::batch::BatchTypes;
+use log::*;
+use std::{
+    collections::HashMap,
+    error::Error,
+    time::Duration,
+};
+use tokio::{
+    fs,
+    sync::watch,
+};
+
+/// The default interval (in seconds) to poll the node for the latest solid milestone.
+pub const DEFAULT_POLL_INTERVAL: u64 = 10;
+// the max number of milestone hashes looked up from the node at once.
+const MAX_MILESTONES_PER_LOOKUP: u64 = 100;
+/// The default address of the coordinator, whose tail transactions are the milestones.
+pub const DEFAULT_COORDINATOR: &str =
+    "EQSAUZXULTTYZCLNJNTXQTQHOMOFZERHTCGTXOLTVAHKSA9OGAZDEKECURBRIXIJWNPFCQIOVFVVXJVD9";
+/// The default path of the file which keeps the last solidified milestone.
+pub const DEFAULT_PROGRESS_FILE: &str = "solidifier.progress";
+
+actor!(SolidifierBuilder {
+    node: String,
+    coordinator: String,
+    progress_file: String,
+    poll_interval: u64,
+    max_walk: usize,
+    max_retries: usize,
+    batch_type: Option<BatchTypes>,
+    tracker: TrackerTx,
+    stop: watch::Receiver<()>
+});
+
+impl SolidifierBuilder {
+    pub fn build(self) -> Solidifier {
+        let node = self.node.unwrap();
+        let mut walker = WalkerBuilder::new()
+            .node(node.clone())
+            .max_retries(self.max_retries.unwrap_or(10))
+            .batch_type(self.batch_type.unwrap_or(None));
+        if let Some(max_walk) = self.max_walk {
+            walker = walker.max_walk(max_walk);
+        }
+        if let Some(tracker) = self.tracker {
+            walker = walker.tracker(tracker);
+        }
+        Solidifier {
+            walker: walker.build(),
+            node,
+            coordinator: self.coordinator.unwrap_or_else(|| DEFAULT_COORDINATOR.to_string()),
+            progress_file: self.progress_file.unwrap_or_else(|| DEFAULT_PROGRESS_FILE.to_string()),
+            poll_interval: self.poll_interval.unwrap_or(DEFAULT_POLL_INTERVAL),
+            solidified: None,
+            stop: self.stop,
+        }
+    }
+}
+
+pub struct Solidifier {
+    walker: Walker,
+    node: String,
+    coordinator: String,
+    progress_file: String,
+    poll_interval: u64,
+    solidified: Option<u64>,
+    stop: Option<watch::Receiver<()>>,
+}
+
+impl Solidifier {
+    pub async fn run(mut self) {
+        // resume after the last solidified milestone (if any)
+        if let Ok(progress) = fs::read_to_string(&self.progress_file).await {
+            if let Ok(last) = progress.trim().parse::<u64>() {
+                info!("solidifier: resuming after milestone: {}", last);
+                self.solidified.replace(last);
+            }
+        }
+        loop {
+            match self.walker.client().get_node_info().await {
+                Ok(node_info) => {
+                    let index = node_info.latest_solid_subtangle_milestone_index;
+                    let hash = node_info.latest_solid_subtangle_milestone;
+                    if let Err(error) = self.solidify_until(hash, index).await {
+                        // the remaining milestones are retried in the next poll
+                        error!(
+                            "solidifier: failed to solidify until milestone: {}, error: {}",
+                            index, error
+                        );
+                    }
+                }
+                Err(error) => warn!("solidifier: node: {}, error: {}", self.node, error),
+            }
+            let delay = tokio::time::delay_for(Duration::from_secs(self.poll_interval));
+            if let Some(stop) = self.stop.as_mut() {
+                tokio::select! {
+                    _ = delay => {}
+                    _ = stopped(stop) => break,
+                }
+            } else {
+                delay.await;
+            }
+        }
+    }
+
+    /// Solidify every milestone after the last solidified one until the latest solid milestone, in index order.
+    /// Without progress, the solidifier starts from the latest solid milestone.
+    async fn solidify_until(&mut self, latest_hash: String, latest: u64) -> Result<(), Box<dyn Error>> {
+        let mut start = self.solidified.map_or(latest, |solidified| solidified + 1);
+        while start <= latest {
+            let end = latest.min(start + MAX_MILESTONES_PER_LOOKUP - 1);
+            let mut milestones = HashMap::new();
+            if start < latest {
+                milestones = self.walker.milestones(&self.coordinator, start..=end).await?;
+            }
+            if end == latest {
+                milestones.insert(latest, latest_hash.clone());
+            }
+            for index in start..=end {
+                // a milestone can't be skipped, its cone would be confirmed by the next one
+                let hash = milestones
+                    .remove(&index)
+                    .ok_or_else(|| format!("milestone: {} is unknown to node: {}", index, self.node))?;
+                let progress = self.solidify(hash, index).await?;
+                info!("solidifier: {:?}", progress);
+                if !progress.complete {
+                    // the progress stays before the incomplete milestone, its walk is continued in the next poll
+                    return Ok(());
+                }
+                self.solidified.replace(index);
+                fs::write(&self.progress_file, index.to_string()).await?;
+            }
+            start = end + 1;
+        }
+        Ok(())
+    }
+
+    /// Walk the cone of the milestone until reaching the transactions confirmed by older milestones.
+    pub async fn solidify(&mut self, milestone_hash: String, milestone: u64) -> Result<Progress, Box<dyn Error>> {
+        self.walker.walk(milestone_hash, milestone).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::mock::mock_node,
+        cone::{
+            mock::{
+                failures,
+                fill_store,
+                mock_store,
+                stored,
+                transaction,
+            },
+            NULL_HASH,
+        },
+    };
+    use chronicle_storage::transaction::TAG;
+
+    #[tokio::test]
+    async fn solidify_missing_parent() {
+        let progress_file = std::env::temp_dir()
+            .join(format!("chronicle-solidifier-{}.progress", std::process::id()))
+            .to_str()
+            .unwrap()
+            .to_string();
+        // the milestone references a parent missing in the store and a transaction confirmed by milestone 5
+        let (parent, parent_trytes) = transaction(&[(TAG, "PARENT")]);
+        let (milestone, older) = ("M".repeat(81), "O".repeat(81));
+        let mut node = HashMap::new();
+        node.insert(parent.clone(), parent_trytes);
+        let mut store = HashMap::new();
+        store.insert(milestone.clone(), (parent.clone(), older.clone(), None));
+        store.insert(older.clone(), (NULL_HASH.to_string(), NULL_HASH.to_string(), Some(5)));
+        fill_store(store, 1);
+        let mut solidifier = SolidifierBuilder::new()
+            .node(mock_node(node, 1))
+            .progress_file(progress_file.clone())
+            .max_walk(1)
+            .max_retries(2)
+            .build();
+        mock_store(&mut solidifier.walker);
+        solidifier.solidified.replace(5);
+        // the walk stops at max_walk once the milestone transaction is confirmed, the progress stays before it
+        solidifier.solidify_until(milestone.clone(), 6).await.unwrap();
+        assert_eq!(solidifier.solidified, Some(5));
+        assert_eq!(stored(&milestone).unwrap().2, Some(6));
+        assert_eq!(stored(&parent), None);
+        // the next walk continues through the confirmed milestone transaction and fetches the missing parent
+        let progress = solidifier.solidify(milestone.clone(), 6).await.unwrap();
+        assert!(progress.complete);
+        assert_eq!(
+            (progress.visited, progress.confirmed, progress.fetched, progress.unknown),
+            (3, 0, 1, 0)
+        );
+        let null = NULL_HASH.to_string();
+        assert_eq!(stored(&parent), Some((null.clone(), null, Some(6))));
+        assert_eq!(stored(&older).unwrap().2, Some(5));
+        // the failed store request and node call got retried
+        assert_eq!(failures(), 0);
+        solidifier.solidify_until(milestone, 6).await.unwrap();
+        assert_eq!(solidifier.solidified, Some(6));
+        assert_eq!(fs::read_to_string(&progress_file).await.unwrap(), "6");
+        fs::remove_file(&progress_file).await.unwrap();
+    }
+}
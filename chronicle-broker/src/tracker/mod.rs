@@ -0,0 +1,292 @@
+// The tracker collects the stored members of each bundle by its hash and last_index, it marks the bundle complete in
+// tangle.bundle once all the indexes 0..=last_index got stored, and flags the bundles which stay incomplete past the
+// timeout.
+use crate::metrics::stopped;
+use chronicle_common::actor;
+use chronicle_cql::{
+    compression::MyCompression,
+    frame::decoder::{
+        Decoder,
+        Frame,
+    },
+};
+use chronicle_storage::{
+    ring::Ring,
+    stage::reporter,
+    transaction::{
+        self,
+        Transaction,
+    },
+    worker,
+};
+use log::*;
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+        VecDeque,
+    },
+    time::{
+        Duration,
+        Instant,
+        SystemTime,
+    },
+};
+use tokio::{
+    sync::{
+        mpsc,
+        watch,
+    },
+    time::interval,
+};
+
+type Sender = mpsc::UnboundedSender<Event>;
+type Receiver = mpsc::UnboundedReceiver<Event>;
+/// The default seconds a bundle can stay incomplete before being flagged.
+pub const DEFAULT_BUNDLE_TIMEOUT: u64 = 600;
+// the number of recently completed bundles kept, so their late members (ie reattachments) are not tracked again.
+const COMPLETED_CAPACITY: usize = 50_000;
+
+actor!(TrackerBuilder {
+    timeout: u64,
+    stop: watch::Receiver<()>
+});
+
+impl TrackerBuilder {
+    pub fn build(self) -> Tracker {
+        let (tx, rx) = mpsc::unbounded_channel::<Event>();
+        let timeout = Duration::from_secs(self.timeout.unwrap_or(DEFAULT_BUNDLE_TIMEOUT).max(1));
+        Tracker {
+            bundles: Bundles::new(timeout),
+            pids: Vec::new(),
+            tx,
+            rx,
+            stop: self.stop.unwrap(),
+        }
+    }
+}
+
+pub struct Tracker {
+    bundles: Bundles,
+    pids: Vec<Box<TrackerId>>,
+    tx: Sender,
+    rx: Receiver,
+    stop: watch::Receiver<()>,
+}
+
+/// A handle to report the stored transactions to the tracker.
+#[derive(Clone)]
+pub struct TrackerTx(Sender);
+impl TrackerTx {
+    pub fn stored(&self, transaction: &Transaction) {
+        let _ = self.0.send(Event::Stored {
+            bundle: transaction.bundle().to_string(),
+            current_index: transaction.current_index(),
+            last_index: transaction.last_index(),
+        });
+    }
+}
+
+#[derive(Debug)]
+pub struct TrackerId(Sender);
+
+pub enum Event {
+    Stored {
+        bundle: String,
+        current_index: i64,
+        last_index: i64,
+    },
+    Void {
+        pid: Box<TrackerId>,
+    },
+    Error {
+        kind: worker::Error,
+        pid: Box<TrackerId>,
+    },
+}
+
+impl Tracker {
+    pub fn sender(&self) -> TrackerTx {
+        TrackerTx(self.tx.clone())
+    }
+    pub async fn run(mut self) {
+        // the bundles are checked for the timeout a few times per timeout
+        let mut sweep = interval(self.bundles.timeout / 4);
+        loop {
+            tokio::select! {
+                Some(event) = self.rx.recv() => self.handle_event(event),
+                _ = sweep.tick() => {
+                    for (bundle, last_index) in self.bundles.expired(Instant::now()) {
+                        warn!("tracker: bundle: {} is incomplete past the timeout", bundle);
+                        self.mark(&bundle, last_index, false);
+                    }
+                }
+                _ = stopped(&mut self.stop) => break,
+            }
+        }
+    }
+    fn handle_event(&mut self, event: Event) {
+        match event {
+            Event::Stored {
+                bundle,
+                current_index,
+                last_index,
+            } => {
+                if self.bundles.stored(&bundle, current_index, last_index, Instant::now()) {
+                    self.mark(&bundle, last_index, true);
+                }
+            }
+            Event::Void { pid } => self.pids.push(pid),
+            Event::Error { kind, pid } => {
+                self.pids.push(pid);
+                error!(
+                    "tracker: failed to record the completeness of a bundle, error: {:?}",
+                    kind
+                );
+            }
+        }
+    }
+    fn mark(&mut self, bundle: &str, last_index: i64, complete: bool) {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let payload = transaction::insert_bundle(bundle, last_index, complete, timestamp);
+        let pid = match self.pids.pop() {
+            Some(pid) => pid,
+            None => Box::new(TrackerId(self.tx.clone())),
+        };
+        let request = reporter::Event::Request { payload, worker: pid };
+        Ring::send_local_random_replica(transaction::token(bundle.as_bytes()), request);
+    }
+}
+
+/// The stored indexes of a bundle.
+struct Members {
+    last_index: i64,
+    indexes: HashSet<i64>,
+    since: Instant,
+    flagged: bool,
+}
+
+/// The bundles with stored members, which are not complete yet.
+struct Bundles {
+    timeout: Duration,
+    pending: HashMap<String, Members>,
+    completed: HashSet<String>,
+    completed_order: VecDeque<String>,
+}
+
+impl Bundles {
+    fn new(timeout: Duration) -> Self {
+        Bundles {
+            timeout,
+            pending: HashMap::new(),
+            completed: HashSet::new(),
+            completed_order: VecDeque::new(),
+        }
+    }
+    /// Add the stored member, returns true if it completes the bundle.
+    fn stored(&mut self, bundle: &str, current_index: i64, last_index: i64, now: Instant) -> bool {
+        if current_index < 0 || current_index > last_index || self.completed.contains(bundle) {
+            return false;
+        }
+        let members = self.pending.entry(bundle.to_string()).or_insert_with(|| Members {
+            last_index,
+            indexes: HashSet::new(),
+            since: now,
+            flagged: false,
+        });
+        // a member which disagrees on the last_index doesn't belong to the bundle
+        if members.last_index != last_index {
+            return false;
+        }
+        members.indexes.insert(current_index);
+        if members.indexes.len() as i64 == last_index + 1 {
+            self.pending.remove(bundle);
+            // the oldest completed bundle is evicted first
+            if self.completed_order.len() == COMPLETED_CAPACITY {
+                let oldest = self.completed_order.pop_front().unwrap();
+                self.completed.remove(&oldest);
+            }
+            self.completed.insert(bundle.to_string());
+            self.completed_order.push_back(bundle.to_string());
+            true
+        } else {
+            false
+        }
+    }
+    /// Flag the bundles which stayed incomplete past the timeout, returns them with their last_index.
+    /// NOTE: a flagged bundle is still tracked for another timeout, so its late members can complete it.
+    fn expired(&mut self, now: Instant) -> Vec<(String, i64)> {
+        let timeout = self.timeout;
+        let mut expired = Vec::new();
+        self.pending.retain(|bundle, members| {
+            let elapsed = now.duration_since(members.since);
+            if !members.flagged && elapsed >= timeout {
+                members.flagged = true;
+                expired.push((bundle.clone(), members.last_index));
+            }
+            elapsed < 2 * timeout
+        });
+        expired
+    }
+}
+
+impl worker::Worker for TrackerId {
+    fn send_response(self: Box<Self>, _: &Option<reporter::Sender>, giveload: Vec<u8>) {
+        let decoder = Decoder::new(giveload, MyCompression::get());
+        unsafe {
+            let raw = Box::into_raw(self);
+            let pid = Box::from_raw(raw);
+            let event;
+            if decoder.is_error() {
+                let error = decoder.get_error();
+                event = Event::Error {
+                    kind: worker::Error::Cql(error),
+                    pid,
+                }
+            } else {
+                event = Event::Void { pid };
+            }
+            let _ = (*raw).0.send(event);
+        }
+    }
+    fn send_error(self: Box<Self>, kind: worker::Error) {
+        unsafe {
+            let raw = Box::into_raw(self);
+            let pid = Box::from_raw(raw);
+            let event = Event::Error { kind, pid };
+            let _ = (*raw).0.send(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn complete_and_incomplete_bundles() {
+        let now = Instant::now();
+        let timeout = Duration::from_secs(10);
+        let mut bundles = Bundles::new(timeout);
+        let (a, b) = ("A".repeat(81), "B".repeat(81));
+        // the members of bundle a arrive out of order, and one of them twice
+        assert!(!bundles.stored(&a, 2, 2, now));
+        assert!(!bundles.stored(&a, 0, 2, now));
+        assert!(!bundles.stored(&a, 0, 2, now));
+        // a member which disagrees on the last_index is ignored
+        assert!(!bundles.stored(&a, 1, 3, now));
+        assert!(bundles.stored(&a, 1, 2, now));
+        // a reattachment of a completed bundle is not tracked again
+        assert!(!bundles.stored(&a, 0, 2, now));
+        assert!(!bundles.stored(&b, 0, 1, now));
+        assert!(bundles.expired(now).is_empty());
+        assert_eq!(bundles.expired(now + timeout), vec![(b.clone(), 1)]);
+        // flagged once, then still completed by a late member
+        assert!(bundles.expired(now + timeout).is_empty());
+        assert!(bundles.stored(&b, 1, 1, now + timeout));
+        assert!(bundles.pending.is_empty());
+    }
+}
@@ -12,6 +12,7 @@ macro_rules! launcher {
             RegisterApp(String, Box<dyn ShutdownTx>),
             RegisterDashboard(String, Box<dyn DashboardTx>),
             AppsStatus(String),
+            AppMetrics(String, String),
             ExitProgram,
         }
 
@@ -28,6 +29,9 @@ macro_rules! launcher {
             fn apps_status(dashboard_name: String) -> Event {
                 Event::AppsStatus(dashboard_name)
             }
+            fn app_metrics(app_name: String, metrics: String) -> Event {
+                Event::AppMetrics(app_name, metrics)
+            }
             fn shutdown_app(app_name: String) -> Event {
                 Event::ShutdownApp(app_name)
             }
@@ -114,6 +118,12 @@ macro_rules! launcher {
                                 dashboard_tx.apps_status(self.apps_status.clone());
                             }
                         }
+                        Event::AppMetrics(app_name, metrics) => {
+                            // forward the metrics to the dashboard(s)
+                            for (_, dashboard_tx) in &mut self.dashboards {
+                                dashboard_tx.app_metrics(app_name.clone(), metrics.clone());
+                            }
+                        }
                         Event::ExitProgram => {
                             self.exit = true;
                             info!("Exiting Program;");
@@ -153,6 +163,7 @@ macro_rules! launcher {
             fn register_app(app_name: String, shutdown_tx: Box<dyn ShutdownTx>) -> Self;
             fn register_dashboard(dashboard_name: String, dashboard_tx: Box<dyn DashboardTx>) -> Self;
             fn apps_status(dashboard_name: String) -> Self;
+            fn app_metrics(app_name: String, metrics: String) -> Self;
             fn exit_program() -> Self;
         }
         #[derive(Clone)]
@@ -177,6 +188,9 @@ macro_rules! launcher {
             fn apps_status(&mut self, dashboard_name: String) {
                 let _ = self.0.send(LauncherEvent::apps_status(dashboard_name));
             }
+            fn app_metrics(&mut self, app_name: String, metrics: String) {
+                let _ = self.0.send(LauncherEvent::app_metrics(app_name, metrics));
+            }
             fn exit_program(&mut self) {
                 let _ = self.0.send(LauncherEvent::exit_program());
             }
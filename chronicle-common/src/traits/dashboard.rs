@@ -5,6 +5,7 @@ pub trait DashboardTx: Send {
     fn restarted_app(&mut self, app_name: String);
     fn shutdown_app(&mut self, app_name: String);
     fn apps_status(&mut self, apps_status: HashMap<String, AppStatus>);
+    fn app_metrics(&mut self, app_name: String, metrics: String);
 }
 
 pub type AppsStatus = HashMap<String, AppStatus>;
@@ -0,0 +1,28 @@
+// The control of the dmp import jobs, implemented by the importer jobs queue and used by the dashboard(s).
+pub trait ImporterTx: Send + ImporterTxClone {
+    fn import(&mut self, filepath: String, milestone: u64);
+    fn pause(&mut self, job_id: usize);
+    fn resume(&mut self, job_id: usize);
+    fn cancel(&mut self, job_id: usize);
+    // report the job list right away, rather than on the next report interval.
+    fn jobs(&mut self);
+}
+
+impl Clone for Box<dyn ImporterTx> {
+    fn clone(&self) -> Box<dyn ImporterTx> {
+        self.clone_box()
+    }
+}
+
+pub trait ImporterTxClone {
+    fn clone_box(&self) -> Box<dyn ImporterTx>;
+}
+
+impl<T> ImporterTxClone for T
+where
+    T: 'static + ImporterTx + Clone,
+{
+    fn clone_box(&self) -> Box<dyn ImporterTx> {
+        Box::new(self.clone())
+    }
+}
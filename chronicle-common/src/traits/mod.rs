@@ -1,3 +1,4 @@
 pub mod dashboard;
+pub mod importer;
 pub mod launcher;
 pub mod shutdown;
@@ -17,6 +17,7 @@ type QueryCount = u16;
 pub struct Batch(pub Vec<u8>, pub QueryCount);
 
 #[repr(u8)]
+#[derive(Clone, Copy, Debug)]
 pub enum BatchTypes {
     Logged = 0,
     Unlogged = 1,
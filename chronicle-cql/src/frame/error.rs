@@ -115,6 +115,24 @@ pub enum ErrorCodes {
     AlreadyExists = 0x2400,
     Unprepared = 0x2500,
 }
+impl ErrorCodes {
+    /// Returns true if the error is transient (timeouts, overload, unavailable replicas), so the query can be retried.
+    /// The other errors (syntax, invalid, unauthorized...) fail the same way on every retry.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            ErrorCodes::ServerError
+                | ErrorCodes::UnavailableException
+                | ErrorCodes::Overloaded
+                | ErrorCodes::IsBoostrapping
+                | ErrorCodes::TruncateError
+                | ErrorCodes::WriteTimeout
+                | ErrorCodes::ReadTimeout
+                | ErrorCodes::ReadFailure
+                | ErrorCodes::WriteFailure
+        )
+    }
+}
 #[derive(Debug)]
 pub enum Additional {
     UnavailableException(UnavailableException),
@@ -53,6 +53,15 @@ CREATE TABLE IF NOT EXISTS tangle.data (
 );
 "#;
 
+pub const CREATE_BUNDLE_TABLE_QUERY: &str = r#"
+CREATE TABLE IF NOT EXISTS tangle.bundle (
+  bundle blob PRIMARY KEY,
+  last_index bigint,
+  complete boolean,
+  timestamp bigint,
+);
+"#;
+
 pub const INSERT_TX_QUERY: &str = r#"
   INSERT INTO tangle.transaction (
     hash,
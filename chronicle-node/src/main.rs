@@ -12,7 +12,26 @@ use chronicle_common::{
 };
 use log::*;
 // import helper async fns to add scylla nodes and build ring, initialize schema, import dmps
-use chronicle_broker::importer::ImporterBuilder;
+use chronicle_broker::{
+    backfill::BackfillBuilder,
+    exporter::ExporterBuilder,
+    importer::{
+        jobs::{
+            self,
+            Jobs,
+            JobsBuilder,
+            Status,
+        },
+        watcher::WatcherBuilder,
+        ImporterBuilder,
+    },
+    selective::{
+        Rules,
+        SelectiveRules,
+    },
+    snapshot::SnapshotBuilder,
+};
+use chronicle_cql::frame::batch::BatchTypes;
 use chronicle_storage::{
     dashboard::client::add_nodes,
     worker::schema_cql::SchemaCqlBuilder,
@@ -24,7 +43,13 @@ use std::{
     path::PathBuf,
 };
 use structopt::StructOpt;
-use tokio::runtime::Builder;
+use tokio::{
+    runtime::Builder,
+    signal::unix::{
+        signal,
+        SignalKind,
+    },
+};
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "chronicle-alpha-v0_1_0", about = "Chronicle Permanode Alpha v0.1.0")]
@@ -40,10 +65,14 @@ pub struct Config {
     version: Version,
     scylla_cluster: ScyllaCluster,
     dmp_files: Option<DmpFiles>,
+    backfill: Option<Backfill>,
+    snapshot: Option<Snapshot>,
+    export: Option<Export>,
     tokio: Tokio,
     storage: Storage,
     api: Api,
     broker: Broker,
+    selective: Option<Selective>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -66,10 +95,50 @@ struct ScyllaCluster {
     local_dc: String,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize)]
 struct DmpFiles {
     files: Option<Vec<(String, u64)>>,
     import_only_confirmed_transactions: Option<bool>,
+    verify_transaction_hashes: Option<bool>,
+    batch_type: Option<String>,
+    max_retries: Option<usize>,
+    checkpoint_interval: Option<u64>,
+    max_in_flight: Option<usize>,
+    parallel_files: Option<usize>,
+    validate_only: Option<bool>,
+    from_milestone: Option<u64>,
+    to_milestone: Option<u64>,
+    attribute_unconfirmed: Option<bool>,
+    watch_folder: Option<String>,
+    watch_interval: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Backfill {
+    node: String,
+    coordinator: String,
+    milestone_range: (u64, u64),
+    progress_file: Option<String>,
+    max_walk: Option<usize>,
+    max_retries: Option<usize>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Snapshot {
+    milestone: u64,
+    path: String,
+    base_path: Option<String>,
+    base_milestone: Option<u64>,
+    page_size: Option<i32>,
+    max_retries: Option<usize>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Export {
+    path: String,
+    milestone_range: Option<(u64, u64)>,
+    parallelism: Option<usize>,
+    page_size: Option<i32>,
     max_retries: Option<usize>,
 }
 
@@ -90,13 +159,52 @@ struct Api {
 
 #[derive(Debug, Clone, Deserialize)]
 struct Broker {
+    sn_nodes: Option<Vec<String>>,
     trytes_nodes: Option<Vec<String>>,
     sn_trytes_nodes: Option<Vec<String>>,
+    mqtt_sn_nodes: Option<Vec<String>>,
+    mqtt_trytes_nodes: Option<Vec<String>>,
+    mqtt_sn_trytes_nodes: Option<Vec<String>>,
+    max_in_flight_transactions: Option<usize>,
+    verify_transaction_hashes: Option<bool>,
+    store_only_confirmed_transactions: Option<bool>,
+    unconfirmed_capacity: Option<usize>,
+    unconfirmed_ttl: Option<u64>,
+    batch_type: Option<String>,
+    solidifier_node: Option<String>,
+    solidifier_coordinator: Option<String>,
+    solidifier_progress_file: Option<String>,
+    solidifier_max_walk: Option<usize>,
+    bundle_timeout: Option<u64>,
+    metrics_address: Option<String>,
+    shutdown_timeout: Option<u64>,
+    failure_log: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Selective {
+    addresses: Option<Vec<String>>,
+    tags: Option<Vec<String>>,
+    min_value: Option<u64>,
+    whole_bundle: Option<bool>,
+}
+
+impl Selective {
+    fn rules(&self) -> Rules {
+        let mut rules = Rules::new()
+            .addresses(self.addresses.clone().unwrap_or_default())
+            .tags(self.tags.clone().unwrap_or_default())
+            .whole_bundle(self.whole_bundle.unwrap_or(false));
+        if let Some(min_value) = self.min_value {
+            rules = rules.min_value(min_value);
+        }
+        rules
+    }
 }
 
 launcher!(
     apps_builder: AppsBuilder {storage: StorageBuilder, api: ApiBuilder, broker: BrokerBuilder},
-    apps: Apps{config: Config}
+    apps: Apps{config: Config, selective: SelectiveRules, jobs: Jobs}
 );
 
 // build your apps
@@ -118,20 +226,95 @@ impl AppsBuilder {
         // 
         // - broker app
         let mut broker = BrokerBuilder::new();
+        if let Some(sn_nodes) = config.broker.sn_nodes.as_ref() {
+            broker = broker.sn(sn_nodes.to_vec());
+        }
         if let Some(trytes_nodes) = config.broker.trytes_nodes.as_ref() {
             broker = broker.trytes(trytes_nodes.to_vec());
         }
         if let Some(sn_trytes_nodes) = config.broker.sn_trytes_nodes.as_ref() {
             broker = broker.sn_trytes(sn_trytes_nodes.to_vec());
         }
+        if let Some(mqtt_sn_nodes) = config.broker.mqtt_sn_nodes.as_ref() {
+            broker = broker.mqtt_sn(mqtt_sn_nodes.to_vec());
+        }
+        if let Some(mqtt_trytes_nodes) = config.broker.mqtt_trytes_nodes.as_ref() {
+            broker = broker.mqtt_trytes(mqtt_trytes_nodes.to_vec());
+        }
+        if let Some(mqtt_sn_trytes_nodes) = config.broker.mqtt_sn_trytes_nodes.as_ref() {
+            broker = broker.mqtt_sn_trytes(mqtt_sn_trytes_nodes.to_vec());
+        }
+        if let Some(max_in_flight) = config.broker.max_in_flight_transactions {
+            broker = broker.max_in_flight(max_in_flight);
+        }
+        if let Some(verify_hash) = config.broker.verify_transaction_hashes {
+            broker = broker.verify_hash(verify_hash);
+        }
+        if let Some(only_confirmed) = config.broker.store_only_confirmed_transactions {
+            broker = broker.only_confirmed(only_confirmed);
+        }
+        if let Some(unconfirmed_capacity) = config.broker.unconfirmed_capacity {
+            broker = broker.unconfirmed_capacity(unconfirmed_capacity);
+        }
+        if let Some(unconfirmed_ttl) = config.broker.unconfirmed_ttl {
+            broker = broker.unconfirmed_ttl(unconfirmed_ttl);
+        }
+        if let Some(batch) = config.broker.batch_type.as_ref() {
+            broker = broker.batch_type(batch_type(batch));
+        }
+        if let Some(solidifier_node) = config.broker.solidifier_node.as_ref() {
+            broker = broker.solidifier_node(solidifier_node.clone());
+        }
+        if let Some(coordinator) = config.broker.solidifier_coordinator.as_ref() {
+            broker = broker.solidifier_coordinator(coordinator.clone());
+        }
+        if let Some(progress_file) = config.broker.solidifier_progress_file.as_ref() {
+            broker = broker.solidifier_progress_file(progress_file.clone());
+        }
+        if let Some(max_walk) = config.broker.solidifier_max_walk {
+            broker = broker.solidifier_max_walk(max_walk);
+        }
+        if let Some(bundle_timeout) = config.broker.bundle_timeout {
+            broker = broker.bundle_timeout(bundle_timeout);
+        }
+        if let Some(metrics_address) = config.broker.metrics_address.as_ref() {
+            broker = broker.metrics_address(metrics_address.parse().expect("invalid metrics_address"));
+        }
+        if let Some(shutdown_timeout) = config.broker.shutdown_timeout {
+            broker = broker.shutdown_timeout(shutdown_timeout);
+        }
+        if let Some(failure_log) = config.broker.failure_log.as_ref() {
+            broker = broker.failure_log(failure_log.clone());
+        }
+        // - selective rules, shared by the broker and the importer
+        let selective = config.selective.as_ref().map(|selective| SelectiveRules::new(selective.rules()));
+        if let Some(selective) = selective.as_ref() {
+            broker = broker.selective(selective.clone());
+        }
+        // - dmp import jobs, controlled through the storage dashboard
+        let dmp_files = config.dmp_files.clone().unwrap_or_default();
+        let parallel_files = dmp_files.parallel_files.unwrap_or(1).max(1);
+        let importer_selective = selective.clone();
+        let jobs = JobsBuilder::new()
+            .importer(Box::new(move |filepath, milestone| {
+                importer_builder(&dmp_files, importer_selective.as_ref(), filepath, milestone)
+            }))
+            .parallel_files(parallel_files)
+            .build();
+        let storage = storage.importer_tx(Box::new(jobs.clone_tx()));
         // add app to AppsBuilder then transform it to Apps
-        self.storage(storage).api(api).broker(broker).to_apps().config(config)
+        let apps = self.storage(storage).api(api).broker(broker).to_apps().config(config).jobs(jobs);
+        if let Some(selective) = selective {
+            apps.selective(selective)
+        } else {
+            apps
+        }
     }
 }
 
 fn main() {
     let args = Args::from_args();
-    let config_as_string = fs::read_to_string(args.path).unwrap();
+    let config_as_string = fs::read_to_string(&args.path).unwrap();
     let config: Config = toml::from_str(&config_as_string).unwrap();
     logger_init(config.logger.clone().finish()).unwrap();
     // build tokio runtime
@@ -150,6 +333,10 @@ fn main() {
         apps.function(|apps| {
             // for instance this is helpful to spawn ctrl_c future
             tokio::spawn(ctrl_c(apps.tx.clone()));
+            // reload the selective rules on SIGHUP
+            if let Some(selective) = apps.selective.clone() {
+                tokio::spawn(reload_selective(args.path.clone(), selective));
+            }
         })
         .await
         .storage()
@@ -158,6 +345,10 @@ fn main() {
         .await // start api app
         .future(|mut apps| async {
             let config = apps.config.take().unwrap();
+            // run the dmp import jobs, the imports of the config are queued as jobs as well
+            let jobs = apps.jobs.take().unwrap();
+            let jobs_tx = jobs.clone_tx();
+            tokio::spawn(jobs.run(Box::new(apps.tx.clone())));
             let dashboard_websocket = format!("ws://{}/", config.storage.dashboard_websocket);
             let scylla_nodes = config.scylla_cluster.addresses.clone();
             let rf = config.scylla_cluster.replication_factor_per_data_center;
@@ -194,8 +385,46 @@ fn main() {
                 .run()
                 .await
                 .expect("failed to create data table");
+            // create bundle table
+            SchemaCqlBuilder::new()
+                .statement(statement_map["CREATE_BUNDLE_TABLE_QUERY"].clone())
+                .build()
+                .run()
+                .await
+                .expect("failed to create bundle table");
+            // create dmp_file table
+            SchemaCqlBuilder::new()
+                .statement(statement_map["CREATE_DMP_FILE_TABLE_QUERY"].clone())
+                .build()
+                .run()
+                .await
+                .expect("failed to create dmp_file table");
             if let Some(dmp_files) = config.dmp_files {
-                import_files(dmp_files).await;
+                let watch_folder = dmp_files.watch_folder.clone();
+                let watch_interval = dmp_files.watch_interval;
+                let max_retries = dmp_files.max_retries.unwrap_or(10);
+                import_files(dmp_files, jobs_tx.clone()).await;
+                // the dmp files arriving in the watch folder are imported once the configured files are imported
+                if let Some(folder) = watch_folder {
+                    let mut watcher = WatcherBuilder::new()
+                        .folder(folder)
+                        .jobs_tx(jobs_tx)
+                        .max_retries(max_retries);
+                    if let Some(interval) = watch_interval {
+                        watcher = watcher.interval(interval);
+                    }
+                    tokio::spawn(watcher.build().run());
+                }
+            }
+            if let Some(backfill) = config.backfill {
+                let batch_type = config.broker.batch_type.as_ref().map(|batch| batch_type(batch));
+                backfill_milestones(backfill, batch_type).await;
+            }
+            if let Some(snapshot) = config.snapshot {
+                export_snapshot(snapshot).await;
+            }
+            if let Some(export) = config.export {
+                export_transactions(export).await;
             }
             apps
         })
@@ -213,6 +442,8 @@ fn create_statements(scylla_cluster: ScyllaCluster) -> HashMap<String, String> {
     let mut create_tx_table_statement = String::new();
     let mut create_edge_table_statement = String::new();
     let mut create_data_table_statement = String::new();
+    let mut create_bundle_table_statement = String::new();
+    let mut create_dmp_file_table_statement = String::new();
     let mut create_key_space_statement = String::from("CREATE KEYSPACE IF NOT EXISTS ");
     create_key_space_statement.push_str(&keyspace_name);
     create_key_space_statement.push_str(" ");
@@ -285,6 +516,30 @@ fn create_statements(scylla_cluster: ScyllaCluster) -> HashMap<String, String> {
     )
     .unwrap();
 
+    write!(
+        &mut create_bundle_table_statement,
+        "CREATE TABLE IF NOT EXISTS {}.bundle (
+            bundle blob PRIMARY KEY,
+            last_index bigint,
+            complete boolean,
+            timestamp bigint,
+          );",
+        keyspace_name
+    )
+    .unwrap();
+
+    write!(
+        &mut create_dmp_file_table_statement,
+        "CREATE TABLE IF NOT EXISTS {}.dmp_file (
+            name text PRIMARY KEY,
+            checksum bigint,
+            milestone bigint,
+            status text
+          );",
+        keyspace_name
+    )
+    .unwrap();
+
     statement_map.insert(
         "CREATE_KEYSPACE_QUERY".to_string(),
         create_key_space_statement.to_string(),
@@ -301,6 +556,14 @@ fn create_statements(scylla_cluster: ScyllaCluster) -> HashMap<String, String> {
         "CREATE_DATE_TABLE_QUERY".to_string(),
         create_data_table_statement.to_string(),
     );
+    statement_map.insert(
+        "CREATE_BUNDLE_TABLE_QUERY".to_string(),
+        create_bundle_table_statement.to_string(),
+    );
+    statement_map.insert(
+        "CREATE_DMP_FILE_TABLE_QUERY".to_string(),
+        create_dmp_file_table_statement.to_string(),
+    );
     statement_map
 }
 
@@ -312,30 +575,161 @@ async fn ctrl_c(mut launcher: Sender) {
     launcher.exit_program();
 }
 
-async fn import_files(dmp_files: DmpFiles) {
-    let mut files: Vec<(String, u64)> = dmp_files.files.unwrap();
+/// Parse the batch type used to insert each transaction as one batch
+fn batch_type(batch_type: &str) -> BatchTypes {
+    match batch_type {
+        "logged" => BatchTypes::Logged,
+        "unlogged" => BatchTypes::Unlogged,
+        _ => panic!("invalid batch_type: {}, expected logged or unlogged", batch_type),
+    }
+}
+
+/// Reload the selective rules from the config file whenever SIGHUP is received
+async fn reload_selective(path: PathBuf, selective: SelectiveRules) {
+    let mut hangup = signal(SignalKind::hangup()).unwrap();
+    while let Some(()) = hangup.recv().await {
+        let config = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|config| toml::from_str::<Config>(&config).map_err(|e| e.to_string()));
+        match config {
+            Ok(Config {
+                selective: Some(config), ..
+            }) => {
+                selective.reload(config.rules());
+                info!("reloaded selective rules from: {:?}", path);
+            }
+            Ok(_) => warn!("no selective section in: {:?}, keeping the current rules", path),
+            Err(error) => error!("failed to reload selective rules from: {:?}, error: {}", path, error),
+        }
+    }
+}
+
+async fn backfill_milestones(backfill: Backfill, batch_type: Option<BatchTypes>) {
+    let (start, end) = backfill.milestone_range;
+    let mut backfill_builder = BackfillBuilder::new()
+        .node(backfill.node)
+        .coordinator(backfill.coordinator)
+        .start(start)
+        .end(end)
+        .progress_file(backfill.progress_file.unwrap_or_else(|| "backfill.progress".to_string()))
+        .max_retries(backfill.max_retries.unwrap_or(1000))
+        .batch_type(batch_type);
+    if let Some(max_walk) = backfill.max_walk {
+        backfill_builder = backfill_builder.max_walk(max_walk);
+    }
+    if let Err(error) = backfill_builder.build().run().await {
+        panic!("failed to backfill milestones {}..={}, error: {}", start, end, error);
+    }
+    info!("succesfully backfilled milestones {}..={}", start, end);
+}
+
+async fn export_snapshot(snapshot: Snapshot) {
+    let milestone = snapshot.milestone;
+    let mut snapshot_builder = SnapshotBuilder::new()
+        .milestone(milestone)
+        .path(snapshot.path)
+        .max_retries(snapshot.max_retries.unwrap_or(1000));
+    if let Some(base_path) = snapshot.base_path {
+        snapshot_builder = snapshot_builder.base_path(base_path).base_milestone(snapshot.base_milestone.unwrap_or(0));
+    }
+    if let Some(page_size) = snapshot.page_size {
+        snapshot_builder = snapshot_builder.page_size(page_size);
+    }
+    match snapshot_builder.build().run().await {
+        Ok(report) if report.is_consistent() => info!("succesfully exported snapshot: {:?}", report),
+        Ok(report) => error!("exported an inconsistent snapshot: {:?}", report),
+        Err(error) => panic!("failed to export the snapshot of milestone {}, error: {}", milestone, error),
+    }
+}
+
+async fn export_transactions(export: Export) {
+    let path = export.path.clone();
+    let mut exporter_builder = ExporterBuilder::new()
+        .path(export.path)
+        .max_retries(export.max_retries.unwrap_or(1000));
+    if let Some(milestone_range) = export.milestone_range {
+        exporter_builder = exporter_builder.milestone_range(milestone_range);
+    }
+    if let Some(parallelism) = export.parallelism {
+        exporter_builder = exporter_builder.parallelism(parallelism);
+    }
+    if let Some(page_size) = export.page_size {
+        exporter_builder = exporter_builder.page_size(page_size);
+    }
+    match exporter_builder.build().run().await {
+        Ok(report) => info!("succesfully exported transactions to {}: {:?}", path, report),
+        Err(error) => panic!("failed to export transactions to {}, error: {}", path, error),
+    }
+}
+
+async fn import_files(dmp_files: DmpFiles, jobs_tx: jobs::Sender) {
+    let mut files: Vec<(String, u64)> = dmp_files.files.unwrap_or_default();
+    let validate_only = dmp_files.validate_only.unwrap_or(false);
+    files.sort_by(|a, b| b.1.cmp(&a.1));
+    // queue the files in order, the jobs import parallel_files files at a time
+    let mut imports = Vec::new();
+    for t in files.iter() {
+        imports.push((t.0.clone(), jobs_tx.queue(t.0.clone(), t.1)));
+    }
+    for (filepath, import) in imports {
+        match import.await {
+            Ok(Status::Done) if validate_only => info!("validated: {}, see {}.report", filepath, filepath),
+            Ok(Status::Done) => info!("succesfully imported: {}", filepath),
+            Ok(Status::Cancelled) => warn!("cancelled the import of file: {}", filepath),
+            _ => panic!("failed to import file: {}", filepath),
+        }
+    }
+}
+
+/// Create the importer of a dmp file with the dmp_files settings, used by the dmp import jobs
+fn importer_builder(
+    dmp_files: &DmpFiles,
+    selective: Option<&SelectiveRules>,
+    filepath: String,
+    milestone: u64,
+) -> ImporterBuilder {
     let mut only_confirmed = false;
     if let Some(is_only_confirmed) = dmp_files.import_only_confirmed_transactions {
         only_confirmed = is_only_confirmed;
     }
-    let mut max_retries = 1000;
+    let mut verify_hash = true;
+    if let Some(is_verify_hash) = dmp_files.verify_transaction_hashes {
+        verify_hash = is_verify_hash;
+    }
+    let mut max_retries = 10;
     if let Some(max) = dmp_files.max_retries {
         max_retries = max;
     }
-    files.sort_by(|a, b| b.1.cmp(&a.1));
-    for t in files.iter() {
-        if let Ok(_) = ImporterBuilder::new()
-            .filepath(t.0.clone())
-            .milestone(t.1)
-            .only_confirmed(only_confirmed)
-            .max_retries(max_retries)
-            .build()
-            .run()
-            .await
-        {
-            info!("succesfully imported: {}", t.0);
-        } else {
-            panic!("failed to import file: {}", t.0);
-        }
+    let parallel_files = dmp_files.parallel_files.unwrap_or(1).max(1);
+    // the progress bars are hidden when importing files in parallel, as they would overwrite each other
+    let mut importer = ImporterBuilder::new()
+        .filepath(filepath)
+        .milestone(milestone)
+        .only_confirmed(only_confirmed)
+        .verify_hash(verify_hash)
+        .max_retries(max_retries)
+        .show_progress(parallel_files == 1)
+        .validate_only(dmp_files.validate_only.unwrap_or(false));
+    if let Some(selective) = selective {
+        importer = importer.selective(selective.clone());
+    }
+    if let Some(batch) = dmp_files.batch_type.as_ref() {
+        importer = importer.batch_type(batch_type(batch));
+    }
+    if let Some(interval) = dmp_files.checkpoint_interval {
+        importer = importer.checkpoint_interval(interval);
+    }
+    if let Some(max_in_flight) = dmp_files.max_in_flight {
+        importer = importer.max_in_flight(max_in_flight);
+    }
+    if let Some(from_milestone) = dmp_files.from_milestone {
+        importer = importer.from_milestone(from_milestone);
+    }
+    if let Some(to_milestone) = dmp_files.to_milestone {
+        importer = importer.to_milestone(to_milestone);
+    }
+    if let Some(attribute_unconfirmed) = dmp_files.attribute_unconfirmed {
+        importer = importer.attribute_unconfirmed(attribute_unconfirmed);
     }
+    importer
 }
@@ -14,6 +14,7 @@ use chronicle_common::{
             AppStatus,
             DashboardTx,
         },
+        importer::ImporterTx,
         launcher::LauncherTx,
         shutdown::ShutdownTx,
     },
@@ -60,6 +61,10 @@ impl DashboardTx for Sender {
         let event = Event::Launcher(Launcher::Apps(apps_status));
         let _ = self.0.send(event);
     }
+    fn app_metrics(&mut self, app_name: std::string::String, metrics: std::string::String) {
+        let event = Event::Launcher(Launcher::Metrics(app_name, metrics));
+        let _ = self.0.send(event);
+    }
 }
 pub struct Shutdown(Sender);
 pub type Receiver = mpsc::UnboundedReceiver<Event>;
@@ -77,6 +82,7 @@ pub enum Event {
     Toplogy(Toplogy),
     Result(Result),
     Launcher(Launcher),
+    Importer(Importer),
     Shutdown,
 }
 pub enum Session {
@@ -99,12 +105,23 @@ pub enum Result {
 pub enum Launcher {
     App(AppStatus),
     Apps(HashMap<String, AppStatus>),
+    Metrics(String, String),
+}
+
+// the commands of the dmp import jobs, the job list is reported back as the importer metrics.
+pub enum Importer {
+    Import(String, u64),
+    Pause(usize),
+    Resume(usize),
+    Cancel(usize),
+    Jobs,
 }
 
 actor!(
     DashboardBuilder {
         listen_address: String,
-        launcher_tx: Box<dyn LauncherTx>
+        launcher_tx: Box<dyn LauncherTx>,
+        importer_tx: Box<dyn ImporterTx>
 });
 
 impl DashboardBuilder {
@@ -112,6 +129,7 @@ impl DashboardBuilder {
         let (tx, rx) = mpsc::unbounded_channel::<Event>();
         Dashboard {
             launcher_tx: self.launcher_tx.unwrap(),
+            importer_tx: self.importer_tx,
             listen_address: self.listen_address.unwrap(),
             listener: None,
             sockets: HashMap::new(),
@@ -123,6 +141,7 @@ impl DashboardBuilder {
 
 pub struct Dashboard {
     launcher_tx: Box<dyn LauncherTx>,
+    importer_tx: Option<Box<dyn ImporterTx>>,
     listener: Option<AbortHandle>,
     listen_address: String,
     sockets: HashMap<SocketAddr, WsTx>,
@@ -214,10 +233,32 @@ impl Dashboard {
                             }
                         }
                     }
-                } /* todo handle websocket decoded msgs (add node, remove node, build,
-                   * get status, get dashboard log, import dump file, etc) */
+                } // todo handle websocket decoded msgs (add node, remove node, build,
+                // get status, get dashboard log, etc)
+                Event::Launcher(Launcher::Metrics(app_name, metrics)) => {
+                    // NOTE: for now we tell all the active sockets
+                    for socket in self.sockets.values_mut() {
+                        let msg = SocketMsg::Metrics(app_name.clone(), metrics.clone());
+                        let j = serde_json::to_string(&msg).unwrap();
+                        let m = Message::text(j);
+                        let _ = socket.send(m).await;
+                    }
+                }
                 Event::Launcher(_launcher_status) => {
-                    //TODO do something with app/apps_status
+                    // TODO do something with app/apps_status
+                }
+                Event::Importer(importer) => {
+                    if let Some(importer_tx) = self.importer_tx.as_mut() {
+                        match importer {
+                            Importer::Import(filepath, milestone) => importer_tx.import(filepath, milestone),
+                            Importer::Pause(job_id) => importer_tx.pause(job_id),
+                            Importer::Resume(job_id) => importer_tx.resume(job_id),
+                            Importer::Cancel(job_id) => importer_tx.cancel(job_id),
+                            Importer::Jobs => importer_tx.jobs(),
+                        }
+                    } else {
+                        warn!("Dashboard: the dmp import jobs are not enabled");
+                    }
                 }
                 Event::Shutdown => {
                     // storage app shutdown including the dashboard/cluster/listener.
@@ -61,6 +61,16 @@ pub enum SocketMsg {
     Ok(String),
     Err(String),
     BuiltRing(bool),
+    // the app name and its metrics (json), the importer metrics are the dmp import jobs.
+    Metrics(String, String),
+    // queue the import of a dmp file (filepath, milestone)
+    Import(String, u64),
+    // pause, resume or cancel the dmp import job
+    PauseImport(usize),
+    ResumeImport(usize),
+    CancelImport(usize),
+    // request the dmp import jobs
+    ImportJobs,
 }
 
 impl Websocket {
@@ -99,6 +109,13 @@ impl Websocket {
                                     uniform_rf as usize,
                                 )));
                         }
+                        SocketMsg::Import(filepath, milestone) => {
+                            self.importer(dashboard::Importer::Import(filepath, milestone));
+                        }
+                        SocketMsg::PauseImport(job_id) => self.importer(dashboard::Importer::Pause(job_id)),
+                        SocketMsg::ResumeImport(job_id) => self.importer(dashboard::Importer::Resume(job_id)),
+                        SocketMsg::CancelImport(job_id) => self.importer(dashboard::Importer::Cancel(job_id)),
+                        SocketMsg::ImportJobs => self.importer(dashboard::Importer::Jobs),
                         _ => panic!("unexpected SocketMsg"),
                     }
                 }
@@ -112,6 +129,9 @@ impl Websocket {
         }
         Ok(())
     }
+    fn importer(&mut self, importer: dashboard::Importer) {
+        let _ = self.dashboard_tx.0.send(dashboard::Event::Importer(importer));
+    }
     async fn authenticate(&mut self) -> bool {
         // authentication through self.dashboard_tx login session, it should login
         // unimplemented!()
@@ -6,4 +6,5 @@ pub mod node;
 pub mod ring;
 pub mod stage;
 pub mod storage;
+pub mod transaction;
 pub mod worker;
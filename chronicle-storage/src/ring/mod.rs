@@ -112,6 +112,14 @@ impl Ring {
     pub fn send_global_random_replica(token: Token, request: Event) {
         RING.with(|local| local.borrow_mut().sending().global_random_replica(token, request))
     }
+    /// The token ranges (left, right] of the vnodes in the ring ordered by token, empty if the ring is not built yet.
+    pub fn token_ranges() -> Vec<(Token, Token)> {
+        RING.with(|local| {
+            let mut ranges = Vec::new();
+            local.borrow_mut().sending().root.ranges(&mut ranges);
+            ranges
+        })
+    }
     fn sending(&mut self) -> &mut Self {
         unsafe {
             if VERSION != self.version {
@@ -327,6 +335,8 @@ impl Endpoints for Option<Replicas> {
 
 pub trait Vnode: VnodeClone + Sync + Send {
     fn search(&mut self, token: Token) -> &mut Box<dyn Endpoints>;
+    // collect the token ranges of the vnode and its children in order.
+    fn ranges(&self, ranges: &mut Vec<(Token, Token)>);
 }
 pub trait VnodeClone {
     fn clone_box(&self) -> Box<dyn Vnode>;
@@ -359,6 +369,11 @@ impl Vnode for Mild {
             self.right_child.search(token)
         }
     }
+    fn ranges(&self, ranges: &mut Vec<(Token, Token)>) {
+        self.left_child.ranges(ranges);
+        ranges.push((self.left, self.right));
+        self.right_child.ranges(ranges);
+    }
 }
 
 impl Vnode for LeftMild {
@@ -370,25 +385,37 @@ impl Vnode for LeftMild {
             self.left_child.search(token)
         }
     }
+    fn ranges(&self, ranges: &mut Vec<(Token, Token)>) {
+        self.left_child.ranges(ranges);
+        ranges.push((self.left, self.right));
+    }
 }
 
 impl Vnode for DeadEnd {
     fn search(&mut self, _token: Token) -> &mut Box<dyn Endpoints> {
         &mut self.replicas
     }
+    fn ranges(&self, ranges: &mut Vec<(Token, Token)>) {
+        if let Some(range) = self.range {
+            ranges.push(range);
+        }
+    }
 }
 
 // this struct represent a vnode without left or right child,
 // we don't need to set conditions because it's a deadend during search(),
 // and condition must be true.
+// the range is only kept to list the token ranges, it's None for the initial vnode.
 #[derive(Clone)]
 struct DeadEnd {
+    range: Option<(Token, Token)>,
     replicas: Box<dyn Endpoints>,
 }
 
 impl DeadEnd {
     fn initial_vnode() -> Vcell {
         Box::new(DeadEnd {
+            range: None,
             replicas: Box::new(None),
         })
     }
@@ -421,6 +448,7 @@ fn compute_vnode(chain: &[(Token, Token, Replicas)]) -> Vcell {
     if right.is_empty() && left.is_empty() {
         // then the parent_vnode without any child so consider it deadend
         Box::new(DeadEnd {
+            range: Some((vnode.0, vnode.1)),
             replicas: Box::new(vnode.2.to_owned()),
         })
     } else if !right.is_empty() && !left.is_empty() {
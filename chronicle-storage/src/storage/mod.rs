@@ -6,7 +6,10 @@ use crate::{
     ring::DC,
     storage::helper::HelperBuilder,
 };
-use chronicle_common::app;
+use chronicle_common::{
+    app,
+    traits::importer::ImporterTx,
+};
 
 type ThreadCount = usize;
 type ReporterCount = u8;
@@ -19,7 +22,8 @@ app!(StorageBuilder {
     buffer_size: usize,
     recv_buffer_size: usize,
     send_buffer_size: usize,
-    nodes: Vec<String>
+    nodes: Vec<String>,
+    importer_tx: Box<dyn ImporterTx>
 });
 
 impl StorageBuilder {
@@ -33,6 +37,7 @@ impl StorageBuilder {
             recv_buffer_size: self.recv_buffer_size,
             send_buffer_size: self.send_buffer_size,
             nodes: self.nodes,
+            importer_tx: self.importer_tx,
             launcher_tx: self.launcher_tx,
         }
     }
@@ -47,6 +52,8 @@ pub struct Storage {
     recv_buffer_size: Option<usize>,
     send_buffer_size: Option<usize>,
     nodes: Option<Vec<String>>,
+    /// The dmp import jobs controlled through the dashboard (if any).
+    importer_tx: Option<Box<dyn ImporterTx>>,
     launcher_tx: Option<Box<dyn LauncherTx>>,
 }
 
@@ -68,10 +75,13 @@ impl Storage {
     async fn init(&mut self) -> Option<dashboard::Sender> {
         let mut launcher_tx = self.launcher_tx.take().unwrap();
         // build dashboard
-        let dashboard = dashboard::DashboardBuilder::new()
+        let mut dashboard_builder = dashboard::DashboardBuilder::new()
             .launcher_tx(launcher_tx.clone())
-            .listen_address(self.listen_address.clone())
-            .build();
+            .listen_address(self.listen_address.clone());
+        if let Some(importer_tx) = self.importer_tx.take() {
+            dashboard_builder = dashboard_builder.importer_tx(importer_tx);
+        }
+        let dashboard = dashboard_builder.build();
         // register dashboard with launcher
         launcher_tx.register_dashboard("StorageDashboard".to_string(), Box::new(dashboard.clone_tx()));
         // build cluster
@@ -0,0 +1,657 @@
+// The typed view of a 2673-tryte transaction, and the cql insert queries of the tangle tables derived from it.
+// Every ingestion path (broker, importer, solidifier and backfill) stores the transactions through it.
+use bee_ternary::{
+    t1b1::T1B1Buf,
+    TritBuf,
+    TryteBuf,
+};
+use chronicle_cql::{
+    compression::MyCompression,
+    frame::{
+        batch::{
+            Batch,
+            BatchTypes,
+        },
+        batchflags::NOFLAGS,
+        consistency::Consistency,
+        encoder::{
+            ColumnEncoder,
+            UNSET_VALUE,
+        },
+        header::Header,
+        query::Query,
+        queryflags::{
+            SKIP_METADATA,
+            VALUES,
+        },
+    },
+    murmur3::murmur3_cassandra_x64_128,
+};
+use chrono::{
+    DateTime,
+    Datelike,
+};
+use std::{
+    convert::TryFrom,
+    error::Error as StdError,
+    fmt,
+    ops::Range,
+};
+
+pub const HASH_TRYTES_LENGTH: usize = 81;
+pub const TRANSACTION_TRYTES_LENGTH: usize = 2673;
+// the offsets of the transaction fields within the transaction trytes.
+pub const PAYLOAD: Range<usize> = 0..2187;
+pub const ADDRESS: Range<usize> = 2187..2268;
+pub const VALUE: Range<usize> = 2268..2295;
+pub const OBSOLETE_TAG: Range<usize> = 2295..2322;
+pub const TIMESTAMP: Range<usize> = 2322..2331;
+pub const CURRENT_INDEX: Range<usize> = 2331..2340;
+pub const LAST_INDEX: Range<usize> = 2340..2349;
+pub const BUNDLE: Range<usize> = 2349..2430;
+pub const TRUNK: Range<usize> = 2430..2511;
+pub const BRANCH: Range<usize> = 2511..2592;
+pub const TAG: Range<usize> = 2592..2619;
+pub const ATTACHMENT_TIMESTAMP: Range<usize> = 2619..2628;
+pub const ATTACHMENT_TIMESTAMP_LOWER: Range<usize> = 2628..2637;
+pub const ATTACHMENT_TIMESTAMP_UPPER: Range<usize> = 2637..2646;
+pub const NONCE: Range<usize> = 2646..2673;
+// the milestone index is encoded in the first 5 trytes of the obsolete tag of the milestone transactions.
+const MILESTONE_INDEX: Range<usize> = 2295..2300;
+
+const BE_3_BYTES_LENGTH: [u8; 4] = [0, 0, 0, 3];
+// the lower sanity bound of a transaction timestamp (2016-01-01), the tangle did not exist before it.
+const MIN_TIMESTAMP: i64 = 1_451_606_400;
+// the upper sanity bound of a transaction timestamp (2100-01-01), fixed so the partition never depends on the
+// wall-clock of the ingestion.
+const MAX_TIMESTAMP: i64 = 4_102_444_800;
+// the attachment timestamps of the transactions confirmed up to this milestone are not reliable.
+const LAST_MILESTONE_WITHOUT_ATTACHMENT_TIMESTAMP: u64 = 337_541;
+// any attachment timestamp above it is considered to be in milliseconds (as attachToTangle sets it).
+const MAX_SECONDS_TIMESTAMP: i64 = 100_000_000_000;
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    HashLength(usize),
+    TrytesLength(usize),
+    InvalidTryte(char),
+    /// A numeric field whose trytes are out of the i64 range.
+    OutOfRange(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::HashLength(length) => write!(f, "invalid hash length: {}", length),
+            Error::TrytesLength(length) => write!(f, "invalid transaction trytes length: {}", length),
+            Error::InvalidTryte(tryte) => write!(f, "invalid tryte: {:?}", tryte),
+            Error::OutOfRange(trytes) => write!(f, "trytes out of the i64 range: {}", trytes),
+        }
+    }
+}
+
+impl StdError for Error {}
+
+/// A parsed transaction, the tryte fields borrow the hash and trytes it got parsed from.
+#[derive(Debug, Clone, Copy)]
+pub struct Transaction<'a> {
+    hash: &'a str,
+    trytes: &'a str,
+    value: i64,
+    timestamp: i64,
+    current_index: i64,
+    last_index: i64,
+    attachment_timestamp: i64,
+    attachment_timestamp_lower: i64,
+    attachment_timestamp_upper: i64,
+}
+
+impl<'a> Transaction<'a> {
+    /// Parse the transaction, the hash and trytes must have the right length and consist of valid trytes only.
+    /// NOTE: the hash is not verified against the trytes (see the curl module in chronicle-broker).
+    pub fn parse(hash: &'a str, trytes: &'a str) -> Result<Self, Error> {
+        if hash.len() != HASH_TRYTES_LENGTH {
+            return Err(Error::HashLength(hash.len()));
+        }
+        if trytes.len() != TRANSACTION_TRYTES_LENGTH {
+            return Err(Error::TrytesLength(trytes.len()));
+        }
+        if let Some(tryte) = hash
+            .chars()
+            .chain(trytes.chars())
+            .find(|t| *t != '9' && !t.is_ascii_uppercase())
+        {
+            return Err(Error::InvalidTryte(tryte));
+        }
+        Ok(Transaction {
+            hash,
+            trytes,
+            value: trytes_to_i64(&trytes[VALUE])?,
+            timestamp: trytes_to_i64(&trytes[TIMESTAMP])?,
+            current_index: trytes_to_i64(&trytes[CURRENT_INDEX])?,
+            last_index: trytes_to_i64(&trytes[LAST_INDEX])?,
+            attachment_timestamp: trytes_to_i64(&trytes[ATTACHMENT_TIMESTAMP])?,
+            attachment_timestamp_lower: trytes_to_i64(&trytes[ATTACHMENT_TIMESTAMP_LOWER])?,
+            attachment_timestamp_upper: trytes_to_i64(&trytes[ATTACHMENT_TIMESTAMP_UPPER])?,
+        })
+    }
+    pub fn hash(&self) -> &'a str {
+        self.hash
+    }
+    pub fn trytes(&self) -> &'a str {
+        self.trytes
+    }
+    pub fn payload(&self) -> &'a str {
+        &self.trytes[PAYLOAD]
+    }
+    pub fn address(&self) -> &'a str {
+        &self.trytes[ADDRESS]
+    }
+    pub fn value(&self) -> i64 {
+        self.value
+    }
+    pub fn obsolete_tag(&self) -> &'a str {
+        &self.trytes[OBSOLETE_TAG]
+    }
+    pub fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+    pub fn current_index(&self) -> i64 {
+        self.current_index
+    }
+    pub fn last_index(&self) -> i64 {
+        self.last_index
+    }
+    pub fn bundle(&self) -> &'a str {
+        &self.trytes[BUNDLE]
+    }
+    pub fn trunk(&self) -> &'a str {
+        &self.trytes[TRUNK]
+    }
+    pub fn branch(&self) -> &'a str {
+        &self.trytes[BRANCH]
+    }
+    pub fn tag(&self) -> &'a str {
+        &self.trytes[TAG]
+    }
+    pub fn attachment_timestamp(&self) -> i64 {
+        self.attachment_timestamp
+    }
+    pub fn attachment_timestamp_lower(&self) -> i64 {
+        self.attachment_timestamp_lower
+    }
+    pub fn attachment_timestamp_upper(&self) -> i64 {
+        self.attachment_timestamp_upper
+    }
+    pub fn nonce(&self) -> &'a str {
+        &self.trytes[NONCE]
+    }
+    pub fn is_tail(&self) -> bool {
+        self.current_index == 0
+    }
+    /// The milestone index, meaningful for the milestone (coordinator) transactions only.
+    pub fn milestone_index(&self) -> u64 {
+        // the 5 trytes of the index always fit in an i64
+        trytes_to_i64(&self.trytes[MILESTONE_INDEX]).unwrap_or(0) as u64
+    }
+    /// The (year, month) partition of the data table rows, given the milestone confirming the transaction (if any).
+    pub fn year_month(&self, milestone: Option<u64>) -> YearMonth {
+        YearMonth::from_timestamps(self.timestamp, self.attachment_timestamp, milestone)
+    }
+    /// Create all the insert queries of the transaction paired with the token of their partition key,
+    /// or a single batch routed to the owner of the transaction row if batch_type is set.
+    /// The milestone is the one confirming the transaction, None leaves the milestone column unset.
+    pub fn insert_queries(&self, milestone: Option<u64>, batch_type: Option<BatchTypes>) -> Vec<(i64, Vec<u8>)> {
+        if let Some(batch_type) = batch_type {
+            return vec![(token(self.hash.as_bytes()), self.insert_batch(batch_type, milestone))];
+        }
+        let Query(payload) = Query::new()
+            .version()
+            .flags(MyCompression::flag())
+            .stream(0)
+            .opcode()
+            .length()
+            .statement(INSERT_TANGLE_TX_QUERY)
+            .consistency(Consistency::One)
+            .query_flags(SKIP_METADATA | VALUES)
+            .transaction(self, milestone)
+            .build(MyCompression::get());
+        let mut queries = vec![(token(self.hash.as_bytes()), payload)];
+        for (vertex, kind, extra) in self.edges(milestone) {
+            let edge = match extra {
+                Extra::Hint(year_month) => insert_to_edge_table(vertex, kind, 0, "0", self.value, year_month),
+                Extra::Bundle(bundle) => {
+                    insert_to_edge_table(vertex, kind, self.timestamp, self.hash, self.value, bundle)
+                }
+                Extra::Unset => insert_to_edge_table(vertex, kind, self.timestamp, self.hash, self.value, UNSET_VALUE),
+            };
+            queries.push((token(vertex.as_bytes()), edge));
+        }
+        let year_month = self.year_month(milestone);
+        let (year, month) = (year_month.year(), year_month.month());
+        for (vertex, kind) in self.data() {
+            let data = insert_to_data_table(vertex, year, month, kind, self.timestamp, self.hash);
+            queries.push((data_token(vertex, year, month), data));
+        }
+        queries
+    }
+    /// Create a batch of all the insert queries of the transaction, the transaction is stored (or fails) as a unit.
+    pub fn insert_batch(&self, batch_type: BatchTypes, milestone: Option<u64>) -> Vec<u8> {
+        let mut batch = Batch::new()
+            .version()
+            .flags(MyCompression::flag())
+            .stream(0)
+            .opcode()
+            .length()
+            .batch_type(batch_type)
+            .statement(INSERT_TANGLE_TX_QUERY)
+            .transaction(self, milestone);
+        for (vertex, kind, extra) in self.edges(milestone) {
+            batch = batch
+                .statement(INSERT_TANGLE_EDGE_STATMENT)
+                .value_count(6)
+                .value(vertex)
+                .value(kind);
+            batch = match extra {
+                Extra::Hint(year_month) => batch.value(0_i64).value("0").value(self.value).value(year_month),
+                Extra::Bundle(bundle) => batch
+                    .value(self.timestamp)
+                    .value(self.hash)
+                    .value(self.value)
+                    .value(bundle),
+                Extra::Unset => batch
+                    .value(self.timestamp)
+                    .value(self.hash)
+                    .value(self.value)
+                    .value(UNSET_VALUE),
+            };
+        }
+        let year_month = self.year_month(milestone);
+        for (vertex, kind) in self.data() {
+            batch = batch
+                .statement(INSERT_TANGLE_DATA_STATMENT)
+                .value_count(6)
+                .value(vertex)
+                .value(year_month.year())
+                .value(year_month.month())
+                .value(kind)
+                .value(self.timestamp)
+                .value(self.hash);
+        }
+        let Batch(payload, _) = batch
+            .consistency(Consistency::One)
+            .batch_flags(NOFLAGS)
+            .build(MyCompression::get());
+        payload
+    }
+    // the edge table rows (vertex, kind, extra), a zero value transaction is a hint of its address.
+    // the conflicts are indexed by the tail edge of the bundle (its reattachments) and the bundle of the address
+    // inputs (its spends).
+    fn edges(&self, milestone: Option<u64>) -> Vec<(&'a str, &'static str, Extra<'a>)> {
+        let address = match self.value {
+            0 => (self.address(), "hint", Extra::Hint(self.year_month(milestone))),
+            v if v > 0 => (self.address(), "output", Extra::Unset),
+            _ => (self.address(), "input", Extra::Bundle(self.bundle())),
+        };
+        let mut edges = vec![
+            address,
+            (self.trunk(), "trunk", Extra::Unset),
+            (self.branch(), "branch", Extra::Unset),
+            (self.bundle(), "bundle", Extra::Unset),
+        ];
+        if self.is_tail() {
+            edges.push((self.bundle(), "tail", Extra::Unset));
+        }
+        edges
+    }
+    // the data table rows (vertex, kind).
+    fn data(&self) -> Vec<(&'a str, &'static str)> {
+        if self.value == 0 {
+            vec![(self.address(), "address"), (self.tag(), "tag")]
+        } else {
+            vec![(self.tag(), "tag")]
+        }
+    }
+}
+
+// the values of the transaction table row, shared by the query and batch frames.
+trait TransactionValues: Sized {
+    fn value_count(self, count: u16) -> Self;
+    fn value(self, value: impl ColumnEncoder) -> Self;
+    fn transaction(self, transaction: &Transaction, milestone: Option<u64>) -> Self {
+        let trytes = transaction.trytes;
+        let values = self
+            .value_count(17) // the total value count
+            .value(transaction.hash)
+            .value(&trytes[PAYLOAD])
+            .value(&trytes[ADDRESS])
+            .value(&trytes[VALUE])
+            .value(&trytes[OBSOLETE_TAG])
+            .value(&trytes[TIMESTAMP])
+            .value(&trytes[CURRENT_INDEX])
+            .value(&trytes[LAST_INDEX])
+            .value(&trytes[BUNDLE])
+            .value(&trytes[TRUNK])
+            .value(&trytes[BRANCH])
+            .value(&trytes[TAG])
+            .value(&trytes[ATTACHMENT_TIMESTAMP])
+            .value(&trytes[ATTACHMENT_TIMESTAMP_LOWER])
+            .value(&trytes[ATTACHMENT_TIMESTAMP_UPPER])
+            .value(&trytes[NONCE]);
+        match milestone {
+            Some(milestone) => values.value(milestone),
+            None => values.value(UNSET_VALUE),
+        }
+    }
+}
+
+impl TransactionValues for Query {
+    fn value_count(self, count: u16) -> Self {
+        Query::value_count(self, count)
+    }
+    fn value(self, value: impl ColumnEncoder) -> Self {
+        Query::value(self, value)
+    }
+}
+
+impl TransactionValues for Batch {
+    fn value_count(self, count: u16) -> Self {
+        Batch::value_count(self, count)
+    }
+    fn value(self, value: impl ColumnEncoder) -> Self {
+        Batch::value(self, value)
+    }
+}
+
+// the extra column of an edge row.
+enum Extra<'a> {
+    // the partition of the hinted data table rows.
+    Hint(YearMonth),
+    // the bundle of an input transaction.
+    Bundle(&'a str),
+    Unset,
+}
+
+pub struct YearMonth(u16, u8);
+impl YearMonth {
+    pub fn new(year: u16, month: u8) -> Self {
+        YearMonth(year, month)
+    }
+    /// The partition-date policy of the data table, so the same transaction always lands in the same (year, month)
+    /// partition whatever the ingestion path.
+    /// It prefers the attachment timestamp (zero means absent, and it is ignored for the transactions confirmed by a
+    /// milestone <= 337541), then falls back to the timestamp, and finally clamps the timestamp into the sanity bounds
+    /// [2016-01-01, 2100-01-01].
+    pub fn from_timestamps(timestamp: i64, attachment_timestamp: i64, milestone: Option<u64>) -> Self {
+        let attachment_timestamp = if let Some(1..=LAST_MILESTONE_WITHOUT_ATTACHMENT_TIMESTAMP) = milestone {
+            0
+        } else if attachment_timestamp > MAX_SECONDS_TIMESTAMP {
+            attachment_timestamp / 1000
+        } else {
+            attachment_timestamp
+        };
+        let seconds = if (MIN_TIMESTAMP..=MAX_TIMESTAMP).contains(&attachment_timestamp) {
+            attachment_timestamp
+        } else {
+            timestamp.clamp(MIN_TIMESTAMP, MAX_TIMESTAMP)
+        };
+        // the seconds are within the sanity bounds, so always representable.
+        let date = DateTime::from_timestamp(seconds, 0).unwrap();
+        YearMonth(date.year() as u16, date.month() as u8)
+    }
+    pub fn year(&self) -> u16 {
+        self.0
+    }
+    pub fn month(&self) -> u8 {
+        self.1
+    }
+}
+impl ColumnEncoder for YearMonth {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        buffer.extend(&BE_3_BYTES_LENGTH);
+        buffer.extend(&u16::to_be_bytes(self.0));
+        buffer.push(self.1);
+    }
+}
+
+/// Create insert(index) cql query in edge table
+pub fn insert_to_edge_table(
+    vertex: &str,
+    kind: &str,
+    timestamp: i64,
+    tx: &str,
+    value: i64,
+    extra: impl ColumnEncoder,
+) -> Vec<u8> {
+    let Query(payload) = Query::new()
+        .version()
+        .flags(MyCompression::flag())
+        .stream(0)
+        .opcode()
+        .length()
+        .statement(INSERT_TANGLE_EDGE_STATMENT)
+        .consistency(Consistency::One)
+        .query_flags(SKIP_METADATA | VALUES)
+        .value_count(6) // the total value count
+        .value(vertex) // vertex
+        .value(kind) // kind
+        .value(timestamp) // timestamp
+        .value(tx) // tx-hash
+        .value(value) // value
+        .value(extra) // extra
+        .build(MyCompression::get());
+    payload
+}
+/// Create insert(index) cql query in data table
+pub fn insert_to_data_table(vertex: &str, year: u16, month: u8, kind: &str, timestamp: i64, tx: &str) -> Vec<u8> {
+    let Query(payload) = Query::new()
+        .version()
+        .flags(MyCompression::flag())
+        .stream(0)
+        .opcode()
+        .length()
+        .statement(INSERT_TANGLE_DATA_STATMENT)
+        .consistency(Consistency::One)
+        .query_flags(SKIP_METADATA | VALUES)
+        .value_count(6) // the total value count
+        .value(vertex) // vertex
+        .value(year)
+        .value(month)
+        .value(kind) // kind
+        .value(timestamp) // timestamp
+        .value(tx) // tx-hash
+        .build(MyCompression::get());
+    payload
+}
+
+/// Create update cql query to set the milestone (confirmation) column of a transaction
+pub fn update_milestone(hash: &str, milestone: u64) -> Vec<u8> {
+    let Query(payload) = Query::new()
+        .version()
+        .flags(MyCompression::flag())
+        .stream(0)
+        .opcode()
+        .length()
+        .statement(UPDATE_TANGLE_TX_MILESTONE_STATMENT)
+        .consistency(Consistency::One)
+        .query_flags(SKIP_METADATA | VALUES)
+        .value_count(2)
+        .value(milestone)
+        .value(hash)
+        .build(MyCompression::get());
+    payload
+}
+
+/// Create insert cql query to record the completeness of a bundle, upserts the previous record (if any)
+pub fn insert_bundle(bundle: &str, last_index: i64, complete: bool, timestamp: i64) -> Vec<u8> {
+    let Query(payload) = Query::new()
+        .version()
+        .flags(MyCompression::flag())
+        .stream(0)
+        .opcode()
+        .length()
+        .statement(INSERT_TANGLE_BUNDLE_STATMENT)
+        .consistency(Consistency::One)
+        .query_flags(SKIP_METADATA | VALUES)
+        .value_count(4)
+        .value(bundle)
+        .value(last_index)
+        .value(complete)
+        .value(timestamp)
+        .build(MyCompression::get());
+    payload
+}
+
+/// Compute the murmur3 token of a (single column) partition key
+pub fn token(partition_key: &[u8]) -> i64 {
+    murmur3_cassandra_x64_128(&mut &partition_key[..], 0).unwrap()
+}
+
+/// Compute the murmur3 token of the data table composite partition key (vertex, year, month)
+pub fn data_token(vertex: &str, year: u16, month: u8) -> i64 {
+    let mut partition_key = Vec::with_capacity(vertex.len() + 14);
+    // each component is serialized as [length][bytes][end-of-component]
+    partition_key.extend(&u16::to_be_bytes(vertex.len() as u16));
+    partition_key.extend(vertex.as_bytes());
+    partition_key.push(0);
+    partition_key.extend(&u16::to_be_bytes(2));
+    partition_key.extend(&u16::to_be_bytes(year));
+    partition_key.push(0);
+    partition_key.extend(&u16::to_be_bytes(1));
+    partition_key.push(month);
+    partition_key.push(0);
+    token(&partition_key)
+}
+
+/// Convert trytes to i64, a field of valid trytes can still be out of the i64 range (ie 27 trytes).
+pub fn trytes_to_i64(slice: &str) -> Result<i64, Error> {
+    let trytes = TryteBuf::try_from_str(slice).map_err(|_| {
+        let tryte = slice.chars().find(|t| *t != '9' && !t.is_ascii_uppercase());
+        Error::InvalidTryte(tryte.unwrap_or_default())
+    })?;
+    let trit_buf: TritBuf<T1B1Buf> = trytes.as_trits().encode();
+    i64::try_from(trit_buf).map_err(|_| Error::OutOfRange(slice.to_string()))
+}
+
+pub const INSERT_TANGLE_TX_QUERY: &str = r#"
+  INSERT INTO tangle.transaction (
+    hash,
+    payload,
+    address,
+    value,
+    obsolete_tag,
+    timestamp,
+    current_index,
+    last_index,
+    bundle,
+    trunk,
+    branch,
+    tag,
+    attachment_timestamp,
+    attachment_timestamp_lower,
+    attachment_timestamp_upper,
+    nonce,
+    milestone
+) VALUES (?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?);
+"#;
+
+pub const UPDATE_TANGLE_TX_MILESTONE_STATMENT: &str = r#"
+  UPDATE tangle.transaction SET milestone = ? WHERE hash = ?;
+"#;
+
+pub const INSERT_TANGLE_EDGE_STATMENT: &str = r#"
+  INSERT INTO tangle.edge (
+    vertex,
+    kind,
+    timestamp,
+    tx,
+    value,
+    extra
+) VALUES (?,?,?,?,?,?);
+"#;
+
+pub const INSERT_TANGLE_DATA_STATMENT: &str = r#"
+  INSERT INTO tangle.data (
+    vertex,
+    year,
+    month,
+    kind,
+    timestamp,
+    tx
+) VALUES (?,?,?,?,?,?);
+"#;
+
+pub const INSERT_TANGLE_BUNDLE_STATMENT: &str = r#"
+  INSERT INTO tangle.bundle (
+    bundle,
+    last_index,
+    complete,
+    timestamp
+) VALUES (?,?,?,?);
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partition_date_policy() {
+        // 2019-03-15 as timestamp, 2020-01-10 as attachment timestamp in milliseconds
+        let year_month = YearMonth::from_timestamps(1_552_608_000, 1_578_614_400_000, None);
+        assert_eq!((year_month.year(), year_month.month()), (2020, 1));
+        let year_month = YearMonth::from_timestamps(1_552_608_000, 1_578_614_400_000, Some(1_300_000));
+        assert_eq!((year_month.year(), year_month.month()), (2020, 1));
+        // the attachment timestamp of a transaction confirmed by an old milestone is ignored
+        let year_month = YearMonth::from_timestamps(1_552_608_000, 1_578_614_400_000, Some(337_541));
+        assert_eq!((year_month.year(), year_month.month()), (2019, 3));
+        // absent attachment timestamp falls back to the timestamp
+        let year_month = YearMonth::from_timestamps(1_552_608_000, 0, None);
+        assert_eq!((year_month.year(), year_month.month()), (2019, 3));
+        // insane timestamps are clamped into the sanity bounds
+        let year_month = YearMonth::from_timestamps(0, -1, None);
+        assert_eq!((year_month.year(), year_month.month()), (2016, 1));
+        let year_month = YearMonth::from_timestamps(i64::MAX, 0, None);
+        assert_eq!((year_month.year(), year_month.month()), (2100, 1));
+    }
+
+    #[test]
+    fn parse_transaction() {
+        let hash = "9".repeat(HASH_TRYTES_LENGTH);
+        let mut trytes = "9".repeat(TRANSACTION_TRYTES_LENGTH);
+        trytes.replace_range(ADDRESS, &"A".repeat(81));
+        trytes.replace_range(VALUE.start..VALUE.start + 1, "A");
+        let transaction = Transaction::parse(&hash, &trytes).unwrap();
+        assert_eq!(transaction.address(), "A".repeat(81));
+        assert_eq!(transaction.value(), 1);
+        assert!(transaction.is_tail());
+        assert_eq!(
+            Transaction::parse(&hash, &trytes[1..]).unwrap_err(),
+            Error::TrytesLength(2672)
+        );
+        // a value field of valid trytes out of the i64 range is rejected rather than panicking
+        let mut overflow = trytes.clone();
+        overflow.replace_range(VALUE, &"M".repeat(27));
+        assert_eq!(
+            Transaction::parse(&hash, &overflow).unwrap_err(),
+            Error::OutOfRange("M".repeat(27))
+        );
+        trytes.replace_range(NONCE.start..NONCE.start + 1, "a");
+        assert_eq!(
+            Transaction::parse(&hash, &trytes).unwrap_err(),
+            Error::InvalidTryte('a')
+        );
+        assert_eq!(trytes_to_i64("9a"), Err(Error::InvalidTryte('a')));
+    }
+
+    #[test]
+    fn transaction_queries() {
+        let hash = "9".repeat(HASH_TRYTES_LENGTH);
+        let trytes = "9".repeat(TRANSACTION_TRYTES_LENGTH);
+        let transaction = Transaction::parse(&hash, &trytes).unwrap();
+        // a zero value tail transaction has 8 inserts (tx, hint, address, trunk, branch, bundle, tail and tag)
+        assert_eq!(transaction.insert_queries(None, None).len(), 8);
+        let payload = transaction.insert_batch(BatchTypes::Unlogged, Some(1));
+        assert_eq!(payload[9], BatchTypes::Unlogged as u8);
+        assert_eq!(u16::from_be_bytes([payload[10], payload[11]]), 8);
+    }
+}
@@ -25,6 +25,16 @@ pub enum Error {
     NoRing,
 }
 
+impl Error {
+    /// Returns true if the query can be retried, the connection errors are always retryable.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Cql(error) => error.code.is_retryable(),
+            _ => true,
+        }
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "Worker Error!")